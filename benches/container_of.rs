@@ -0,0 +1,74 @@
+// Times getting from an OVERLAPPED back to its container, for a container
+// with the OVERLAPPED at offset 0, with and without the `OFFSET_IS_ZERO`
+// fast path. Run with `cargo bench --bench container_of`.
+
+use std::hint::black_box;
+use std::mem::offset_of;
+use std::time::{Duration, Instant};
+
+use miox::container_of::ContainerOf;
+use miox::winapi::OVERLAPPED;
+use miox::EventState;
+
+const ITERATIONS: u32 = 100_000_000;
+const ROUNDS: usize = 5;
+
+// Same layout twice; only whether the trait knows the offset is zero differs.
+#[repr(C)]
+#[derive(Default)]
+struct Fast {
+  overlapped: OVERLAPPED,
+  data: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Slow {
+  overlapped: OVERLAPPED,
+  data: u64,
+}
+
+impl ContainerOf<OVERLAPPED> for Fast {
+  const OFFSET_IS_ZERO: bool = offset_of!(Fast, overlapped) == 0;
+
+  fn member(&self) -> &OVERLAPPED {
+    &self.overlapped
+  }
+}
+
+impl ContainerOf<OVERLAPPED> for Slow {
+  fn member(&self) -> &OVERLAPPED {
+    &self.overlapped
+  }
+}
+
+// The best of a few rounds, which is the least disturbed by everything else
+// going on.
+fn time<C>(container: &C) -> Duration
+where
+  C: ContainerOf<OVERLAPPED>,
+{
+  let overlapped = container.member();
+  let round = || {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+      let member = black_box(overlapped);
+      black_box(unsafe { C::container_of(member) });
+    }
+    start.elapsed()
+  };
+  (0..ROUNDS).map(|_| round()).min().unwrap()
+}
+
+fn report(name: &str, elapsed: Duration) {
+  let per_call = elapsed.as_secs_f64() * 1e9 / f64::from(ITERATIONS);
+  println!("{:<24} {:>8.3} ns/call", name, per_call);
+}
+
+fn main() {
+  let (fast, slow) = (Fast::default(), Slow::default());
+  black_box((fast.data, slow.data));
+  report("offset 0, fast path", time(&fast));
+  report("offset 0, no fast path", time(&slow));
+  report("EventState", time(&EventState::new()));
+}
@@ -0,0 +1,157 @@
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::ptr::NonNull;
+
+use miox::completion_port::CompletionPort;
+use miox::event_handler;
+use miox::iocp::*;
+use miox::winapi::{OVERLAPPED, STATUS_SUCCESS};
+
+// Sample usage -- AfdPoll is an 'iocp plugin'.
+struct AfdPoll {
+  state: EventState,
+  bits: u32,
+}
+
+event_handler! {
+  AfdPoll { state } => {
+    fn complete(self: Box<Self>) {
+      println!("AfdPoll event, bits: {}", self.bits);
+    }
+  }
+}
+
+impl Debug for AfdPoll {
+  fn fmt(&self, f: &mut Formatter) -> FmtResult {
+    write!(f, "AfdPoll {{ bits: {:?} }}", self.bits)
+  }
+}
+
+// Sample usage -- PipeRead is another 'iocp plugin'.
+struct PipeRead {
+  text: &'static str,
+  state: EventState,
+}
+
+event_handler! {
+  PipeRead { state } => {
+    fn complete(self: Box<Self>) {
+      println!(
+        "PipeRead event #{}, text: {}, offset: {}",
+        self.state.sequence(),
+        self.text,
+        self.state.get_file_offset()
+      );
+    }
+  }
+}
+
+// Sample usage -- PipeWrite is the write side to PipeRead. It owns the data
+// being written, since WriteFile() needs the buffer to stay put until the
+// operation completes.
+struct PipeWrite {
+  state: EventState,
+  data: Vec<u8>,
+}
+
+impl EventHandler for PipeWrite {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(mut self: Box<Self>) {
+    let result = self.state().result();
+    println!(
+      "PipeWrite event, wrote {} of {} bytes, status: {}",
+      result.bytes_transferred,
+      self.data.len(),
+      result.status
+    );
+  }
+}
+
+// Stand-in for WriteFile(); pretends the pipe accepts all of the data, so the
+// kernel posts the completion to the port the pipe is associated with.
+fn fake_write_file(
+  port: &CompletionPort,
+  _buffer: *const u8,
+  length: usize,
+  overlapped: *mut OVERLAPPED,
+) -> bool {
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: length as u32,
+  };
+  let overlapped = NonNull::new(overlapped).unwrap();
+  unsafe { port.complete_io(0, overlapped, result) };
+  true
+}
+
+fn main() {
+  let pipe_read_1 = Box::new(PipeRead {
+    text: "foo",
+    state: EventState::new(),
+  });
+  let mut pipe_read_2 = Box::new(PipeRead {
+    text: "bar",
+    state: EventState::new(),
+  });
+  pipe_read_2.state.set_file_offset(1 << 32);
+  let afd_poll_1 = Box::new(AfdPoll {
+    bits: 22,
+    state: EventState::new(),
+  });
+  let afd_poll_2 = Box::new(AfdPoll {
+    bits: 1234,
+    state: EventState::new(),
+  });
+
+  // How it would be used.
+  /*
+  let dispatch = pipe_read_1.dispatch();
+  let result = ReadFile(pipe_handle, yadda, yadda, dispatch.overlapped());
+  if result == TRUE || GetLastError() == ERROR_IO_PENDING {
+      dispatch.pending();
+  } else {
+      dispatch.failed();
+  }
+  */
+
+  // But in this test it's all phony.
+  let mut fake_iocp_results: Vec<*mut OVERLAPPED> = Vec::new();
+  let mut d = pipe_read_1.dispatch();
+  fake_iocp_results.push(d.overlapped());
+  d.pending();
+  let mut d = pipe_read_2.dispatch();
+  fake_iocp_results.push(d.overlapped());
+  d.pending();
+  let mut d = afd_poll_1.dispatch();
+  fake_iocp_results.push(d.overlapped());
+  d.pending();
+
+  // Pretend this one fails.
+  let d = afd_poll_2.dispatch();
+  let event: Box<AfdPoll> = d.failed();
+  println!("Dispatch failed for {:?}", event);
+
+  for overlapped in fake_iocp_results {
+    unsafe { EventState::complete(NonNull::new(overlapped).unwrap()) }
+  }
+
+  // Writes go through the same dispatch -> pending -> complete cycle, with
+  // the completion delivered through a completion port this time. (See
+  // tcp_accept.rs for sockets.)
+  let mut port = CompletionPort::new();
+  let write = Box::new(PipeWrite {
+    state: EventState::new(),
+    data: b"hello, pipe".to_vec(),
+  });
+  let (buffer, length) = (write.data.as_ptr(), write.data.len());
+  let mut d = port.dispatch(write);
+  if fake_write_file(&port, buffer, length, d.overlapped()) {
+    d.pending();
+  } else {
+    d.failed();
+  }
+  port.run_until_idle(|_| {});
+  port.assert_no_leaks();
+}
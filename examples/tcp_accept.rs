@@ -0,0 +1,79 @@
+use std::ptr::NonNull;
+
+use miox::completion_port::CompletionPort;
+use miox::iocp::*;
+use miox::winapi::{OVERLAPPED, SOCKET, STATUS_SUCCESS};
+
+// Sample usage -- SocketAcceptHandler wraps AcceptEx() on a listening socket.
+// AcceptEx() needs room for the local and remote address, each of which must
+// be at least 16 bytes larger than the biggest sockaddr (sockaddr_in6).
+const ACCEPT_ADDRESS_LENGTH: usize = 28 + 16;
+
+struct SocketAcceptHandler {
+  state: EventState,
+  listen_socket: SOCKET,
+  accept_socket: SOCKET,
+  addresses: [u8; 2 * ACCEPT_ADDRESS_LENGTH],
+}
+
+impl EventHandler for SocketAcceptHandler {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(mut self: Box<Self>) {
+    let result = self.state().result();
+    println!(
+      "SocketAcceptHandler event, listener: {}, socket: {}, status: {}",
+      self.listen_socket, self.accept_socket, result.status
+    );
+  }
+}
+
+// Stand-in for AcceptEx(); pretends a client connects right away, so the
+// kernel posts the completion to the port the listening socket is associated
+// with.
+fn fake_accept_ex(
+  port: &CompletionPort,
+  _listen_socket: SOCKET,
+  _accept_socket: SOCKET,
+  _addresses: *mut u8,
+  overlapped: *mut OVERLAPPED,
+) -> bool {
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 0,
+  };
+  let overlapped = NonNull::new(overlapped).unwrap();
+  unsafe { port.complete_io(0, overlapped, result) };
+  true
+}
+
+fn main() {
+  let mut port = CompletionPort::new();
+  let mut accept = Box::new(SocketAcceptHandler {
+    state: EventState::new(),
+    listen_socket: 100,
+    accept_socket: 101,
+    addresses: [0; 2 * ACCEPT_ADDRESS_LENGTH],
+  });
+  let addresses = accept.addresses.as_mut_ptr();
+  let (listen_socket, accept_socket) =
+    (accept.listen_socket, accept.accept_socket);
+
+  // The addresses buffer lives inside the handler's allocation, so it stays
+  // put until AcceptEx() completes.
+  let mut d = accept.dispatch();
+  if fake_accept_ex(
+    &port,
+    listen_socket,
+    accept_socket,
+    addresses,
+    d.overlapped(),
+  ) {
+    d.pending();
+  } else {
+    d.failed();
+  }
+  port.run_until_idle(|_| {});
+}
@@ -0,0 +1,85 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr::NonNull;
+
+use crate::iocp::{
+  read_overlapped_result, write_overlapped_result, EventState, IoResult,
+};
+use crate::winapi::{
+  DWORD, ERROR_OPERATION_ABORTED, ERROR_SUCCESS,
+  LPOVERLAPPED_COMPLETION_ROUTINE, NTSTATUS, OVERLAPPED, STATUS_CANCELLED,
+  STATUS_PENDING, STATUS_SUCCESS, STATUS_UNSUCCESSFUL,
+};
+
+// Completion through APCs instead of a completion port: APIs like
+// ReadFileEx() and WriteFileEx() take a completion routine, which Windows
+// queues as an APC to the thread that started the operation, and runs the
+// next time that thread enters an alertable wait (e.g. SleepEx(.., TRUE)).
+// Pass `ApcBackend::ROUTINE` as that routine, with the OVERLAPPED of a
+// dispatched handler; the handler is then completed from the APC, the same
+// way `EventState::complete()` does for a packet from a port.
+pub struct ApcBackend;
+
+impl ApcBackend {
+  pub const ROUTINE: LPOVERLAPPED_COMPLETION_ROUTINE = Some(completion_routine);
+
+  // What the completion routine does, for driving it by hand, e.g. from a
+  // mock of the OS call. `error` is the Win32 error code the operation
+  // finished with, and `bytes` the number of bytes it transferred.
+  pub unsafe fn complete(
+    error: DWORD,
+    bytes: DWORD,
+    overlapped: NonNull<OVERLAPPED>,
+  ) {
+    let recorded = read_overlapped_result(overlapped.as_ptr()).status;
+    let result = IoResult {
+      status: status_of(error, recorded),
+      bytes_transferred: bytes,
+    };
+    write_overlapped_result(overlapped.as_ptr(), result);
+    EventState::complete(overlapped)
+  }
+}
+
+// Called by the kernel, so it must not unwind: that would abort the process.
+// A panic in the handler is therefore caught and written to stderr, and the
+// thread carries on with its alertable wait.
+unsafe extern "system" fn completion_routine(
+  error: DWORD,
+  bytes: DWORD,
+  overlapped: *mut OVERLAPPED,
+) {
+  let overlapped = match NonNull::new(overlapped) {
+    Some(overlapped) => overlapped,
+    None => {
+      eprintln!("ignoring completion routine call without OVERLAPPED");
+      return;
+    }
+  };
+  let completed = panic::catch_unwind(AssertUnwindSafe(|| {
+    ApcBackend::complete(error, bytes, overlapped)
+  }));
+  if let Err(panic) = completed {
+    let message = panic
+      .downcast_ref::<&str>()
+      .copied()
+      .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+      .unwrap_or("Box<dyn Any>");
+    eprintln!(
+      "completion of OVERLAPPED {:p} panicked: {}",
+      overlapped, message
+    );
+  }
+}
+
+// Handlers read their result as an NTSTATUS, but the completion routine gets
+// a Win32 error code. The kernel has normally left the actual status in the
+// OVERLAPPED already, so that is kept; otherwise (e.g. when the routine is
+// called by hand) the status is made up from the error code.
+fn status_of(error: DWORD, recorded: NTSTATUS) -> NTSTATUS {
+  match error {
+    ERROR_SUCCESS => STATUS_SUCCESS,
+    _ if recorded != STATUS_SUCCESS && recorded != STATUS_PENDING => recorded,
+    ERROR_OPERATION_ABORTED => STATUS_CANCELLED,
+    _ => STATUS_UNSUCCESSFUL,
+  }
+}
@@ -0,0 +1,87 @@
+use std::marker::PhantomData;
+use std::ptr::{null_mut, NonNull};
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::iocp::{Dispatch, EventHandler, EventState};
+use crate::winapi::OVERLAPPED;
+
+// A Dispatch that can be cloned and handed to other threads before it is
+// settled, e.g. to the worker of a work-stealing executor that ends up issuing
+// the OS call. All clones share one settlement: the first `pending()` or
+// `failed()` call wins, later ones are no-ops. Like Dispatch, it panics if the
+// last clone is dropped while still unsettled.
+pub struct ArcDispatch<T> {
+  inner: Arc<Inner<T>>,
+}
+
+struct Inner<T> {
+  overlapped: AtomicPtr<OVERLAPPED>,
+  _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> ArcDispatch<T>
+where
+  T: EventHandler,
+{
+  pub fn new(dispatch: Dispatch<T>) -> Self {
+    let overlapped = dispatch.disarm().as_ptr();
+    Self {
+      inner: Arc::new(Inner {
+        overlapped: AtomicPtr::new(overlapped),
+        _phantom: PhantomData,
+      }),
+    }
+  }
+
+  // The pointer to pass to the OS call, or null once settled.
+  pub fn overlapped(&self) -> *mut OVERLAPPED {
+    self.inner.overlapped.load(Ordering::Acquire)
+  }
+
+  pub fn is_settled(&self) -> bool {
+    self.overlapped().is_null()
+  }
+
+  // Returns false if another clone settled the dispatch first.
+  pub fn pending(&self) -> bool {
+    self.inner.settle().is_some()
+  }
+
+  // Returns None if another clone settled the dispatch first, or if the
+  // handler went missing; see `Dispatch::try_failed()`.
+  pub fn failed(&self) -> Option<Box<T>> {
+    let overlapped = self.inner.settle()?;
+    unsafe {
+      EventState::unregister_many(&[overlapped]);
+      EventState::reclaim(overlapped)
+    }
+  }
+}
+
+impl<T> Inner<T> {
+  fn settle(&self) -> Option<NonNull<OVERLAPPED>> {
+    NonNull::new(self.overlapped.swap(null_mut(), Ordering::AcqRel))
+  }
+}
+
+impl<T> Clone for ArcDispatch<T> {
+  fn clone(&self) -> Self {
+    Self {
+      inner: self.inner.clone(),
+    }
+  }
+}
+
+impl<T> Drop for Inner<T> {
+  fn drop(&mut self) {
+    if let Some(overlapped) = self.settle() {
+      if thread::panicking() {
+        unsafe { EventState::abandon_event_handler(overlapped) };
+      } else {
+        panic!("Either ArcDispatch::pending() or ArcDispatch::failed() must be called after dispatching an EventState.");
+      }
+    }
+  }
+}
@@ -0,0 +1,52 @@
+use std::convert::TryFrom;
+use std::ptr;
+
+use crate::winapi::WSABUF;
+
+// The buffers for a vectored WSASend()/WSARecv(), along with the WSABUF array
+// that describes them, for embedding in an event handler. The OS reads the
+// array as well as the buffers it points at while the operation is
+// outstanding, so both must stay put until it completes. The buffers do,
+// since they're owned here and never resized; the array does, since the
+// handler is boxed.
+pub struct BufArray<const N: usize> {
+  wsabufs: [WSABUF; N],
+  bufs: Vec<Box<[u8]>>,
+}
+
+impl<const N: usize> BufArray<N> {
+  // Panics unless exactly N buffers are given, or if one is too large for a
+  // WSABUF to describe.
+  pub fn new(bufs: Vec<Vec<u8>>) -> Self {
+    assert_eq!(bufs.len(), N, "BufArray<{}> needs {} buffers", N, N);
+    let mut bufs: Vec<Box<[u8]>> =
+      bufs.into_iter().map(Vec::into_boxed_slice).collect();
+    let mut wsabufs = [WSABUF {
+      len: 0,
+      buf: ptr::null_mut(),
+    }; N];
+    for (wsabuf, buf) in wsabufs.iter_mut().zip(bufs.iter_mut()) {
+      wsabuf.len = u32::try_from(buf.len()).expect("buffer too large");
+      wsabuf.buf = buf.as_mut_ptr();
+    }
+    Self { wsabufs, bufs }
+  }
+
+  pub fn descriptors(&self) -> &[WSABUF; N] {
+    &self.wsabufs
+  }
+
+  pub fn buf(&self, index: usize) -> &[u8] {
+    &self.bufs[index]
+  }
+
+  // Gives the buffers back, once the operation is done with them.
+  pub fn into_bufs(self) -> Vec<Box<[u8]>> {
+    self.bufs
+  }
+
+  // The lpBuffers and dwBufferCount arguments for WSASend()/WSARecv().
+  pub fn wsabufs(&mut self) -> (*mut WSABUF, u32) {
+    (self.wsabufs.as_mut_ptr(), N as u32)
+  }
+}
@@ -0,0 +1,97 @@
+use std::io;
+
+use crate::iocp::IoResult;
+use crate::winapi::{
+  NTSTATUS, STATUS_CANCELLED, STATUS_CONNECTION_REFUSED, STATUS_END_OF_FILE,
+  STATUS_IO_TIMEOUT, STATUS_PIPE_BROKEN, STATUS_SUCCESS, STATUS_TIMEOUT,
+};
+
+// Whether `status` is of the success or informational severity, i.e. its top
+// bit is clear, like NT_SUCCESS(). Such statuses (e.g. STATUS_TIMEOUT, which
+// is 0x102) mean the operation went through; warnings and errors don't.
+fn is_success(status: NTSTATUS) -> bool {
+  status >= 0
+}
+
+// The outcome of an operation, decoded once from the raw status and byte
+// count, so handlers can match on it in `complete()` rather than interpret
+// NTSTATUS values themselves. Get it from `EventState::read_completion()` or
+// `EventState::write_completion()`.
+#[derive(Debug)]
+pub enum CompletionKind {
+  // All requested bytes were transferred (or as many as the operation
+  // happened to move, if the request size isn't known).
+  Complete(u32),
+  // Fewer bytes than requested were transferred, e.g. a short read.
+  Partial(u32),
+  // A read hit the end of the file or stream.
+  Eof,
+  Cancelled(CancelReason),
+  Error(io::Error),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CancelReason {
+  // Cancelled with CancelIo() or CancelIoEx(), or because the handle was
+  // closed.
+  Cancelled,
+  // Timed out through `EventState::schedule_timeout()`.
+  TimedOut,
+}
+
+impl CompletionKind {
+  // `requested` is the number of bytes the read asked for, if known. A read
+  // ends at EOF when it fails with STATUS_END_OF_FILE (files), or with
+  // STATUS_PIPE_BROKEN (pipes whose writer went away), or when it succeeds
+  // with 0 bytes (sockets, and files opened for asynchronous I/O on some
+  // drivers). A read that asked for 0 bytes, e.g. to wait for a socket to
+  // become readable, is never at EOF though.
+  pub fn of_read(result: IoResult, requested: Option<usize>) -> Self {
+    match result.status {
+      STATUS_END_OF_FILE | STATUS_PIPE_BROKEN => Self::Eof,
+      STATUS_SUCCESS
+        if result.bytes_transferred == 0 && requested != Some(0) =>
+      {
+        Self::Eof
+      }
+      _ => Self::of_write(result, requested),
+    }
+  }
+
+  // `requested` is the number of bytes the write asked to transfer, if known.
+  // A write never ends at EOF; a broken pipe is an error. Statuses are told
+  // apart by severity, so any success or informational status counts as a
+  // transfer. Only `EventState::read_completion()` and `write_completion()`
+  // know whether a cancellation was due to a timeout; here it's
+  // `CancelReason::Cancelled`.
+  pub fn of_write(result: IoResult, requested: Option<usize>) -> Self {
+    let bytes = result.bytes_transferred;
+    match result.status {
+      status if is_success(status) => match requested {
+        Some(requested) if (bytes as usize) < requested => Self::Partial(bytes),
+        _ => Self::Complete(bytes),
+      },
+      STATUS_CANCELLED => Self::Cancelled(CancelReason::Cancelled),
+      status => Self::Error(status_to_io_error(status)),
+    }
+  }
+}
+
+// An io::Error for a failed operation's status. The ErrorKind is only known
+// for a few common statuses; the status itself is always in the message.
+// A cancelled operation is an Other error rather than Interrupted, which
+// read_exact(), io::copy() and most retry loops take as "try again".
+pub fn status_to_io_error(status: NTSTATUS) -> io::Error {
+  let kind = match status {
+    STATUS_CONNECTION_REFUSED => io::ErrorKind::ConnectionRefused,
+    STATUS_TIMEOUT | STATUS_IO_TIMEOUT => io::ErrorKind::TimedOut,
+    STATUS_PIPE_BROKEN => io::ErrorKind::BrokenPipe,
+    STATUS_END_OF_FILE => io::ErrorKind::UnexpectedEof,
+    _ => io::ErrorKind::Other,
+  };
+  let outcome = match status {
+    STATUS_CANCELLED => "operation was cancelled",
+    _ => "operation failed",
+  };
+  io::Error::new(kind, format!("{} with status {:#010x}", outcome, status))
+}
@@ -0,0 +1,623 @@
+use std::any::TypeId;
+use std::collections::{HashMap, VecDeque};
+use std::default::Default;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::mem::take;
+use std::panic::Location;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::iocp::{
+  write_overlapped_result, Dispatch, EventHandler, EventState, IoResult,
+};
+use crate::raw::{from_handle, to_handle, RawHandle};
+use crate::registry::Registry;
+use crate::winapi::{
+  DWORD, HANDLE, NTSTATUS, OVERLAPPED, OVERLAPPED_ENTRY, STATUS_CANCELLED,
+  ULONG_PTR,
+};
+
+// Minimal in-process stand-in for a Win32 I/O completion port, so event loops
+// can be written (and run) without the real thing. Clones refer to the same
+// port.
+#[derive(Clone, Default)]
+pub struct CompletionPort {
+  inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+  queue: Mutex<VecDeque<OVERLAPPED_ENTRY>>,
+  available: Condvar,
+  registry: Arc<Registry>,
+  associations: Mutex<HashMap<usize, usize>>,
+  // See `CompletionPort::set_byte_budget()`; zero if there is none.
+  byte_budget: AtomicUsize,
+  // The number of worker threads started by `CompletionPortBuilder::build()`
+  // that are still running.
+  workers: Mutex<usize>,
+  workers_exited: Condvar,
+  shut_down: AtomicBool,
+}
+
+// Returned by `CompletionPort::drain_shutdown()` if operations were still
+// outstanding when the timeout expired.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DrainTimeout {
+  pub outstanding: usize,
+}
+
+impl Display for DrainTimeout {
+  fn fmt(&self, f: &mut Formatter) -> FmtResult {
+    write!(
+      f,
+      "{} operation(s) still outstanding after draining the completion port",
+      self.outstanding
+    )
+  }
+}
+
+impl Error for DrainTimeout {}
+
+// How long `CompletionPort::drain_shutdown()` waits for a packet at a time.
+const DRAIN_POLL: Duration = Duration::from_millis(10);
+
+// An operation that was still outstanding; see
+// `CompletionPort::report_orphans()`.
+#[derive(Clone, Debug)]
+pub struct OrphanInfo {
+  pub type_id: TypeId,
+  pub type_name: &'static str,
+  pub handle: Option<RawHandle>,
+  pub location: &'static Location<'static>,
+  pub dispatched_at: Instant,
+  pub age: Duration,
+}
+
+// Counters for monitoring the operations dispatched through a CompletionPort;
+// see `CompletionPort::stats()`. Every operation is counted as submitted when
+// it's dispatched, and then as exactly one of the others when its handler
+// leaves the EventState:
+// - failed if it never started: its Dispatch was settled with `failed()`, or
+//   dropped while unwinding from a panic before it was settled;
+// - cancelled if it ended with STATUS_CANCELLED, or its handler was taken
+//   out without completing, by `EventState::detach_event_handler()` or
+//   `CompletionPort::drain_handlers()`;
+// - completed otherwise.
+#[derive(Debug, Default)]
+pub struct CompletionPortStats {
+  submitted: AtomicU64,
+  completed: AtomicU64,
+  failed: AtomicU64,
+  cancelled: AtomicU64,
+}
+
+// The values of the CompletionPortStats counters at one point in time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+  pub submitted: u64,
+  pub completed: u64,
+  pub failed: u64,
+  pub cancelled: u64,
+}
+
+impl StatsSnapshot {
+  // Operations that were submitted but haven't ended yet.
+  pub fn outstanding(&self) -> u64 {
+    let ended = self.completed + self.failed + self.cancelled;
+    self.submitted.saturating_sub(ended)
+  }
+}
+
+impl CompletionPortStats {
+  // The counters are updated independently, so operations that end while
+  // this runs may be missed; but an operation is never counted as ended
+  // without also being counted as submitted.
+  pub fn snapshot(&self) -> StatsSnapshot {
+    let completed = self.completed.load(Ordering::Acquire);
+    let failed = self.failed.load(Ordering::Acquire);
+    let cancelled = self.cancelled.load(Ordering::Acquire);
+    let submitted = self.submitted.load(Ordering::Acquire);
+    StatsSnapshot {
+      submitted,
+      completed,
+      failed,
+      cancelled,
+    }
+  }
+
+  pub(crate) fn record_submitted(&self) {
+    self.submitted.fetch_add(1, Ordering::AcqRel);
+  }
+
+  pub(crate) fn record_ended(&self, status: NTSTATUS) {
+    let counter = if status == STATUS_CANCELLED {
+      &self.cancelled
+    } else {
+      &self.completed
+    };
+    counter.fetch_add(1, Ordering::AcqRel);
+  }
+
+  pub(crate) fn record_cancelled(&self) {
+    self.cancelled.fetch_add(1, Ordering::AcqRel);
+  }
+
+  pub(crate) fn record_failed(&self, count: usize) {
+    self.failed.fetch_add(count as u64, Ordering::AcqRel);
+  }
+}
+
+// Completion key of the packets `CompletionPort::shutdown()` posts to make
+// the worker threads exit. They carry no OVERLAPPED.
+const SHUTDOWN_KEY: usize = usize::MAX;
+
+// Configures a CompletionPort that runs its completions on a pool of worker
+// threads, the way an IOCP runtime has N threads blocking in
+// GetQueuedCompletionStatusEx().
+pub struct CompletionPortBuilder {
+  thread_count: usize,
+}
+
+impl CompletionPortBuilder {
+  // Creates the port and starts the worker threads. The returned JoinHandle
+  // belongs to a thread that joins all workers, so it finishes once
+  // `CompletionPort::shutdown()` has stopped them, and joining it re-raises
+  // the panic if a worker panicked.
+  pub fn build(self) -> (CompletionPort, JoinHandle<()>) {
+    let port = CompletionPort::new();
+    *port.inner.workers.lock().unwrap() = self.thread_count;
+    let workers: Vec<_> = (0..self.thread_count)
+      .map(|_| {
+        let port = port.clone();
+        thread::spawn(move || port.run_worker())
+      })
+      .collect();
+    let supervisor = thread::spawn(move || {
+      let mut panicked = None;
+      for worker in workers {
+        if let Err(panic) = worker.join() {
+          panicked = Some(panic);
+        }
+      }
+      if let Some(panic) = panicked {
+        std::panic::resume_unwind(panic);
+      }
+    });
+    (port, supervisor)
+  }
+}
+
+// Counts a worker thread as exited when it stops, also if it panics.
+struct WorkerGuard<'a>(&'a Inner);
+
+impl Drop for WorkerGuard<'_> {
+  fn drop(&mut self) {
+    *self.0.workers.lock().unwrap() -= 1;
+    self.0.workers_exited.notify_all();
+  }
+}
+
+impl CompletionPort {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn with_thread_count(thread_count: usize) -> CompletionPortBuilder {
+    CompletionPortBuilder { thread_count }
+  }
+
+  // Posts a shutdown packet for every worker thread started by
+  // `CompletionPortBuilder::build()`, and waits until they have all exited.
+  // Packets queued before the shutdown are still processed. Must not be called
+  // from a worker thread, since that one would be waiting for itself.
+  pub fn shutdown(&self) {
+    let mut workers = self.inner.workers.lock().unwrap();
+    for _ in 0..*workers {
+      unsafe { self.post(SHUTDOWN_KEY, 0, std::ptr::null_mut()) };
+    }
+    while *workers > 0 {
+      workers = self.inner.workers_exited.wait(workers).unwrap();
+    }
+  }
+
+  // Graceful teardown. Stops new operations from being dispatched through
+  // the port, calls `cancel_io` (i.e. CancelIoEx()) for every associated
+  // handle, and runs completions until all operations dispatched through the
+  // port have come back or `timeout` expires. Then the port is closed: worker
+  // threads are shut down, and associations and any remaining packets are
+  // dropped.
+  pub fn drain_shutdown<F>(
+    &self,
+    timeout: Duration,
+    mut cancel_io: F,
+  ) -> Result<(), DrainTimeout>
+  where
+    F: FnMut(RawHandle),
+  {
+    let deadline = Instant::now() + timeout;
+    self.inner.shut_down.store(true, Ordering::Release);
+    let handles: Vec<usize> = self
+      .inner
+      .associations
+      .lock()
+      .unwrap()
+      .keys()
+      .copied()
+      .collect();
+    for handle in handles {
+      cancel_io(from_handle(handle as HANDLE));
+    }
+    let mut result = Ok(());
+    while !self.inner.registry.is_empty() {
+      let now = Instant::now();
+      if now >= deadline {
+        result = Err(DrainTimeout {
+          outstanding: self.inner.registry.len(),
+        });
+        break;
+      }
+      self.run_one(Some(DRAIN_POLL.min(deadline - now)));
+    }
+    self.shutdown();
+    self.inner.associations.lock().unwrap().clear();
+    self.inner.queue.lock().unwrap().clear();
+    result
+  }
+
+  // The loop each worker thread runs: completes whatever arrives, until it
+  // dequeues a shutdown packet.
+  fn run_worker(&self) {
+    let _guard = WorkerGuard(&self.inner);
+    while let Some(entry) = self.run_one(None) {
+      if entry.lpOverlapped.is_null() && entry.lpCompletionKey == SHUTDOWN_KEY {
+        break;
+      }
+    }
+  }
+
+  // Like CreateIoCompletionPort() with an existing port: completions of
+  // overlapped operations on `handle` are henceforth posted to this port with
+  // `key`. The handler that will perform those operations is told the key.
+  pub fn associate(
+    &self,
+    handle: RawHandle,
+    key: usize,
+    event_handler: &mut dyn EventHandler,
+  ) {
+    let mut associations = self.inner.associations.lock().unwrap();
+    associations.insert(to_handle(handle) as usize, key);
+    event_handler.on_completion_port_associate(key);
+  }
+
+  // Recovery for when the port had to be recreated: moves everything that
+  // refers to this port over to `new_port`. Handles associated with this port
+  // are associated with `new_port` under the same key, so completions of the
+  // operations outstanding on them are delivered there, as are the packets
+  // already queued here. Handles that had to be reopened too are replaced
+  // according to `new_handle_map`. The outstanding operations are moved to
+  // `new_port`'s registry, where they count as submitted; here, they count as
+  // cancelled, since they'll never complete through this port. Nothing may be
+  // dispatched or completed through either port while this runs.
+  pub unsafe fn reassociate_all(
+    &self,
+    new_port: &CompletionPort,
+    new_handle_map: &HashMap<RawHandle, RawHandle>,
+  ) {
+    let map = |handle: usize| {
+      let handle = from_handle(handle as HANDLE);
+      let handle = new_handle_map.get(&handle).copied().unwrap_or(handle);
+      to_handle(handle) as usize
+    };
+    let associations = take(&mut *self.inner.associations.lock().unwrap());
+    let mut new_associations = new_port.inner.associations.lock().unwrap();
+    for (handle, key) in associations {
+      new_associations.insert(map(handle), key);
+    }
+    drop(new_associations);
+    let registry = &new_port.inner.registry;
+    for (overlapped, mut record) in self.inner.registry.take_all() {
+      self.inner.registry.stats().record_cancelled();
+      record.handle = record.handle.map(map);
+      registry.charge(record.cost, None);
+      EventState::set_registry(NonNull::new(overlapped).unwrap(), registry);
+      registry.insert(overlapped, record);
+    }
+    let queued = take(&mut *self.inner.queue.lock().unwrap());
+    for entry in queued {
+      new_port.enqueue(entry);
+    }
+  }
+
+  // The completion key `handle` was associated with, if any.
+  pub fn key_of(&self, handle: RawHandle) -> Option<usize> {
+    let associations = self.inner.associations.lock().unwrap();
+    associations.get(&(to_handle(handle) as usize)).copied()
+  }
+
+  // Dispatches `event_handler` like `Dispatchable::dispatch()` does, but also
+  // records the operation as outstanding on this port until its handler is
+  // completed or reclaimed.
+  #[track_caller]
+  pub fn dispatch<T>(&self, event_handler: Box<T>) -> Dispatch<T>
+  where
+    T: EventHandler,
+  {
+    self.register(event_handler, Location::caller(), None)
+  }
+
+  // Like `dispatch()`, for an operation on `handle`. The handle shows up in
+  // `report_orphans()`.
+  #[track_caller]
+  pub fn dispatch_on<T>(
+    &self,
+    handle: RawHandle,
+    event_handler: Box<T>,
+  ) -> Dispatch<T>
+  where
+    T: EventHandler,
+  {
+    self.register(event_handler, Location::caller(), Some(handle))
+  }
+
+  // Like `dispatch()`, but only if the handler's `resource_cost()` fits in
+  // what's left of the port's byte budget. Otherwise the handler is handed
+  // back, e.g. to be retried once some outstanding operations have ended.
+  #[track_caller]
+  pub fn try_dispatch<T>(
+    &self,
+    event_handler: Box<T>,
+  ) -> Result<Dispatch<T>, Box<T>>
+  where
+    T: EventHandler,
+  {
+    let budget = self.byte_budget();
+    self.register_within(event_handler, Location::caller(), None, budget)
+  }
+
+  // Limits the sum of the `resource_cost()` of the operations dispatched
+  // through `try_dispatch()`. Operations count against the budget until
+  // their handlers leave their EventStates. `dispatch()` and `dispatch_on()`
+  // ignore the budget, but what they dispatch still counts against it.
+  pub fn set_byte_budget(&self, budget: Option<usize>) {
+    let budget = budget.unwrap_or(0);
+    self.inner.byte_budget.store(budget, Ordering::Release);
+  }
+
+  pub fn byte_budget(&self) -> Option<usize> {
+    match self.inner.byte_budget.load(Ordering::Acquire) {
+      0 => None,
+      budget => Some(budget),
+    }
+  }
+
+  // The bytes tied up in the operations outstanding on this port, by their
+  // handlers' `resource_cost()`.
+  pub fn bytes_outstanding(&self) -> usize {
+    self.inner.registry.bytes()
+  }
+
+  fn register<T>(
+    &self,
+    event_handler: Box<T>,
+    location: &'static Location<'static>,
+    handle: Option<RawHandle>,
+  ) -> Dispatch<T>
+  where
+    T: EventHandler,
+  {
+    match self.register_within(event_handler, location, handle, None) {
+      Ok(dispatch) => dispatch,
+      // Without a budget, only an overflow gets the handler rejected.
+      Err(_) => panic!("outstanding resource cost overflowed"),
+    }
+  }
+
+  fn register_within<T>(
+    &self,
+    event_handler: Box<T>,
+    location: &'static Location<'static>,
+    handle: Option<RawHandle>,
+    budget: Option<usize>,
+  ) -> Result<Dispatch<T>, Box<T>>
+  where
+    T: EventHandler,
+  {
+    assert!(
+      !self.inner.shut_down.load(Ordering::Acquire),
+      "dispatch on a CompletionPort that has been shut down"
+    );
+    let registry = &self.inner.registry;
+    let cost = event_handler.resource_cost();
+    if !registry.charge(cost, budget) {
+      return Err(event_handler);
+    }
+    let dispatch =
+      EventState::register(event_handler, registry, location, handle, cost);
+    Ok(dispatch)
+  }
+
+  // For diagnostics at shutdown: the operations dispatched through this port
+  // that are still outstanding, oldest first, e.g. to spot a hung driver.
+  // Unlike `drain_shutdown()`, this leaves them alone.
+  pub fn report_orphans(&self) -> Vec<OrphanInfo> {
+    let now = Instant::now();
+    self
+      .inner
+      .registry
+      .snapshot()
+      .into_iter()
+      .map(|(_, op)| OrphanInfo {
+        type_id: op.type_id,
+        type_name: op.type_name,
+        handle: op.handle.map(|handle| from_handle(handle as HANDLE)),
+        location: op.location,
+        dispatched_at: op.dispatched_at,
+        age: now.saturating_duration_since(op.dispatched_at),
+      })
+      .collect()
+  }
+
+  // Takes the handlers of all operations dispatched through this port that
+  // are still outstanding out of their EventStates, without completing them,
+  // e.g. to drop them at shutdown or dispatch them on another port. This
+  // leaves the registry empty, and drops the packets already queued for those
+  // operations. The OS must be done with the operations: their handles must
+  // have been closed, or the operations cancelled and their packets dequeued
+  // (only a packet still on its way from the OS could otherwise arrive).
+  // The handlers are handed back alive, so `on_free()` isn't called on them,
+  // and the operations are counted as cancelled.
+  pub unsafe fn drain_handlers(&self) -> Vec<Box<dyn EventHandler>> {
+    let overlappeds: Vec<_> = self
+      .inner
+      .registry
+      .snapshot()
+      .into_iter()
+      .map(|(overlapped, _)| overlapped)
+      .collect();
+    self
+      .inner
+      .queue
+      .lock()
+      .unwrap()
+      .retain(|entry| !overlappeds.contains(&entry.lpOverlapped));
+    overlappeds
+      .into_iter()
+      .map(|overlapped| {
+        let overlapped = NonNull::new(overlapped).unwrap();
+        EventState::drain_event_handler(overlapped)
+      })
+      .collect()
+  }
+
+  pub fn registry(&self) -> &Registry {
+    &self.inner.registry
+  }
+
+  pub fn stats(&self) -> &CompletionPortStats {
+    self.inner.registry.stats()
+  }
+
+  // For test teardown: panics if any operation dispatched through this port
+  // is still outstanding, listing the handler type and dispatch location of
+  // each.
+  #[track_caller]
+  pub fn assert_no_leaks(&self) {
+    let leaks = self.inner.registry.snapshot();
+    if leaks.is_empty() {
+      return;
+    }
+    let list: Vec<String> = leaks
+      .iter()
+      .map(|(_, op)| {
+        format!("  {} dispatched at {}", op.type_name, op.location)
+      })
+      .collect();
+    panic!(
+      "{} dispatched operation(s) never completed:\n{}",
+      leaks.len(),
+      list.join("\n")
+    );
+  }
+
+  // Like PostQueuedCompletionStatus(): queues a completion packet without
+  // touching the OVERLAPPED. `overlapped` must be null, or belong to an
+  // operation that was dispatched and has finished, like for `complete_io()`:
+  // `run_one()` completes the handler that owns it.
+  pub unsafe fn post(
+    &self,
+    key: usize,
+    bytes: u32,
+    overlapped: *mut OVERLAPPED,
+  ) {
+    self.enqueue(OVERLAPPED_ENTRY {
+      lpCompletionKey: key as ULONG_PTR,
+      lpOverlapped: overlapped,
+      Internal: 0,
+      dwNumberOfBytesTransferred: bytes as DWORD,
+    });
+  }
+
+  pub(crate) fn enqueue(&self, entry: OVERLAPPED_ENTRY) {
+    self.inner.queue.lock().unwrap().push_back(entry);
+    self.inner.available.notify_one();
+  }
+
+  // Does what the kernel does when an overlapped operation on a handle that is
+  // associated with this port finishes: records the result in the OVERLAPPED
+  // and queues a completion packet for it. `overlapped` must belong to an
+  // operation that was dispatched and is still outstanding; `run_one()`
+  // completes the handler that owns it.
+  pub unsafe fn complete_io(
+    &self,
+    key: usize,
+    overlapped: NonNull<OVERLAPPED>,
+    result: IoResult,
+  ) {
+    write_overlapped_result(overlapped.as_ptr(), result);
+    self.enqueue(OVERLAPPED_ENTRY {
+      lpCompletionKey: key as ULONG_PTR,
+      lpOverlapped: overlapped.as_ptr(),
+      Internal: result.status as ULONG_PTR,
+      dwNumberOfBytesTransferred: result.bytes_transferred as DWORD,
+    });
+  }
+
+  // Like GetQueuedCompletionStatus(). Waits at most `timeout` for a packet to
+  // arrive, or forever if it is None.
+  pub fn get_queued_completion_status(
+    &self,
+    timeout: Option<Duration>,
+  ) -> Option<OVERLAPPED_ENTRY> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut queue = self.inner.queue.lock().unwrap();
+    loop {
+      if let Some(entry) = queue.pop_front() {
+        return Some(entry);
+      }
+      queue = match deadline {
+        None => self.inner.available.wait(queue).unwrap(),
+        Some(deadline) => {
+          let now = Instant::now();
+          if now >= deadline {
+            return None;
+          }
+          self
+            .inner
+            .available
+            .wait_timeout(queue, deadline - now)
+            .unwrap()
+            .0
+        }
+      };
+    }
+  }
+
+  // Dequeues one packet and, if it carries an OVERLAPPED, completes the event
+  // handler that owns it. Returns None if no packet arrived within `timeout`.
+  // Packets only carry an OVERLAPPED if they were queued by `complete_io()` or
+  // `post()`, whose callers vouched for it, so this can be safe.
+  pub fn run_one(&self, timeout: Option<Duration>) -> Option<OVERLAPPED_ENTRY> {
+    let entry = self.get_queued_completion_status(timeout)?;
+    if !entry.lpOverlapped.is_null() {
+      unsafe { EventState::complete_entry(&entry) };
+    }
+    Some(entry)
+  }
+
+  // Keeps running completions until none are left in the queue, calling `f`
+  // after each one. Completions that get queued while this runs (e.g. by `f`
+  // or by handlers) are processed too.
+  pub fn run_until_idle<F>(&mut self, mut f: F)
+  where
+    F: FnMut(&mut CompletionPort),
+  {
+    while self.run_one(Some(Duration::from_secs(0))).is_some() {
+      f(self);
+    }
+  }
+}
@@ -0,0 +1,91 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::iocp::OwnedCompletion;
+
+// Bounded multi-producer multi-consumer queue for handing completed
+// operations from the thread that dequeues them to worker threads, which
+// `finish()` them. Bounded so that slow workers push back on the dequeue
+// thread rather than letting the backlog grow without limit; when the queue
+// is full, the dequeue thread can either wait (`push()`) or get the completion
+// back and run it itself (`try_push()`). Clones refer to the same queue.
+#[derive(Clone)]
+pub struct CompletionQueue {
+  inner: Arc<Inner>,
+}
+
+struct Inner {
+  completions: Mutex<VecDeque<OwnedCompletion>>,
+  capacity: usize,
+  not_empty: Condvar,
+  not_full: Condvar,
+}
+
+impl CompletionQueue {
+  pub fn with_capacity(capacity: usize) -> Self {
+    assert!(capacity > 0, "CompletionQueue capacity must be nonzero");
+    Self {
+      inner: Arc::new(Inner {
+        completions: Mutex::new(VecDeque::with_capacity(capacity)),
+        capacity,
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+      }),
+    }
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.inner.capacity
+  }
+
+  pub fn len(&self) -> usize {
+    self.inner.completions.lock().unwrap().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  // Queues `completion`, waiting for room if the queue is full.
+  pub fn push(&self, completion: OwnedCompletion) {
+    let mut completions = self.inner.completions.lock().unwrap();
+    while completions.len() == self.inner.capacity {
+      completions = self.inner.not_full.wait(completions).unwrap();
+    }
+    completions.push_back(completion);
+    self.inner.not_empty.notify_one();
+  }
+
+  // Queues `completion`, or hands it back if the queue is full.
+  pub fn try_push(
+    &self,
+    completion: OwnedCompletion,
+  ) -> Result<(), OwnedCompletion> {
+    let mut completions = self.inner.completions.lock().unwrap();
+    if completions.len() == self.inner.capacity {
+      return Err(completion);
+    }
+    completions.push_back(completion);
+    self.inner.not_empty.notify_one();
+    Ok(())
+  }
+
+  // Takes the oldest completion out of the queue, waiting for one if the
+  // queue is empty.
+  pub fn pop(&self) -> OwnedCompletion {
+    let mut completions = self.inner.completions.lock().unwrap();
+    loop {
+      if let Some(completion) = completions.pop_front() {
+        self.inner.not_full.notify_one();
+        return completion;
+      }
+      completions = self.inner.not_empty.wait(completions).unwrap();
+    }
+  }
+
+  pub fn try_pop(&self) -> Option<OwnedCompletion> {
+    let completion = self.inner.completions.lock().unwrap().pop_front()?;
+    self.inner.not_full.notify_one();
+    Some(completion)
+  }
+}
@@ -1,13 +1,27 @@
+use std::any::TypeId;
 use std::mem::{align_of, size_of};
 use std::ptr::NonNull;
+use std::rc::Rc;
+use std::sync::Arc;
 
 pub trait ContainerOf<T>
 where
-  Self: Sized + 'static,
-  T: Sized + 'static,
+  Self: Sized,
+  T: Sized,
 {
+  // Implementors whose member is at offset 0 (e.g. the first field of a
+  // `#[repr(C)]` struct) set this, so that `container_of()` and friends skip
+  // the offset computation and the checks, and become a plain cast. Derive
+  // it with `offset_of!(Self, member) == 0` rather than setting it to true,
+  // so it can't go stale when fields are moved around.
+  const OFFSET_IS_ZERO: bool = false;
+
   fn member(&self) -> &T;
 
+  // Computed from a dangling pointer on every call. The result never changes
+  // for a given type pair, but a `static` in a generic function is shared by
+  // all its instantiations, so it can't be cached here; implementors on hot
+  // paths can override this with a cached version.
   #[inline(always)]
   unsafe fn member_offset() -> usize {
     let dummy = NonNull::<Self>::dangling();
@@ -28,13 +42,18 @@ where
 
   #[inline(always)]
   unsafe fn container_of_ptr(member: *const T) -> *const Self {
+    if Self::OFFSET_IS_ZERO {
+      debug_assert_eq!(Self::member_offset(), 0);
+      return member as *const Self;
+    }
+
     let member_addr = member as usize;
     let member_offset = Self::member_offset();
     assert!(member_addr > member_offset);
 
     let container_addr = member_addr - member_offset;
     let container_alignment = align_of::<Self>();
-    assert!(container_addr % container_alignment == 0);
+    assert!(container_addr.is_multiple_of(container_alignment));
 
     container_addr as *const Self
   }
@@ -53,3 +72,88 @@ where
     &mut *container_ptr
   }
 }
+
+// ContainerOf for types without borrowed data, which can also be identified
+// at runtime by their TypeId.
+pub trait ContainerOfStatic<T>: ContainerOf<T>
+where
+  Self: 'static,
+  T: 'static,
+{
+  #[inline(always)]
+  fn container_type_id() -> TypeId {
+    TypeId::of::<Self>()
+  }
+
+  #[inline(always)]
+  fn member_type_id() -> TypeId {
+    TypeId::of::<T>()
+  }
+}
+
+// Why is this not in std? https://github.com/rust-lang/rust/issues/47336
+pub trait CastNonNull<T>
+where
+  T: ?Sized,
+{
+  fn into_non_null(self) -> NonNull<T>;
+  unsafe fn from_non_null(from: NonNull<T>) -> Self;
+}
+
+impl<T> CastNonNull<T> for Box<T>
+where
+  T: ?Sized,
+{
+  #[inline(always)]
+  fn into_non_null(self) -> NonNull<T> {
+    let ptr = Box::into_raw(self);
+    unsafe { NonNull::new_unchecked(ptr) }
+  }
+
+  #[inline(always)]
+  unsafe fn from_non_null(from: NonNull<T>) -> Self {
+    let ptr = from.as_ptr();
+    unsafe { Box::from_raw(ptr) }
+  }
+}
+
+// The pointer carries one strong reference: `into_non_null` hands it over
+// without decrementing the count, and `from_non_null` takes it back. Every
+// pointer must therefore be converted back exactly once, and only with the
+// same smart pointer type it came from (never via Box) -- otherwise the
+// count is leaked or dropped twice.
+impl<T> CastNonNull<T> for Arc<T>
+where
+  T: ?Sized,
+{
+  #[inline(always)]
+  fn into_non_null(self) -> NonNull<T> {
+    let ptr = Arc::into_raw(self) as *mut T;
+    unsafe { NonNull::new_unchecked(ptr) }
+  }
+
+  #[inline(always)]
+  unsafe fn from_non_null(from: NonNull<T>) -> Self {
+    let ptr = from.as_ptr() as *const T;
+    unsafe { Arc::from_raw(ptr) }
+  }
+}
+
+// Same reference count semantics as the Arc impl above. Note that Rc is not
+// Send, so the pointer must also be converted back on the thread it came from.
+impl<T> CastNonNull<T> for Rc<T>
+where
+  T: ?Sized,
+{
+  #[inline(always)]
+  fn into_non_null(self) -> NonNull<T> {
+    let ptr = Rc::into_raw(self) as *mut T;
+    unsafe { NonNull::new_unchecked(ptr) }
+  }
+
+  #[inline(always)]
+  unsafe fn from_non_null(from: NonNull<T>) -> Self {
+    let ptr = from.as_ptr() as *const T;
+    unsafe { Rc::from_raw(ptr) }
+  }
+}
@@ -0,0 +1,84 @@
+use std::default::Default;
+use std::mem::take;
+use std::thread;
+
+use crate::iocp::{Dispatch, EventHandler};
+use crate::winapi::OVERLAPPED;
+
+// Groups dispatches that must be settled all the same way, for protocols
+// where a batch of operations either all start or all fail. Like a single
+// Dispatch, the batch must be settled (with `commit_all_pending()` or
+// `rollback_all_failed()`) before it is dropped.
+pub struct DispatchBatch<T>
+where
+  T: EventHandler,
+{
+  dispatches: Vec<Dispatch<T>>,
+}
+
+impl<T> DispatchBatch<T>
+where
+  T: EventHandler,
+{
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn len(&self) -> usize {
+    self.dispatches.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.dispatches.is_empty()
+  }
+
+  pub fn push(&mut self, dispatch: Dispatch<T>) {
+    self.dispatches.push(dispatch);
+  }
+
+  // The OVERLAPPEDs to pass to the OS calls, in the order they were pushed.
+  pub fn overlappeds(&mut self) -> Vec<*mut OVERLAPPED> {
+    self
+      .dispatches
+      .iter_mut()
+      .map(Dispatch::overlapped)
+      .collect()
+  }
+
+  pub fn commit_all_pending(mut self) {
+    for dispatch in take(&mut self.dispatches) {
+      dispatch.pending();
+    }
+  }
+
+  // Hands back the handlers, in the order they were pushed.
+  pub fn rollback_all_failed(mut self) -> Vec<Box<T>> {
+    Dispatch::fail_many(take(&mut self.dispatches))
+  }
+}
+
+impl<T> Default for DispatchBatch<T>
+where
+  T: EventHandler,
+{
+  fn default() -> Self {
+    Self {
+      dispatches: Vec::new(),
+    }
+  }
+}
+
+impl<T> Drop for DispatchBatch<T>
+where
+  T: EventHandler,
+{
+  fn drop(&mut self) {
+    // When unwinding, the dispatches clean up after themselves.
+    if !self.dispatches.is_empty() && !thread::panicking() {
+      panic!(
+        "DispatchBatch of {} dispatch(es) dropped without being settled",
+        self.dispatches.len()
+      );
+    }
+  }
+}
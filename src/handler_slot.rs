@@ -0,0 +1,74 @@
+use std::cell::UnsafeCell;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::iocp::EventHandler;
+
+// The place in an EventState where its event handler is embedded. Taking the
+// handler out is a single atomic swap, so when extractions race (e.g. a
+// completion on one thread, and `Dispatch::failed()` or a timeout on another),
+// exactly one of them gets it, without locking.
+//
+// A `*mut dyn EventHandler` is too wide for an AtomicPtr. So the atomic holds
+// just the data pointer, which tells whether the slot is full, and the whole
+// pointer is kept next to it. The latter is only written while the slot is
+// empty, before the data pointer is published, and only read by whoever owns
+// the slot's contents.
+pub(crate) struct HandlerSlot {
+  data: AtomicPtr<()>,
+  handler: UnsafeCell<Option<*mut dyn EventHandler>>,
+}
+
+// The slot owns a Box<dyn EventHandler>, which is Send.
+unsafe impl Send for HandlerSlot {}
+
+impl HandlerSlot {
+  pub(crate) fn new() -> Self {
+    Self {
+      data: AtomicPtr::new(ptr::null_mut()),
+      handler: UnsafeCell::new(None),
+    }
+  }
+
+  pub(crate) fn is_some(&self) -> bool {
+    !self.data.load(Ordering::Acquire).is_null()
+  }
+
+  pub(crate) fn is_none(&self) -> bool {
+    !self.is_some()
+  }
+
+  // Panics if the slot is full already.
+  pub(crate) fn put(&mut self, handler: Box<dyn EventHandler>) {
+    assert!(self.is_none());
+    let handler = Box::into_raw(handler);
+    *self.handler.get_mut() = Some(handler);
+    self.data.store(handler as *mut (), Ordering::Release);
+  }
+
+  pub(crate) fn take(&self) -> Option<Box<dyn EventHandler>> {
+    let data = self.data.swap(ptr::null_mut(), Ordering::AcqRel);
+    if data.is_null() {
+      return None;
+    }
+    let handler = unsafe { (*self.handler.get()).unwrap() };
+    Some(unsafe { Box::from_raw(handler) })
+  }
+
+  pub(crate) fn get(&self) -> Option<&dyn EventHandler> {
+    if self.is_none() {
+      return None;
+    }
+    let handler = unsafe { (*self.handler.get()).unwrap() };
+    Some(unsafe { &*handler })
+  }
+}
+
+// A handler still embedded when its EventState goes away is abandoned.
+impl Drop for HandlerSlot {
+  fn drop(&mut self) {
+    if let Some(mut handler) = self.take() {
+      handler.on_free();
+    }
+  }
+}
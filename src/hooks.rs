@@ -0,0 +1,77 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::default::Default;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use crate::iocp::{EventHandler, IoResult};
+
+// What a completion hook gets to see about the operation that completed.
+#[derive(Clone, Copy, Debug)]
+pub struct CompletionInfo {
+  pub type_id: TypeId,
+  pub result: IoResult,
+}
+
+pub type CompletionHook = Box<dyn Fn(&CompletionInfo) + Send + Sync>;
+
+// Completion hooks keyed by event handler type, for cross-cutting concerns
+// (e.g. per-operation-type metrics) that shouldn't live in the handlers
+// themselves. A hook runs right before the handler's own `complete()`. Hooks
+// either apply to every completion in the process, once installed with
+// `EventState::set_completion_hooks()`, or to a single one, when passed to
+// `EventState::complete_with_hooks()`.
+#[derive(Default)]
+pub struct CompletionHooks {
+  hooks: HashMap<TypeId, CompletionHook>,
+}
+
+impl CompletionHooks {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  // Registers the hook for event handlers of type `T`, replacing the one that
+  // was registered before, if any.
+  pub fn register<T, F>(&mut self, hook: F) -> Option<CompletionHook>
+  where
+    T: EventHandler,
+    F: Fn(&CompletionInfo) + Send + Sync + 'static,
+  {
+    self.hooks.insert(TypeId::of::<T>(), Box::new(hook))
+  }
+
+  pub fn unregister<T>(&mut self) -> Option<CompletionHook>
+  where
+    T: EventHandler,
+  {
+    self.hooks.remove(&TypeId::of::<T>())
+  }
+
+  pub(crate) fn run(&self, info: &CompletionInfo) {
+    if let Some(hook) = self.hooks.get(&info.type_id) {
+      hook(info);
+    }
+  }
+}
+
+// The hooks installed with `EventState::set_completion_hooks()`. Whether there
+// are any is tracked separately, so completions don't take the lock for
+// nothing.
+static INSTALLED: RwLock<Option<CompletionHooks>> = RwLock::new(None);
+static ANY_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn install(hooks: Option<CompletionHooks>) {
+  let mut installed = INSTALLED.write().unwrap();
+  ANY_INSTALLED.store(hooks.is_some(), Ordering::Release);
+  *installed = hooks;
+}
+
+pub(crate) fn run_installed(info: &CompletionInfo) {
+  if !ANY_INSTALLED.load(Ordering::Acquire) {
+    return;
+  }
+  if let Some(hooks) = &*INSTALLED.read().unwrap() {
+    hooks.run(info);
+  }
+}
@@ -1,27 +1,264 @@
-use std::any::{Any, TypeId};
+use std::any::{type_name, Any, TypeId};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
 use std::default::Default;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::hash::{Hash, Hasher};
+use std::io;
 use std::marker::PhantomData;
-use std::mem::transmute;
+use std::mem::{align_of, offset_of, size_of, size_of_val, take};
 use std::ops::{Deref, DerefMut};
-use std::ptr::NonNull;
+use std::panic::{self, AssertUnwindSafe, Location};
+use std::pin::Pin;
+use std::ptr::{self, NonNull};
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{SendError, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
 
-use crate::container_of::ContainerOf;
-use crate::winapi::OVERLAPPED;
+use crate::completion_kind::{CancelReason, CompletionKind};
+use crate::completion_port::{CompletionPort, CompletionPortStats};
+use crate::container_of::{ContainerOf, ContainerOfStatic};
+use crate::handler_slot::HandlerSlot;
+use crate::hooks::{self, CompletionHooks, CompletionInfo};
+use crate::non_send::{LocalEventHandler, NonSend};
+use crate::raw::{to_handle, RawHandle};
+use crate::registry::{OpRecord, Registry};
+use crate::timer_queue::{TimerId, TimerQueue};
+#[cfg(feature = "trace-hooks")]
+use crate::trace_context::{self, Entered, Span, SpanSlot, TraceId};
+use crate::vtable::{UnboxedEventHandler, Vtable};
+use crate::waker_set::WakerSet;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::winapi::STATUS_SUCCESS;
+use crate::winapi::{
+  DWORD, INVALID_HANDLE_VALUE, NTSTATUS, OVERLAPPED, OVERLAPPED_ENTRY,
+  STATUS_CANCELLED, STATUS_PENDING, ULONG_PTR,
+};
+
+// Outcome of an overlapped operation, as recorded by the kernel in the
+// OVERLAPPED's `Internal` (status) and `InternalHigh` (byte count) fields.
+//
+// `InternalHigh` is a ULONG_PTR, so its width depends on the target, but a
+// single operation never transfers more than a DWORD's worth of bytes: that's
+// also the type GetOverlappedResult() and OVERLAPPED_ENTRY report it as. So
+// the byte count is a u32 on every target; it's zero-extended when written
+// back into `InternalHigh`, and narrowed the same way Windows does on read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IoResult {
+  pub status: NTSTATUS,
+  pub bytes_transferred: DWORD,
+}
+
+// Reads the status and byte count straight out of a raw OVERLAPPED, without
+// touching the event handler embedded in the surrounding EventState. This lets
+// a reactor make routing decisions (e.g. cancelled vs. success) before it
+// extracts the handler.
+pub unsafe fn read_overlapped_result(overlapped: *mut OVERLAPPED) -> IoResult {
+  let internal =
+    AtomicUsize::from_ptr(ptr::addr_of_mut!((*overlapped).Internal));
+  let status = internal.load(Ordering::Acquire) as NTSTATUS;
+  IoResult {
+    status,
+    bytes_transferred: (*overlapped).InternalHigh as DWORD,
+  }
+}
+
+// Records `result` in a raw OVERLAPPED, the way the kernel does when the
+// operation finishes. The status goes in last, with release ordering, so
+// whoever sees it change (e.g. `EventState::wait()` on another thread) sees
+// the byte count as well.
+pub unsafe fn write_overlapped_result(
+  overlapped: *mut OVERLAPPED,
+  result: IoResult,
+) {
+  (*overlapped).InternalHigh = result.bytes_transferred as ULONG_PTR;
+  let internal =
+    AtomicUsize::from_ptr(ptr::addr_of_mut!((*overlapped).Internal));
+  internal.store(result.status as ULONG_PTR, Ordering::Release);
+}
+
+// What to do about an OVERLAPPED that shows up without an event handler in
+// its EventState, e.g. a stray or duplicate completion. A server may prefer
+// to survive those. Set with `EventState::set_missing_handler_policy()`; the
+// default is to panic in debug builds, and to log and ignore otherwise.
+#[derive(Clone, Copy, Debug)]
+pub enum MissingHandlerPolicy {
+  Panic,
+  // Writes a message to stderr.
+  LogAndIgnore,
+  // Calls the function with the OVERLAPPED, and otherwise ignores it.
+  Hook(fn(*mut OVERLAPPED)),
+}
+
+// How an operation that was aborted with `Dispatch::abort()` ended. Unless
+// the wait timed out, its handler has been completed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AbortResult {
+  Cancelled,
+  // The operation finished before it could be cancelled.
+  Completed(IoResult),
+  // The operation didn't come back in time. It's still outstanding, and is
+  // completed by whoever drains the port once its packet arrives.
+  TimedOut,
+}
+
+// How long `Dispatch::abort()` waits for a packet at a time.
+const ABORT_POLL: Duration = Duration::from_millis(10);
+
+// A completed operation whose handler hasn't run yet; see
+// `EventState::take_completion()`. Dropping it drops the handler without
+// completing it, but still wakes the tasks that were awaiting it.
+pub struct OwnedCompletion {
+  handler: Option<Box<dyn EventHandler>>,
+}
+
+impl OwnedCompletion {
+  pub fn handler(&self) -> &dyn EventHandler {
+    &**self.handler.as_ref().unwrap()
+  }
+
+  // Completes the handler, and then wakes the tasks that were awaiting it.
+  pub fn finish(mut self) {
+    EventState::run_complete(self.handler.take().unwrap())
+  }
+}
+
+impl Drop for OwnedCompletion {
+  fn drop(&mut self) {
+    if let Some(mut handler) = self.handler.take() {
+      let wakers = handler.state().take_wakers();
+      handler.on_free();
+      drop(handler);
+      wakers.wake_all()
+    }
+  }
+}
+
+// Why `EventState::complete_checked()` rejected a pointer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvalidOverlapped {
+  Null,
+  Misaligned,
+  // Outside the range of addresses user-mode memory can live at.
+  OutOfRange,
+  // Not an operation that is outstanding on the port.
+  Foreign,
+}
+
+impl Display for InvalidOverlapped {
+  fn fmt(&self, f: &mut Formatter) -> FmtResult {
+    let reason = match self {
+      Self::Null => "null",
+      Self::Misaligned => "misaligned",
+      Self::OutOfRange => "outside the user address range",
+      Self::Foreign => "not an outstanding operation",
+    };
+    write!(f, "invalid OVERLAPPED pointer: {}", reason)
+  }
+}
+
+impl Error for InvalidOverlapped {}
 
 // Wrapper around OVERLAPPED.
 // mio expects all events that arrive on it's completion port to be wrapped with this.
-#[derive(Default)]
+#[repr(C)]
 pub struct EventState {
-  event_handler: Option<Box<dyn EventHandler>>,
+  // Kept first, so getting from the OVERLAPPED to the EventState is free.
   overlapped: OVERLAPPED,
+  event_handler: HandlerSlot,
+  // Alternatively, an unboxed handler; see `embed_vtable()`.
+  vtable: Option<Vtable<()>>,
+  registry: Option<Arc<Registry>>,
+  size_hint: usize,
+  sequence: u64,
+  #[cfg(feature = "trace-hooks")]
+  trace_id: Option<TraceId>,
+  #[cfg(feature = "trace-hooks")]
+  span: SpanSlot,
+  #[cfg(all(target_os = "linux", feature = "io-uring"))]
+  user_data: u64,
+  wakers: WakerSet,
+  // Set while `complete_reusable()` has the handler out.
+  reusing: AtomicBool,
+  // The timer set with `schedule_timeout()`, if any, and whether it fired.
+  timeout: Option<(TimerQueue, TimerId)>,
+  timed_out: AtomicBool,
+}
+
+// The range of addresses user-mode memory can live at on Windows. The lowest
+// 64K are never mapped, and neither is anything past the user address space.
+const LOWEST_USER_ADDRESS: usize = 0x1_0000;
+#[cfg(target_pointer_width = "64")]
+const HIGHEST_USER_ADDRESS: usize = 0x7fff_fffe_ffff;
+#[cfg(not(target_pointer_width = "64"))]
+const HIGHEST_USER_ADDRESS: usize = 0xfffe_ffff;
+
+// Source of `EventState::sequence()`. Starts at 1, so 0 can mean 'never
+// dispatched'.
+static NEXT_SEQUENCE: AtomicU64 = AtomicU64::new(1);
+
+impl Default for EventState {
+  fn default() -> Self {
+    Self {
+      overlapped: OVERLAPPED::default(),
+      event_handler: HandlerSlot::new(),
+      vtable: None,
+      registry: None,
+      size_hint: 0,
+      sequence: 0,
+      #[cfg(feature = "trace-hooks")]
+      trace_id: None,
+      #[cfg(feature = "trace-hooks")]
+      span: SpanSlot::default(),
+      #[cfg(all(target_os = "linux", feature = "io-uring"))]
+      user_data: 0,
+      wakers: WakerSet::new(),
+      reusing: AtomicBool::new(false),
+      timeout: None,
+      timed_out: AtomicBool::new(false),
+    }
+  }
+}
+
+// So async code that needs the EventState at a stable address can write
+// `EventState::new().into()`.
+impl From<EventState> for Pin<Box<EventState>> {
+  fn from(state: EventState) -> Self {
+    Box::pin(state)
+  }
 }
 
+// Where the OVERLAPPED sits in an EventState. Reordering the fields would
+// quietly take `container_of()` off its fast path, so it isn't allowed to
+// happen by accident.
+const OVERLAPPED_OFFSET: usize = offset_of!(EventState, overlapped);
+const _: () = assert!(
+  OVERLAPPED_OFFSET == 0,
+  "the OVERLAPPED must be the first field of EventState"
+);
+const _: () = assert!(
+  OVERLAPPED_OFFSET + size_of::<OVERLAPPED>() <= size_of::<EventState>()
+);
+
 impl ContainerOf<OVERLAPPED> for EventState {
+  const OFFSET_IS_ZERO: bool = OVERLAPPED_OFFSET == 0;
+
   fn member(&self) -> &OVERLAPPED {
     &self.overlapped
   }
+
+  // `is_ours()` needs this for every packet, and it's known at compile time.
+  unsafe fn member_offset() -> usize {
+    OVERLAPPED_OFFSET
+  }
 }
 
+impl ContainerOfStatic<OVERLAPPED> for EventState {}
+
 impl EventState {
   pub fn new() -> Self {
     Default::default()
@@ -39,27 +276,406 @@ impl EventState {
     Self::container_of_mut(overlapped)
   }
 
+  // Whether getting from this EventState to its OVERLAPPED and back, the way
+  // dispatching and completing do, lands on this very EventState. A guard for
+  // the address arithmetic in `container_of`; see the tests below.
+  #[cfg(test)]
+  fn round_trips(&mut self) -> bool {
+    let this: *const Self = self;
+    let overlapped = self.as_overlapped();
+    let state: *const Self = unsafe { Self::from_overlapped(overlapped) };
+    ptr::eq(state, this)
+  }
+
   // Embed ownership of the EventHandler inside its own EventState, and then
   // This reference cycle violates Rust borrowing rules, so we make both the state
   // and handler inaccessible by returning a raw pointer to the win32 OVERLAPPED struct.
   fn embed_event_handler(
     mut event_handler: Box<dyn EventHandler>,
   ) -> NonNull<OVERLAPPED> {
+    let size_hint = event_handler.size_hint();
+    // The OVERLAPPED is handed out by address, so `state()` must return the
+    // same EventState every time, not a temporary or one that moves around.
+    debug_assert!(
+      ptr::eq(
+        event_handler.state() as *const Self,
+        event_handler.state() as *const Self
+      ),
+      "{}::state() doesn't return a stable EventState",
+      event_handler.type_name()
+    );
     let state: &mut Self = event_handler.state();
     assert!(state.event_handler.is_none());
-    let state: &'static mut Self = unsafe { transmute(state) };
-    state.event_handler = Some(event_handler);
-    state.as_overlapped()
+    state.size_hint = size_hint;
+    state.sequence = NEXT_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    state.set_pending();
+    *state.timed_out.get_mut() = false;
+    #[cfg(feature = "trace-hooks")]
+    {
+      state.trace_id = trace_context::current();
+    }
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    {
+      state.user_data = state.as_overlapped().as_ptr() as u64;
+    }
+    // The state lives inside the handler's allocation, which doesn't move
+    // when the Box does, so a raw pointer to it stays valid while the handler
+    // is moved into it.
+    let state: *mut Self = state;
+    unsafe {
+      (*state).event_handler.put(event_handler);
+      (*state).as_overlapped()
+    }
   }
 
   // The OVERLAPPED can and must be converted back to an EventHandler exactly once.
   // At this point the reference cycle is also broken and ownership of the event handler
   // is returned to the caller.
-  unsafe fn extract_event_handler(
+  pub(crate) unsafe fn extract_event_handler(
+    overlapped: NonNull<OVERLAPPED>,
+  ) -> Box<dyn EventHandler> {
+    let state = Self::from_overlapped(overlapped);
+    state.take_event_handler().unwrap()
+  }
+
+  // Like `extract_event_handler()`, for an operation that's being given up on
+  // rather than completed, e.g. when draining a port. Its handler is handed
+  // back alive, and the operation is counted as cancelled.
+  pub(crate) unsafe fn drain_event_handler(
     overlapped: NonNull<OVERLAPPED>,
   ) -> Box<dyn EventHandler> {
     let state = Self::from_overlapped(overlapped);
-    state.event_handler.take().unwrap()
+    state
+      .take_event_handler_with(CompletionPortStats::record_cancelled)
+      .unwrap()
+  }
+
+  // Drops the handler of an operation that will never complete, e.g. because
+  // we're unwinding from a panic that happened before the OS call was made.
+  // The operation is counted as failed, since it never started.
+  pub(crate) unsafe fn abandon_event_handler(overlapped: NonNull<OVERLAPPED>) {
+    let state = Self::from_overlapped(overlapped);
+    let mut event_handler = state
+      .take_event_handler_with(|stats| stats.record_failed(1))
+      .unwrap();
+    event_handler.on_free();
+  }
+
+  // Every path that takes the handler out of the EventState goes through here,
+  // so the operation is also dropped from the registry it's recorded in, and
+  // its timer (if any) is cancelled. It's counted as having ended with the
+  // status in its OVERLAPPED.
+  fn take_event_handler(&mut self) -> Option<Box<dyn EventHandler>> {
+    let status = self.result().status;
+    self.take_event_handler_with(|stats| stats.record_ended(status))
+  }
+
+  // Like `take_event_handler()`, but `record` counts how the operation ended.
+  fn take_event_handler_with<F>(
+    &mut self,
+    record: F,
+  ) -> Option<Box<dyn EventHandler>>
+  where
+    F: FnOnce(&CompletionPortStats),
+  {
+    let event_handler = self.event_handler.take()?;
+    self.cancel_timeout();
+    if let Some(registry) = self.registry.take() {
+      registry.remove(&mut self.overlapped);
+      record(registry.stats());
+    }
+    Some(event_handler)
+  }
+
+  // Drops a batch of operations that failed to start from the registries
+  // they're recorded in, taking each registry's lock once rather than once
+  // per operation.
+  pub(crate) unsafe fn unregister_many(overlappeds: &[NonNull<OVERLAPPED>]) {
+    let mut batches: Vec<(Arc<Registry>, Vec<*mut OVERLAPPED>)> = Vec::new();
+    for &overlapped in overlappeds {
+      let registry = match Self::from_overlapped(overlapped).registry.take() {
+        Some(registry) => registry,
+        None => continue,
+      };
+      match batches.iter_mut().find(|(r, _)| Arc::ptr_eq(r, &registry)) {
+        Some((_, batch)) => batch.push(overlapped.as_ptr()),
+        None => batches.push((registry, vec![overlapped.as_ptr()])),
+      }
+    }
+    for (registry, batch) in batches {
+      registry.remove_many(&batch);
+      registry.stats().record_failed(batch.len());
+    }
+  }
+
+  // Points an outstanding operation at the registry it has been moved to.
+  pub(crate) unsafe fn set_registry(
+    overlapped: NonNull<OVERLAPPED>,
+    registry: &Arc<Registry>,
+  ) {
+    Self::from_overlapped(overlapped).registry = Some(registry.clone());
+  }
+
+  // Records the operation in `registry` until the handler leaves the
+  // EventState again. `cost` must have been charged to the registry already.
+  pub(crate) fn register<T>(
+    mut event_handler: Box<T>,
+    registry: &Arc<Registry>,
+    location: &'static Location<'static>,
+    handle: Option<RawHandle>,
+    cost: usize,
+  ) -> Dispatch<T>
+  where
+    T: EventHandler,
+  {
+    let record = OpRecord {
+      type_id: TypeId::of::<T>(),
+      type_name: event_handler.type_name(),
+      location,
+      dispatched_at: Instant::now(),
+      handle: handle.map(|handle| to_handle(handle) as usize),
+      cost,
+    };
+    event_handler.state().registry = Some(registry.clone());
+    let mut dispatch = Self::dispatch(event_handler);
+    registry.insert(dispatch.overlapped(), record);
+    dispatch
+  }
+
+  // Removes the embedded event handler without completing it, leaving the
+  // EventState idle. Meant for cleanup paths (e.g. shutdown) where the handler
+  // must be dropped before its completion has arrived. The handler gets its
+  // `on_free()` before it's handed back. The operation is counted as
+  // cancelled.
+  pub fn detach_event_handler(&mut self) -> Option<Box<dyn EventHandler>> {
+    let mut handler =
+      self.take_event_handler_with(CompletionPortStats::record_cancelled)?;
+    handler.on_free();
+    Some(handler)
+  }
+
+  // The outcome of the operation. Only meaningful once it has completed, e.g.
+  // when called by an EventHandler from within `complete()`.
+  pub fn result(&self) -> IoResult {
+    let overlapped = &self.overlapped as *const _ as *mut OVERLAPPED;
+    unsafe { read_overlapped_result(overlapped) }
+  }
+
+  // The byte count of a completed operation, for deciding e.g. whether to
+  // prefetch before the handler runs. None if the EventState was never
+  // dispatched, or its operation is still in progress.
+  pub fn peek_bytes_transferred(&self) -> Option<u32> {
+    if self.sequence == 0 {
+      return None;
+    }
+    let result = self.result();
+    if result.status == STATUS_PENDING {
+      return None;
+    }
+    Some(result.bytes_transferred)
+  }
+
+  // The file position ReadFile()/WriteFile() should start at. The OVERLAPPED
+  // stores it as two DWORDs, low part in `Offset`, high part in `OffsetHigh`.
+  pub fn set_file_offset(&mut self, offset: u64) {
+    self.overlapped.Offset = offset as DWORD;
+    self.overlapped.OffsetHigh = (offset >> 32) as DWORD;
+  }
+
+  pub fn get_file_offset(&self) -> u64 {
+    let low = self.overlapped.Offset as u64;
+    let high = self.overlapped.OffsetHigh as u64;
+    (high << 32) | low
+  }
+
+  // Has Windows signal a manual-reset event when the operation completes, so
+  // it can be waited for with WaitForSingleObject() instead of (or besides)
+  // through a completion port.
+  pub fn set_event_handle(&mut self, handle: RawHandle) {
+    let handle = to_handle(handle);
+    assert!(!handle.is_null() && handle != INVALID_HANDLE_VALUE);
+    self.overlapped.hEvent = handle;
+  }
+
+  pub fn clear_event_handle(&mut self) {
+    self.overlapped.hEvent = ptr::null_mut();
+  }
+
+  // Records the outcome of the operation in the OVERLAPPED, the way the
+  // kernel does when it completes.
+  pub(crate) fn set_result(&mut self, result: IoResult) {
+    unsafe { write_overlapped_result(&mut self.overlapped, result) }
+  }
+
+  // Marks the operation as in progress until something records its outcome,
+  // so a result left over from an earlier dispatch can't be mistaken for
+  // this one's.
+  fn set_pending(&mut self) {
+    self.set_result(IoResult {
+      status: STATUS_PENDING,
+      bytes_transferred: 0,
+    });
+  }
+
+  // Identifies the most recent dispatch of this EventState, for correlating
+  // log lines written at dispatch and at completion. Ids are process-global
+  // and increase with every dispatch, so they stay distinct when a handler is
+  // dispatched again. Zero if the EventState was never dispatched.
+  pub fn sequence(&self) -> u64 {
+    self.sequence
+  }
+
+  // The `user_data` to put in the io_uring SQE for this operation, so the CQE
+  // can be routed back with `complete_from_userdata()`. Dispatching sets it
+  // to the address of the OVERLAPPED, which is what that expects; it's only
+  // worth overriding for a ring whose CQEs are routed some other way.
+  #[cfg(all(target_os = "linux", feature = "io-uring"))]
+  pub fn get_sqe_userdata(&self) -> u64 {
+    self.user_data
+  }
+
+  #[cfg(all(target_os = "linux", feature = "io-uring"))]
+  pub fn set_sqe_userdata(&mut self, user_data: u64) {
+    self.user_data = user_data;
+  }
+
+  // The io_uring counterpart of `complete()`: completes the handler whose
+  // `get_sqe_userdata()` was `user_data`, with the CQE's `res`. That is
+  // either the byte count, or a negated errno, which is stored as the status
+  // unchanged (it's negative, so it reads as an error either way).
+  #[cfg(all(target_os = "linux", feature = "io-uring"))]
+  pub unsafe fn complete_from_userdata(user_data: u64, res: i32) {
+    let overlapped = NonNull::new(user_data as usize as *mut OVERLAPPED)
+      .expect("io_uring CQE without user_data");
+    let result = if res >= 0 {
+      IoResult {
+        status: STATUS_SUCCESS,
+        bytes_transferred: res as u32,
+      }
+    } else {
+      IoResult {
+        status: res,
+        bytes_transferred: 0,
+      }
+    };
+    Self::from_overlapped(overlapped).set_result(result);
+    Self::complete(overlapped)
+  }
+
+  // The trace that was current when this EventState was last dispatched.
+  #[cfg(feature = "trace-hooks")]
+  pub fn trace_id(&self) -> Option<TraceId> {
+    self.trace_id
+  }
+
+  // Dispatches `event_handler`, which keeps `span` until it's completed.
+  // Completion then runs inside the span, so it covers the operation from
+  // start to end. If the handler is dropped without being completed, the
+  // span is abandoned instead.
+  #[cfg(feature = "trace-hooks")]
+  pub fn dispatch_with_span<T>(
+    mut event_handler: Box<T>,
+    span: Box<dyn Span>,
+  ) -> Dispatch<T>
+  where
+    T: EventHandler,
+  {
+    event_handler.state().span = SpanSlot::new(span);
+    Self::dispatch(event_handler)
+  }
+
+  // Like `complete()`, but runs the completion inside `span`. Any
+  // `trace_context::Span` will do; for a span of the `tracing` crate, wrap it
+  // as described there.
+  #[cfg(feature = "trace-hooks")]
+  pub unsafe fn complete_with_span<S>(overlapped: NonNull<OVERLAPPED>, span: S)
+  where
+    S: Span,
+  {
+    let _span = Entered::new(&span);
+    Self::complete(overlapped)
+  }
+
+  // The result size the embedded handler predicted when it was dispatched.
+  pub fn size_hint(&self) -> usize {
+    self.size_hint
+  }
+
+  // The outcome of the read this EventState was dispatched for, taking the
+  // handler's `size_hint()` as the number of bytes requested (unless it's
+  // zero, which means unknown). Only meaningful once the read has completed.
+  // For zero-byte reads, use `CompletionKind::of_read()` with Some(0).
+  // An operation that was cancelled by its timeout is reported as
+  // `CancelReason::TimedOut`.
+  pub fn read_completion(&self) -> CompletionKind {
+    let kind = CompletionKind::of_read(self.result(), self.requested_bytes());
+    self.attribute_cancellation(kind)
+  }
+
+  // Like `read_completion()`, for a write.
+  pub fn write_completion(&self) -> CompletionKind {
+    let kind = CompletionKind::of_write(self.result(), self.requested_bytes());
+    self.attribute_cancellation(kind)
+  }
+
+  fn attribute_cancellation(&self, kind: CompletionKind) -> CompletionKind {
+    match kind {
+      CompletionKind::Cancelled(_) if self.is_timed_out() => {
+        CompletionKind::Cancelled(CancelReason::TimedOut)
+      }
+      kind => kind,
+    }
+  }
+
+  // Whether the operation was cancelled by the timer set with
+  // `schedule_timeout()`, or at least was about to be.
+  pub fn is_timed_out(&self) -> bool {
+    self.timed_out.load(Ordering::Acquire)
+  }
+
+  pub(crate) unsafe fn mark_timed_out(overlapped: *mut OVERLAPPED) {
+    let state = Self::container_of_ptr(overlapped);
+    (*state).timed_out.store(true, Ordering::Release);
+  }
+
+  // Drops the timer set with `schedule_timeout()`. If it's firing right now,
+  // this waits for it to finish, so the EventState can be freed afterwards.
+  fn cancel_timeout(&mut self) {
+    if let Some((timer, id)) = self.timeout.take() {
+      timer.cancel(id);
+    }
+  }
+
+  fn requested_bytes(&self) -> Option<usize> {
+    Some(self.size_hint).filter(|&size_hint| size_hint != 0)
+  }
+
+  // The tasks awaiting this operation. They are woken right after the
+  // handler's `complete()` has run, whichever path completes it. Paths that
+  // hand the handler off instead (e.g. `complete_to()`) wake them once it has
+  // left the EventState.
+  pub fn wakers(&self) -> &WakerSet {
+    &self.wakers
+  }
+
+  // A typed view of the embedded event handler, without taking it out of the
+  // EventState. Returns None if the slot is empty or holds a different type.
+  pub fn handler_as<T>(&self) -> Option<&T>
+  where
+    T: EventHandler,
+  {
+    let event_handler: &dyn EventHandler = self.event_handler.get()?;
+    if event_handler.type_id() != TypeId::of::<T>() {
+      return None;
+    }
+    let ptr = event_handler as *const dyn EventHandler as *const T;
+    Some(unsafe { &*ptr })
+  }
+
+  // The number of bytes the embedded event handler occupies, for memory
+  // accounting. Zero if no boxed handler is embedded.
+  pub fn embedded_size_of(&self) -> usize {
+    self.event_handler.get().map_or(0, size_of_val)
   }
 
   fn downcast_event_handler<T>(event_handler: Box<dyn EventHandler>) -> Box<T>
@@ -86,23 +702,809 @@ impl EventState {
     // completion event to show up on the completion port eventually.
   }
 
+  // Dispatches a handler that isn't Send. The caller must make sure that the
+  // completion is always processed on the thread that dispatched it, and that
+  // the returned Dispatch doesn't leave that thread either. That thread is the
+  // handler's `expected_thread()`, so in debug builds, completing it on any
+  // other thread panics before the handler runs.
+  pub unsafe fn try_embed_non_send<T>(
+    event_handler: Box<T>,
+  ) -> Dispatch<NonSend<Box<T>>>
+  where
+    T: LocalEventHandler,
+  {
+    Self::dispatch(Box::new(NonSend::new(event_handler)))
+  }
+
   // If the windows API indicated failure, this function can be used to turn the raw
   // *mut OVERLAPPED back into the original boxed event handler. This is not called
   // by the user directly, but by the implementation of `struct Dispatch`.
-  unsafe fn undispatch<T>(overlapped: NonNull<OVERLAPPED>) -> Box<T>
+  // Returns None if there's no handler to turn it back into, once the
+  // MissingHandlerPolicy has dealt with that (if it didn't panic).
+  pub(crate) unsafe fn undispatch<T>(
+    overlapped: NonNull<OVERLAPPED>,
+  ) -> Option<Box<T>>
   where
     T: EventHandler,
   {
-    let handler = Self::extract_event_handler(overlapped);
-    Self::downcast_event_handler(handler)
+    let handler = Self::extract_or_report(overlapped)?;
+    Some(Self::downcast_event_handler(handler))
     // TODO: notify MIO here that some event isn't coming after all.
   }
 
+  // `undispatch()` for a Dispatch that failed: the handler is done with this
+  // operation, so it gets its `on_free()` before it's handed back.
+  pub(crate) unsafe fn reclaim<T>(
+    overlapped: NonNull<OVERLAPPED>,
+  ) -> Option<Box<T>>
+  where
+    T: EventHandler,
+  {
+    let mut handler = Self::undispatch::<T>(overlapped)?;
+    handler.on_free();
+    Some(handler)
+  }
+
+  // Takes the handler out, or if there is none, deals with that as the
+  // MissingHandlerPolicy says and returns None. Every path that expects a
+  // handler to be there goes through here.
+  unsafe fn extract_or_report(
+    overlapped: NonNull<OVERLAPPED>,
+  ) -> Option<Box<dyn EventHandler>> {
+    if !Self::has_event_handler(overlapped) {
+      Self::missing_event_handler(overlapped);
+      return None;
+    }
+    Some(Self::extract_event_handler(overlapped))
+  }
+
+  // Embeds an UnboxedEventHandler in its EventState, the way dispatching does
+  // for a boxed one, and returns the OVERLAPPED to pass to the OS call. When
+  // the operation completes, `complete()` runs the handler and drops it in
+  // place. If the OS call fails, `unembed_vtable()` must be called instead.
+  pub unsafe fn embed_vtable<T>(vtable: Vtable<T>) -> NonNull<OVERLAPPED>
+  where
+    T: UnboxedEventHandler,
+  {
+    let state = &mut *vtable.state();
+    assert!(state.event_handler.is_none() && state.vtable.is_none());
+    state.sequence = NEXT_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    state.set_pending();
+    state.vtable = Some(vtable.erase());
+    state.as_overlapped()
+  }
+
+  // Undoes `embed_vtable()` for an operation that failed to start, dropping
+  // the handler in place.
+  pub unsafe fn unembed_vtable(overlapped: NonNull<OVERLAPPED>) {
+    let vtable = Self::from_overlapped(overlapped).vtable.take().unwrap();
+    vtable.drop_handler()
+  }
+
+  unsafe fn has_event_handler(overlapped: NonNull<OVERLAPPED>) -> bool {
+    Self::from_overlapped(overlapped).event_handler.is_some()
+  }
+
+  // Deals with an OVERLAPPED whose handler is gone already, e.g. because the
+  // same operation was completed twice, as the MissingHandlerPolicy says.
+  unsafe fn missing_event_handler(overlapped: NonNull<OVERLAPPED>) {
+    let policy = *MISSING_HANDLER_POLICY.lock().unwrap();
+    match policy {
+      MissingHandlerPolicy::Panic => {
+        panic!("no event handler embedded in OVERLAPPED {:p}", overlapped)
+      }
+      MissingHandlerPolicy::LogAndIgnore => eprintln!(
+        "ignoring completion of OVERLAPPED {:p}: no event handler embedded",
+        overlapped
+      ),
+      MissingHandlerPolicy::Hook(hook) => hook(overlapped.as_ptr()),
+    }
+  }
+
   // Called by mio when the OVERLAPPED was returned by GetQueuedCompletionStatusEx()
-  pub unsafe fn complete(overlapped: NonNull<OVERLAPPED>) -> () {
+  pub unsafe fn complete(overlapped: NonNull<OVERLAPPED>) {
+    let state = Self::from_overlapped(overlapped);
+    if let Some(vtable) = state.vtable.take() {
+      state.cancel_timeout();
+      let _wakers = state.take_wakers().wake_on_drop();
+      let completing = (vtable.type_id(), "an UnboxedEventHandler");
+      let _completing = CompletingGuard::enter(completing);
+      return vtable.complete();
+    }
+    // The handler isn't missing, it's just out for `complete_mut()`.
+    assert!(
+      !state.reusing.load(Ordering::Acquire),
+      "EventState::complete() while complete_reusable() runs"
+    );
+    if let Some(handler) = Self::extract_or_report(overlapped) {
+      Self::run_complete(handler)
+    }
+  }
+
+  // For callers that don't run a completion port loop: blocks until the
+  // operation on `handle` that `overlapped` belongs to is done, completes the
+  // handler, and returns the result. The handle must not be associated with
+  // a completion port (or the OVERLAPPED's hEvent must have its low bit set),
+  // or the completion would be delivered there as well. If waiting fails
+  // (e.g. because `handle` is invalid) while the operation is still in
+  // progress, the error is returned and the handler is left alone, since the
+  // OS still owns the OVERLAPPED.
+  pub unsafe fn wait(
+    overlapped: NonNull<OVERLAPPED>,
+    handle: RawHandle,
+  ) -> io::Result<IoResult> {
+    Self::wait_for_result(overlapped, handle)?;
+    let result = read_overlapped_result(overlapped.as_ptr());
+    Self::complete(overlapped);
+    Ok(result)
+  }
+
+  #[cfg(windows)]
+  unsafe fn wait_for_result(
+    overlapped: NonNull<OVERLAPPED>,
+    handle: RawHandle,
+  ) -> io::Result<()> {
+    let mut bytes: DWORD = 0;
+    let done = crate::winapi::GetOverlappedResult(
+      to_handle(handle),
+      overlapped.as_ptr(),
+      &mut bytes,
+      1,
+    );
+    // It also fails when the operation itself did, but then the outcome is
+    // in the OVERLAPPED, and the operation is over all the same.
+    let result = read_overlapped_result(overlapped.as_ptr());
+    if done == 0 && result.status == STATUS_PENDING {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(())
+  }
+
+  // Elsewhere there's no kernel to ask. Like on Windows, an operation counts
+  // as in progress while the `Internal` field of its OVERLAPPED says
+  // STATUS_PENDING, which dispatching sets. Whatever stands in for the OS
+  // call replaces it with the outcome when the operation is done, using
+  // `write_overlapped_result()`, possibly from another thread.
+  #[cfg(not(windows))]
+  unsafe fn wait_for_result(
+    overlapped: NonNull<OVERLAPPED>,
+    _handle: RawHandle,
+  ) -> io::Result<()> {
+    while read_overlapped_result(overlapped.as_ptr()).status == STATUS_PENDING {
+      thread::yield_now();
+    }
+    Ok(())
+  }
+
+  // Like `complete()`, but if the handler panics, the panic is caught and
+  // returned, so the event loop can log it and carry on draining the port
+  // rather than unwinding. The handler is gone either way.
+  pub unsafe fn complete_panic_safe(
+    overlapped: NonNull<OVERLAPPED>,
+  ) -> thread::Result<()> {
+    panic::catch_unwind(AssertUnwindSafe(|| Self::complete(overlapped)))
+  }
+
+  // For event loops that also receive packets without a handler, e.g. a
+  // null OVERLAPPED posted with PostQueuedCompletionStatus() as a shutdown
+  // signal. Completes the handler and returns true, unless `overlapped` is
+  // null or its handler was taken out already, in which case it does nothing
+  // and returns false. The latter is only detectable while the EventState
+  // itself is still alive, e.g. when it's owned by something other than the
+  // handler.
+  pub unsafe fn complete_or_ignore(overlapped: *mut OVERLAPPED) -> bool {
+    let overlapped = match NonNull::new(overlapped) {
+      Some(overlapped) => overlapped,
+      None => return false,
+    };
+    let state = Self::from_overlapped(overlapped);
+    if state.event_handler.is_none() && state.vtable.is_none() {
+      return false;
+    }
+    Self::complete(overlapped);
+    true
+  }
+
+  // Like `complete()`, but passes the packet dequeued for the operation on to
+  // the handler's `decode_dyn()`. InPlaceEventHandlers are completed in place
+  // instead.
+  pub unsafe fn complete_entry(raw: &OVERLAPPED_ENTRY) {
+    let overlapped = NonNull::new(raw.lpOverlapped).unwrap();
+    // Unboxed handlers, and missing ones, are dealt with the usual way.
+    if !Self::has_event_handler(overlapped) {
+      return Self::complete(overlapped);
+    }
     let handler = Self::extract_event_handler(overlapped);
+    Self::run_complete_with(handler, |mut handler| {
+      if handler.as_in_place().is_some() {
+        Self::complete_boxed(handler)
+      } else {
+        handler.on_free();
+        handler.decode_dyn(raw)
+      }
+    })
+  }
+
+  // Completes an operation that read into a buffer the handler doesn't own,
+  // by passing that buffer to the handler. Handlers that don't support this
+  // (see `EventHandler::as_in_place()`) are completed as usual.
+  pub unsafe fn complete_in_place(
+    overlapped: NonNull<OVERLAPPED>,
+    buf: &mut [u8],
+  ) {
+    let result = read_overlapped_result(overlapped.as_ptr());
+    let handler = match Self::extract_or_report(overlapped) {
+      Some(handler) => handler,
+      None => return,
+    };
+    Self::run_complete_with(handler, |mut handler| {
+      match handler.as_in_place() {
+        Some(in_place) => {
+          in_place.complete_in_place(buf, result.bytes_transferred);
+          handler.on_free();
+        }
+        None => Self::free_and_complete(handler),
+      }
+    })
+  }
+
+  // The zero-reallocation completion path, for handlers that implement
+  // ReusableEventHandler. The handler is taken out of the EventState just for
+  // the duration of `complete_mut()`, and then put back, so it stays embedded
+  // and its OVERLAPPED can be passed to the next OS call right away. Panics
+  // if the same operation is completed again while `complete_mut()` runs,
+  // e.g. because the handler re-armed itself and that completed already. If
+  // `complete_mut()` panics, the handler is put back all the same.
+  pub unsafe fn complete_reusable(
+    overlapped: NonNull<OVERLAPPED>,
+    result: IoResult,
+  ) {
+    let state = Self::from_overlapped(overlapped);
+    let reusing = state.reusing.compare_exchange(
+      false,
+      true,
+      Ordering::AcqRel,
+      Ordering::Acquire,
+    );
+    assert!(
+      reusing.is_ok(),
+      "re-entrant EventState::complete_reusable()"
+    );
+    state.set_result(result);
+    let _wakers = state.take_wakers().wake_on_drop();
+    let mut reusing = ReusingGuard {
+      overlapped,
+      event_handler: state.event_handler.take(),
+    };
+    reusing
+      .event_handler
+      .as_mut()
+      .unwrap()
+      .as_reusable()
+      .expect("complete_reusable() requires a ReusableEventHandler")
+      .complete_mut();
+  }
+
+  // Calls the handler's `complete()`, while recording which handler is being
+  // completed, so a Dispatch that `complete()` leaves unsettled can name it.
+  // Also makes the trace the operation was dispatched on behalf of current
+  // again, so the completion work is attributed to it, and enters the span it
+  // was dispatched with, if any. The installed completion hooks run right
+  // before `complete()`, and the tasks awaiting the operation are woken right
+  // after it. In debug builds, checks that the handler is completed on its
+  // `expected_thread()`.
+  pub(crate) fn run_complete(handler: Box<dyn EventHandler>) {
+    Self::run_complete_with(handler, Self::complete_boxed)
+  }
+
+  // Calls `complete()`, or `complete_in_place()` for an InPlaceEventHandler,
+  // with the buffer it reports through `io_buffer()`.
+  fn complete_boxed(mut handler: Box<dyn EventHandler>) {
+    let bytes = handler.state().result().bytes_transferred;
+    let buf = handler.io_buffer();
+    match (handler.as_in_place(), buf) {
+      (Some(in_place), Some((ptr, len))) => {
+        let buf = unsafe { slice::from_raw_parts_mut(ptr, len) };
+        in_place.complete_in_place(buf, bytes);
+        handler.on_free();
+      }
+      _ => Self::free_and_complete(handler),
+    }
+  }
+
+  // `complete()` takes the handler with it, so `on_free()` is the last thing
+  // that happens to the handler before it's handed over.
+  fn free_and_complete(mut handler: Box<dyn EventHandler>) {
+    handler.on_free();
     handler.complete()
   }
+
+  // Like `run_complete()`, for handlers that outlive their completion, e.g.
+  // to be dispatched again (see `HandlerPool::complete_and_rearm()`): `f`
+  // stands in for `complete()`, gets the handler as a `T`, and what it
+  // returns is passed on. None if the handler is missing (and the
+  // MissingHandlerPolicy let that pass), or timed out, in which case its
+  // `timed_out()` is called instead of `f`.
+  pub(crate) unsafe fn complete_reused<T, F, R>(
+    overlapped: NonNull<OVERLAPPED>,
+    f: F,
+  ) -> Option<R>
+  where
+    T: EventHandler,
+    F: FnOnce(Box<T>) -> R,
+  {
+    let handler = Self::extract_or_report(overlapped)?;
+    let mut reused = None;
+    Self::run_complete_with(handler, |handler| {
+      reused = Some(f(Self::downcast_event_handler(handler)))
+    });
+    reused
+  }
+
+  // Like `run_complete()`, with `f` standing in for `complete()`, for the
+  // completion paths that call some other method of the handler.
+  fn run_complete_with<F>(mut handler: Box<dyn EventHandler>, f: F)
+  where
+    F: FnOnce(Box<dyn EventHandler>),
+  {
+    Self::check_thread(&*handler);
+    let completing = ((*handler).type_id(), handler.type_name());
+    let _completing = CompletingGuard::enter(completing);
+    #[cfg(feature = "trace-hooks")]
+    let _trace = trace_context::enter(handler.state().trace_id);
+    #[cfg(feature = "trace-hooks")]
+    let span = handler.state().span.take();
+    #[cfg(feature = "trace-hooks")]
+    let _span = span.as_deref().map(Entered::new);
+    hooks::run_installed(&CompletionInfo {
+      type_id: completing.0,
+      result: handler.state().result(),
+    });
+    let _wakers = handler.state().take_wakers().wake_on_drop();
+    let state = handler.state();
+    if state.is_timed_out() && state.result().status == STATUS_CANCELLED {
+      handler.on_free();
+      handler.timed_out();
+    } else {
+      f(handler);
+    }
+  }
+
+  fn check_thread(handler: &dyn EventHandler) {
+    if let Some(expected) = handler.expected_thread() {
+      debug_assert!(
+        thread::current().id() == expected,
+        "{} completed on {:?}, but belongs to {:?}",
+        handler.type_name(),
+        thread::current().id(),
+        expected
+      );
+    }
+  }
+
+  // The wakers have to be moved out of the EventState before the handler is
+  // completed, since that normally frees the handler and the state with it.
+  pub(crate) fn take_wakers(&mut self) -> WakerSet {
+    take(&mut self.wakers)
+  }
+
+  // Whether `overlapped` belongs to an operation that is outstanding in
+  // `registry`, i.e. was dispatched through the completion port that owns it,
+  // as opposed to being owned by some foreign (e.g. C) code that shares the
+  // port. Only the address is looked up, so foreign OVERLAPPEDs are never
+  // read.
+  pub fn is_ours(overlapped: *mut OVERLAPPED, registry: &Registry) -> bool {
+    !overlapped.is_null() && registry.contains(overlapped)
+  }
+
+  // Like `complete()`, but first checks that `overlapped` belongs to an
+  // operation outstanding in `registry`, so a bogus pointer handed over by
+  // buggy foreign code (e.g. a C extension that misaligns the struct) is
+  // reported rather than dereferenced.
+  pub unsafe fn complete_checked(
+    overlapped: *mut OVERLAPPED,
+    registry: &Registry,
+  ) -> Result<(), InvalidOverlapped> {
+    let addr = overlapped as usize;
+    if overlapped.is_null() {
+      return Err(InvalidOverlapped::Null);
+    }
+    if !addr.is_multiple_of(align_of::<OVERLAPPED>()) {
+      return Err(InvalidOverlapped::Misaligned);
+    }
+    let offset = Self::member_offset();
+    if addr < LOWEST_USER_ADDRESS + offset
+      || addr > HIGHEST_USER_ADDRESS - size_of::<OVERLAPPED>()
+    {
+      return Err(InvalidOverlapped::OutOfRange);
+    }
+    if !Self::is_ours(overlapped, registry) {
+      return Err(InvalidOverlapped::Foreign);
+    }
+    Self::complete(NonNull::new_unchecked(overlapped));
+    Ok(())
+  }
+
+  // Completes every entry dequeued by GetQueuedCompletionStatusEx() from the
+  // port that owns `registry`. Entries that don't belong to an operation
+  // outstanding there (including packets without an OVERLAPPED) are passed
+  // to `foreign` instead.
+  pub unsafe fn complete_all<F>(
+    entries: &[OVERLAPPED_ENTRY],
+    registry: &Registry,
+    mut foreign: F,
+  ) where
+    F: FnMut(&OVERLAPPED_ENTRY),
+  {
+    for entry in entries {
+      match NonNull::new(entry.lpOverlapped) {
+        Some(overlapped) if Self::is_ours(entry.lpOverlapped, registry) => {
+          Self::complete(overlapped)
+        }
+        _ => foreign(entry),
+      }
+    }
+  }
+
+  // Like `complete_all()`, but interleaves the completions of different
+  // handles, so one busy handle can't hold up the others until all of its
+  // completions in the batch have run. Handles are told apart by completion
+  // key, which is what they were associated with the port with. Each round
+  // runs one completion per key, in the order the keys first appear in the
+  // batch; completions for the same key keep their order.
+  pub unsafe fn complete_all_round_robin<F>(
+    entries: &[OVERLAPPED_ENTRY],
+    registry: &Registry,
+    mut foreign: F,
+  ) where
+    F: FnMut(&OVERLAPPED_ENTRY),
+  {
+    let mut queues: Vec<(ULONG_PTR, VecDeque<&OVERLAPPED_ENTRY>)> = Vec::new();
+    for entry in entries {
+      let key = entry.lpCompletionKey;
+      match queues.iter_mut().find(|(k, _)| *k == key) {
+        Some((_, queue)) => queue.push_back(entry),
+        None => queues.push((key, VecDeque::from(vec![entry]))),
+      }
+    }
+    while !queues.is_empty() {
+      for (_, queue) in &mut queues {
+        let entry = queue.pop_front().unwrap();
+        match NonNull::new(entry.lpOverlapped) {
+          Some(overlapped) if Self::is_ours(entry.lpOverlapped, registry) => {
+            Self::complete(overlapped)
+          }
+          _ => foreign(entry),
+        }
+      }
+      queues.retain(|(_, queue)| !queue.is_empty());
+    }
+  }
+
+  // Completes a batch of operations whose handlers are all of type `T`, e.g.
+  // the ones one GetQueuedCompletionStatusEx() call returned, with a single
+  // `T::batch_complete()` call. Each handler gets what `run_complete()`
+  // would do for it: the thread check, the installed hooks, and its tasks
+  // woken afterwards. The batch runs inside the spans of all of them, and in
+  // their trace if they share one. Handlers whose timeout cancelled them are
+  // left out of the batch, and get their `timed_out()` call instead.
+  pub unsafe fn complete_batch<T>(overlappeds: &[NonNull<OVERLAPPED>])
+  where
+    T: EventHandler,
+  {
+    let mut wakers = Vec::with_capacity(overlappeds.len());
+    let mut handlers = Vec::with_capacity(overlappeds.len());
+    let mut results = Vec::with_capacity(overlappeds.len());
+    #[cfg(feature = "trace-hooks")]
+    let mut spans = Vec::new();
+    #[cfg(feature = "trace-hooks")]
+    let mut trace_ids = Vec::with_capacity(overlappeds.len());
+    for &overlapped in overlappeds {
+      let result = read_overlapped_result(overlapped.as_ptr());
+      let mut handler = match Self::undispatch::<T>(overlapped) {
+        Some(handler) => handler,
+        None => continue,
+      };
+      if handler.state().is_timed_out() && result.status == STATUS_CANCELLED {
+        Self::run_complete(handler);
+        continue;
+      }
+      Self::check_thread(&*handler);
+      if let (_, Some(upper)) = handler.io_size_hint() {
+        debug_assert!(
+          result.bytes_transferred as usize <= upper,
+          "{} transferred {} bytes, more than its io_size_hint() of {}",
+          type_name::<T>(),
+          result.bytes_transferred,
+          upper
+        );
+      }
+      #[cfg(feature = "trace-hooks")]
+      {
+        trace_ids.push(handler.state().trace_id);
+        spans.extend(handler.state().span.take());
+      }
+      hooks::run_installed(&CompletionInfo {
+        type_id: TypeId::of::<T>(),
+        result,
+      });
+      wakers.push(handler.state().take_wakers().wake_on_drop());
+      results.push(result);
+      handler.on_free();
+      handlers.push(handler);
+    }
+    if handlers.is_empty() {
+      return;
+    }
+    let completing = (TypeId::of::<T>(), type_name::<T>());
+    let _completing = CompletingGuard::enter(completing);
+    #[cfg(feature = "trace-hooks")]
+    let _trace = {
+      let first = trace_ids[0];
+      let shared = trace_ids.iter().all(|&trace_id| trace_id == first);
+      trace_context::enter(if shared { first } else { None })
+    };
+    #[cfg(feature = "trace-hooks")]
+    let mut entered: Vec<_> =
+      spans.iter().map(|span| Entered::new(&**span)).collect();
+    T::batch_complete(handlers, results);
+    // Exit the spans in the reverse order they were entered in.
+    #[cfg(feature = "trace-hooks")]
+    while entered.pop().is_some() {}
+  }
+
+  // Like `complete()`, but first runs the hook `hooks` has registered for the
+  // handler's type, if there is one, besides the installed ones.
+  pub unsafe fn complete_with_hooks(
+    overlapped: NonNull<OVERLAPPED>,
+    hooks: &CompletionHooks,
+  ) {
+    let result = read_overlapped_result(overlapped.as_ptr());
+    let handler = match Self::extract_or_report(overlapped) {
+      Some(handler) => handler,
+      None => return,
+    };
+    let type_id = (*handler).type_id();
+    hooks.run(&CompletionInfo { type_id, result });
+    Self::run_complete(handler)
+  }
+
+  // Like `complete()`, but hands the extracted handler and the result of the
+  // operation to `f` instead of calling `EventHandler::complete()`. Handy for
+  // one-off operations where the handler is just a carrier of state; `f` may
+  // downcast it or simply drop it. The tasks awaiting the operation are woken
+  // once `f` returns.
+  pub unsafe fn complete_with_callback<F>(overlapped: NonNull<OVERLAPPED>, f: F)
+  where
+    F: FnOnce(Box<dyn EventHandler>, IoResult),
+  {
+    let result = read_overlapped_result(overlapped.as_ptr());
+    let mut handler = match Self::extract_or_report(overlapped) {
+      Some(handler) => handler,
+      None => return,
+    };
+    let _wakers = handler.state().take_wakers().wake_on_drop();
+    handler.on_free();
+    f(handler, result)
+  }
+
+  // Like `complete()`, but instead of running the handler inline, hands it to
+  // `sink` so it can be completed elsewhere (e.g. on another thread). The
+  // tasks awaiting the operation are woken once it has been handed off.
+  pub unsafe fn complete_to<S>(overlapped: NonNull<OVERLAPPED>, sink: &S)
+  where
+    S: CompletionSink + ?Sized,
+  {
+    let mut handler = match Self::extract_or_report(overlapped) {
+      Some(handler) => handler,
+      None => return,
+    };
+    let _wakers = handler.state().take_wakers().wake_on_drop();
+    handler.on_free();
+    sink.post(handler)
+  }
+
+  // Like `complete_to()`, for event loops that deliver completions to a
+  // central `std::sync::mpsc` channel. If the receiving end is gone, the
+  // handler comes back inside the SendError. If the handler is missing, and
+  // the MissingHandlerPolicy lets that pass, nothing is sent.
+  pub unsafe fn complete_via_channel(
+    overlapped: NonNull<OVERLAPPED>,
+    tx: &Sender<Box<dyn EventHandler>>,
+  ) -> Result<(), SendError<Box<dyn EventHandler>>> {
+    let mut handler = match Self::extract_or_report(overlapped) {
+      Some(handler) => handler,
+      None => return Ok(()),
+    };
+    let _wakers = handler.state().take_wakers().wake_on_drop();
+    handler.on_free();
+    tx.send(handler)
+  }
+
+  // Takes the handler of a completed operation out of its EventState, to be
+  // completed later, possibly on another thread, by `OwnedCompletion::finish()`.
+  // None if the handler is missing, and the MissingHandlerPolicy lets that
+  // pass.
+  pub unsafe fn take_completion(
+    overlapped: NonNull<OVERLAPPED>,
+  ) -> Option<OwnedCompletion> {
+    let handler = Self::extract_or_report(overlapped)?;
+    Some(OwnedCompletion {
+      handler: Some(handler),
+    })
+  }
+
+  // Hands the completion to a thread pool, for handlers whose `complete()` is
+  // CPU-bound (e.g. TLS decryption), so it doesn't hold up the thread that
+  // drains the port. `spawn` submits a job to the pool, e.g. `rayon::spawn`.
+  pub unsafe fn complete_on<S>(overlapped: NonNull<OVERLAPPED>, spawn: S)
+  where
+    S: FnOnce(Box<dyn FnOnce() + Send>),
+  {
+    if let Some(completion) = Self::take_completion(overlapped) {
+      spawn(Box::new(move || completion.finish()))
+    }
+  }
+
+  // Arranges for the operation to be cancelled if it's still outstanding at
+  // `deadline` (see `TimerQueue::fire_expired()`). When the cancellation
+  // comes back, the handler's `timed_out()` is called instead of
+  // `complete()`. The timer is dropped when the operation completes, however
+  // that happens. Must be called before the OS call is made, so that can't
+  // have completed yet.
+  pub unsafe fn schedule_timeout(
+    overlapped: NonNull<OVERLAPPED>,
+    deadline: Instant,
+    timer: &TimerQueue,
+  ) -> TimerId {
+    let state = Self::from_overlapped(overlapped);
+    state.cancel_timeout();
+    let id = timer.schedule(deadline, overlapped.as_ptr());
+    state.timeout = Some((timer.clone(), id));
+    id
+  }
+
+  // Some operations finish synchronously and never post to the completion port
+  // (e.g. ReadFile on a handle opened without FILE_FLAG_OVERLAPPED), but still
+  // take an OVERLAPPED to specify the file offset. This records the result the
+  // way the kernel would have, and then completes the handler inline.
+  //
+  // A handler that re-arms itself from `complete()`, and keeps completing
+  // synchronously, would recurse here without bound. Past the depth set with
+  // `set_inline_completion_limit()`, completions are therefore queued, and
+  // the outermost `complete_sync()` call runs them before returning (or
+  // unwinding, if a handler panics).
+  pub unsafe fn complete_sync(
+    overlapped: NonNull<OVERLAPPED>,
+    bytes: u32,
+    status: NTSTATUS,
+  ) {
+    let result = IoResult {
+      status,
+      bytes_transferred: bytes,
+    };
+    write_overlapped_result(overlapped.as_ptr(), result);
+
+    let depth = INLINE_DEPTH.with(Cell::get);
+    // The outermost call always runs, since it's the one that drains the queue.
+    if depth > 0 && depth >= INLINE_COMPLETION_LIMIT.load(Ordering::Relaxed) {
+      DEFERRED_COMPLETIONS
+        .with(|queue| queue.borrow_mut().push_back(overlapped));
+      return;
+    }
+
+    let _deferred = if depth == 0 {
+      Some(DeferredCompletionsGuard)
+    } else {
+      None
+    };
+    let _depth = InlineDepthGuard::enter(depth);
+    Self::complete(overlapped);
+    if depth == 0 {
+      while let Some(overlapped) = pop_deferred_completion() {
+        Self::complete(overlapped);
+      }
+    }
+  }
+
+  // For operations that finished before the handler was ever dispatched, e.g.
+  // a ReadFile() that returned TRUE on a handle with
+  // FILE_SKIP_COMPLETION_PORT_ON_SUCCESS set: records `result` in the
+  // handler's EventState and completes it right away, without embedding and
+  // extracting it. Sync and async completions thus end up in the same
+  // `complete()`.
+  pub fn complete_immediate<T>(mut handler: Box<T>, result: IoResult)
+  where
+    T: EventHandler,
+  {
+    handler.state().set_result(result);
+    Self::run_complete(handler)
+  }
+
+  // What `complete()` and `Dispatch::failed()` do when they find no handler
+  // in the EventState. Applies to the whole process.
+  pub fn set_missing_handler_policy(policy: MissingHandlerPolicy) {
+    *MISSING_HANDLER_POLICY.lock().unwrap() = policy;
+  }
+
+  // Installs `hooks` for every completion in the process that runs the
+  // handler's `complete()`, replacing the ones installed before (if any).
+  // None uninstalls them. Hooks must not call this themselves.
+  pub fn set_completion_hooks(hooks: Option<CompletionHooks>) {
+    hooks::install(hooks)
+  }
+
+  // The maximum nesting depth of `complete_sync()` calls on one thread.
+  pub fn set_inline_completion_limit(limit: usize) {
+    INLINE_COMPLETION_LIMIT.store(limit, Ordering::Relaxed);
+  }
+}
+
+static INLINE_COMPLETION_LIMIT: AtomicUsize = AtomicUsize::new(32);
+
+static MISSING_HANDLER_POLICY: Mutex<MissingHandlerPolicy> =
+  Mutex::new(if cfg!(debug_assertions) {
+    MissingHandlerPolicy::Panic
+  } else {
+    MissingHandlerPolicy::LogAndIgnore
+  });
+
+thread_local! {
+  static INLINE_DEPTH: Cell<usize> = const { Cell::new(0) };
+  static DEFERRED_COMPLETIONS: RefCell<VecDeque<NonNull<OVERLAPPED>>> =
+    const { RefCell::new(VecDeque::new()) };
+}
+
+fn pop_deferred_completion() -> Option<NonNull<OVERLAPPED>> {
+  DEFERRED_COMPLETIONS.with(|queue| queue.borrow_mut().pop_front())
+}
+
+// Held by the outermost `complete_sync()` call. If a handler panics, the
+// operations queued up to then have still completed, and there's nobody
+// else to run their handlers, so they're run while unwinding. Panics they
+// raise in turn are dropped, since they can't be propagated.
+struct DeferredCompletionsGuard;
+
+impl Drop for DeferredCompletionsGuard {
+  fn drop(&mut self) {
+    if thread::panicking() {
+      while let Some(overlapped) = pop_deferred_completion() {
+        let _ = unsafe { EventState::complete_panic_safe(overlapped) };
+      }
+    }
+  }
+}
+
+// Puts the handler back into its EventState once `complete_reusable()` is
+// done with it, also when `complete_mut()` panics, so the operation isn't
+// stuck looking like it's being completed.
+struct ReusingGuard {
+  overlapped: NonNull<OVERLAPPED>,
+  event_handler: Option<Box<dyn EventHandler>>,
+}
+
+impl Drop for ReusingGuard {
+  fn drop(&mut self) {
+    let state = unsafe { EventState::from_overlapped(self.overlapped) };
+    state.event_handler.put(self.event_handler.take().unwrap());
+    state.reusing.store(false, Ordering::Release);
+  }
+}
+
+// Restores the inline completion depth, also when `complete()` panics.
+struct InlineDepthGuard(usize);
+
+impl InlineDepthGuard {
+  fn enter(depth: usize) -> Self {
+    INLINE_DEPTH.with(|d| d.set(depth + 1));
+    Self(depth)
+  }
+}
+
+impl Drop for InlineDepthGuard {
+  fn drop(&mut self) {
+    INLINE_DEPTH.with(|d| d.set(self.0));
+  }
 }
 
 impl Deref for EventState {
@@ -119,11 +1521,60 @@ impl DerefMut for EventState {
   }
 }
 
-pub struct Dispatch<T> {
+// EventStates have identity: two of them are only equal if they're the same
+// instance. That only means something for EventStates that stay put, so as
+// HashMap keys for per-connection state they go by reference (or in a pinned
+// box), not by value. The hash goes by address too, consistent with
+// equality, so states that were never dispatched don't all collide.
+impl PartialEq for EventState {
+  fn eq(&self, other: &Self) -> bool {
+    ptr::eq(self, other)
+  }
+}
+
+impl Eq for EventState {}
+
+impl Hash for EventState {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    (self as *const Self as usize).hash(state)
+  }
+}
+
+pub struct Dispatch<T: ?Sized> {
   overlapped: Option<NonNull<OVERLAPPED>>,
+  // The type the handler had before `into_dyn()` erased it; None otherwise.
+  erased_type_id: Option<TypeId>,
   _phantom: PhantomData<T>,
 }
 
+// What doesn't depend on the handler type, so it works for erased dispatches
+// (see `into_dyn()`) as well.
+impl<T: ?Sized> Dispatch<T> {
+  pub fn pending(mut self) {
+    // In a multi-threaded scenario, the overlapped event might complete and
+    // be picked up by another thread *before* `pending()` is called. So it is
+    // paramount never to convert `self.overlapped` back to an EventState!
+    self.overlapped.take().unwrap();
+  }
+
+  pub fn overlapped(&mut self) -> *mut OVERLAPPED {
+    self.overlapped.unwrap().as_ptr()
+  }
+
+  // False for a `Dispatch::default()` placeholder.
+  pub fn is_dispatched(&self) -> bool {
+    self.overlapped.is_some()
+  }
+
+  // Whether the guard still awaits `pending()` (or `failed()`), i.e. the
+  // handler is embedded but the operation hasn't been handed over yet. Both
+  // consume the guard, so this is only false for a placeholder; it's meant
+  // for debug assertions.
+  pub fn is_pending(&self) -> bool {
+    self.is_dispatched()
+  }
+}
+
 impl<T> Dispatch<T>
 where
   T: EventHandler,
@@ -131,51 +1582,588 @@ where
   fn new(overlapped: NonNull<OVERLAPPED>) -> Self {
     Self {
       overlapped: Some(overlapped),
+      erased_type_id: None,
       _phantom: PhantomData,
     }
   }
 
-  pub fn pending(mut self) -> () {
-    // In a multi-threaded scenario, the overlapped event might complete and
-    // be picked up by another thread *before* `pending()` is called. So it is
-    // paramount never to convert `self.overlapped` back to an EventState!
-    self.overlapped.take().unwrap();
+  // The type of the dispatched handler; for a placeholder, the type it's
+  // meant to hold.
+  pub fn type_id(&self) -> TypeId {
+    TypeId::of::<T>()
   }
 
-  pub fn failed(mut self) -> Box<T> {
+  // Panics if the handler went missing, e.g. because the operation was
+  // completed after all; after the MissingHandlerPolicy has had its say, if it
+  // doesn't panic itself. Use `try_failed()` to carry on instead.
+  pub fn failed(self) -> Box<T> {
+    self
+      .try_failed()
+      .expect("Dispatch::failed() found no event handler to hand back")
+  }
+
+  // Like `failed()`, but returns None if the handler went missing and the
+  // MissingHandlerPolicy let that pass.
+  pub fn try_failed(mut self) -> Option<Box<T>> {
     let overlapped = self.overlapped.take().unwrap();
-    unsafe { EventState::undispatch(overlapped) }
+    unsafe {
+      EventState::unregister_many(&[overlapped]);
+      EventState::reclaim(overlapped)
+    }
   }
 
-  pub fn overlapped(&mut self) -> *mut OVERLAPPED {
-    self.overlapped.unwrap().as_ptr()
+  // Like `failed()`, but passes the handler on to `f`, e.g. to put it back
+  // into a pool or dispatch it again.
+  pub fn failed_with<F>(self, f: F)
+  where
+    F: FnOnce(Box<T>),
+  {
+    f(self.failed())
+  }
+
+  // A completion packet for this operation, for building completion lists by
+  // hand (e.g. for UpdateCompletionList()).
+  pub fn overlapped_entry(
+    &mut self,
+    key: usize,
+    bytes: u32,
+  ) -> OVERLAPPED_ENTRY {
+    OVERLAPPED_ENTRY {
+      lpCompletionKey: key as ULONG_PTR,
+      lpOverlapped: self.overlapped(),
+      Internal: 0,
+      dwNumberOfBytesTransferred: bytes as DWORD,
+    }
+  }
+
+  // The embedded handler's `io_size_hint()`.
+  pub fn io_size_hint(&self) -> (usize, Option<usize>) {
+    self.as_ref().io_size_hint()
+  }
+
+  // Like `pending()`, but returns the OVERLAPPED, for handing the operation
+  // over to foreign code that takes it from here. That code promises that the
+  // operation completes eventually, and that the pointer then comes back to
+  // `EventState::complete()` (or one of its variants) exactly once. (Named
+  // this way since `into_raw()` produces a RawDispatch, which is still
+  // unsettled.)
+  pub fn into_overlapped(mut self) -> *mut OVERLAPPED {
+    self.overlapped.take().unwrap().as_ptr()
+  }
+
+  // Rebuilds the guard around an operation whose Dispatch was given up with
+  // `into_overlapped()`, for call sites that only learn whether the OS call
+  // went through in a later callback. `overlapped` must belong to a `T` that
+  // hasn't been completed, and only one guard may exist for it at a time.
+  pub unsafe fn reattach(overlapped: *mut OVERLAPPED) -> Self {
+    let overlapped = NonNull::new(overlapped).expect("null OVERLAPPED");
+    if cfg!(debug_assertions) {
+      let state = EventState::from_overlapped(overlapped);
+      let event_handler = state.event_handler.get().expect("no handler");
+      assert!(
+        (*event_handler).type_id() == TypeId::of::<T>(),
+        "reattaching a {} as a {}",
+        event_handler.type_name(),
+        type_name::<T>()
+      );
+    }
+    Self::new(overlapped)
+  }
+
+  // Cancels the operation, which must have been started already, and waits
+  // for it to come back through `port`, so the handler has been completed by
+  // the time this returns. `cancel_io` makes the actual OS call (i.e.
+  // CancelIoEx() on the handle the operation was started on). The port must be
+  // drained by the calling thread only; completions of other operations
+  // dispatched through it that arrive in the meantime are run as usual. If
+  // the operation hasn't come back within `timeout` (None waits for as long
+  // as it takes), this gives up with `AbortResult::TimedOut`.
+  pub fn abort<F>(
+    mut self,
+    port: &CompletionPort,
+    timeout: Option<Duration>,
+    cancel_io: F,
+  ) -> AbortResult
+  where
+    F: FnOnce(*mut OVERLAPPED),
+  {
+    let overlapped = self.overlapped();
+    self.pending();
+    // If the operation finished before it could be cancelled, its packet is
+    // on its way (or queued already) all the same.
+    cancel_io(overlapped);
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut foreign = Vec::new();
+    let result = loop {
+      let wait = match deadline {
+        Some(deadline) => {
+          let left = deadline.saturating_duration_since(Instant::now());
+          if left == Duration::from_secs(0) {
+            break None;
+          }
+          left.min(ABORT_POLL)
+        }
+        None => ABORT_POLL,
+      };
+      let entry = match port.get_queued_completion_status(Some(wait)) {
+        Some(entry) => entry,
+        None => continue,
+      };
+      if entry.lpOverlapped == overlapped {
+        let result = unsafe { read_overlapped_result(overlapped) };
+        unsafe { EventState::complete_entry(&entry) };
+        break Some(result);
+      }
+      if EventState::is_ours(entry.lpOverlapped, port.registry()) {
+        unsafe { EventState::complete_entry(&entry) };
+      } else {
+        foreign.push(entry);
+      }
+    };
+    // Packets that don't belong to a handler (e.g. shutdown signals) are for
+    // whoever normally drains the port.
+    for entry in foreign {
+      port.enqueue(entry);
+    }
+    match result {
+      None => AbortResult::TimedOut,
+      Some(result) if result.status == STATUS_CANCELLED => {
+        AbortResult::Cancelled
+      }
+      Some(result) => AbortResult::Completed(result),
+    }
+  }
+
+  // Gives up the guard without settling it; the caller takes over the
+  // responsibility of calling `pending()` or `failed()` in some other form.
+  pub(crate) fn disarm(mut self) -> NonNull<OVERLAPPED> {
+    self.overlapped.take().unwrap()
+  }
+
+  // Rolls back a batch of dispatches whose operations failed to start, e.g.
+  // after a partially failed submission batch.
+  pub fn fail_many(dispatches: Vec<Dispatch<T>>) -> Vec<Box<T>> {
+    let overlappeds: Vec<_> =
+      dispatches.iter().map(|d| d.overlapped.unwrap()).collect();
+    unsafe { EventState::unregister_many(&overlappeds) };
+    dispatches.into_iter().map(Dispatch::failed).collect()
+  }
+
+  // Erases the handler type, e.g. to keep dispatches of different handler
+  // types in one collection.
+  pub fn into_dyn(self) -> Dispatch<dyn EventHandler> {
+    Dispatch {
+      erased_type_id: Some(TypeId::of::<T>()),
+      overlapped: Some(self.disarm()),
+      _phantom: PhantomData,
+    }
+  }
+
+  // Converts the guard into a plain pointer that can be stored somewhere Rust
+  // ownership doesn't reach (e.g. a C array indexed by socket). It must be
+  // turned back into a Dispatch with `RawDispatch::recover()` and settled.
+  pub fn into_raw(self) -> RawDispatch {
+    RawDispatch(self.disarm().as_ptr())
+  }
+
+  // Turns a dispatch of one handler type into a dispatch of another, e.g. to
+  // wrap a low-level handler in a higher-level one. The inner handler is
+  // taken out, transformed by `f`, and then dispatched again, so this must be
+  // called before the OS operation is started. It's still the same operation
+  // though: it keeps its sequence id, and if it was dispatched through a
+  // CompletionPort, it stays registered there, without being counted again.
+  pub fn map<U, F>(self, f: F) -> Dispatch<U>
+  where
+    U: EventHandler,
+    F: FnOnce(Box<T>) -> Box<U>,
+  {
+    let overlapped = self.disarm();
+    let state = unsafe { EventState::from_overlapped(overlapped) };
+    let sequence = state.sequence;
+    // With the registry out of the way, the operation isn't counted as ended.
+    let registry = state.registry.take();
+    let record = registry
+      .as_ref()
+      .and_then(|registry| registry.remove(overlapped.as_ptr()));
+    let event_handler = state
+      .take_event_handler_with(|_| {})
+      .expect("Dispatch::map() on a dispatch whose handler went missing");
+    let mut event_handler =
+      f(EventState::downcast_event_handler::<T>(event_handler));
+
+    let registered = registry.zip(record).map(|(registry, record)| {
+      let cost = event_handler.resource_cost();
+      assert!(
+        registry.charge(cost, None),
+        "outstanding resource cost overflowed"
+      );
+      event_handler.state().registry = Some(registry.clone());
+      let record = OpRecord {
+        type_id: TypeId::of::<U>(),
+        type_name: event_handler.type_name(),
+        cost,
+        ..record
+      };
+      (registry, record)
+    });
+    let mut dispatch = event_handler.dispatch();
+    let overlapped = dispatch.overlapped();
+    if let Some((registry, record)) = registered {
+      registry.reinsert(overlapped, record);
+    }
+    let overlapped = NonNull::new(overlapped).unwrap();
+    unsafe { EventState::from_overlapped(overlapped) }.sequence = sequence;
+    dispatch
+  }
+}
+
+// A Dispatch whose handler type was erased with `into_dyn()`. Since the type
+// is unknown, a handler reclaimed with `failed_dyn()` comes back as a trait
+// object.
+impl Dispatch<dyn EventHandler> {
+  // The type the handler had before erasure. It's recorded by `into_dyn()`,
+  // so it doesn't depend on the handler still being around. A placeholder has
+  // no handler type, so it reports `dyn EventHandler` itself.
+  pub fn type_id(&self) -> TypeId {
+    self
+      .erased_type_id
+      .unwrap_or_else(TypeId::of::<dyn EventHandler>)
+  }
+
+  // Like `failed()`, this panics if the handler went missing, after the
+  // MissingHandlerPolicy has had its say; `try_failed_dyn()` carries on.
+  pub fn failed_dyn(self) -> Box<dyn EventHandler> {
+    self
+      .try_failed_dyn()
+      .expect("Dispatch::failed_dyn() found no event handler to hand back")
+  }
+
+  pub fn try_failed_dyn(mut self) -> Option<Box<dyn EventHandler>> {
+    let overlapped = self.overlapped.take().unwrap();
+    unsafe {
+      EventState::unregister_many(&[overlapped]);
+      let mut handler = EventState::extract_or_report(overlapped)?;
+      handler.on_free();
+      Some(handler)
+    }
+  }
+}
+
+// An unsettled Dispatch with its type and guard stripped off; see
+// `Dispatch::into_raw()`.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct RawDispatch(*mut OVERLAPPED);
+
+unsafe impl Send for RawDispatch {}
+
+impl RawDispatch {
+  pub fn as_ptr(&self) -> *mut OVERLAPPED {
+    self.0
+  }
+
+  // Like `Dispatch::fail_many()`, for a batch of dispatches with different
+  // handler types.
+  pub fn fail_many(dispatches: Vec<RawDispatch>) -> Vec<Box<dyn EventHandler>> {
+    let overlappeds: Vec<_> = dispatches
+      .iter()
+      .map(|d| unsafe { NonNull::new_unchecked(d.0) })
+      .collect();
+    unsafe {
+      EventState::unregister_many(&overlappeds);
+      overlappeds
+        .into_iter()
+        .map(|overlapped| {
+          let mut handler = EventState::extract_event_handler(overlapped);
+          handler.on_free();
+          handler
+        })
+        .collect()
+    }
+  }
+
+  // `T` must be the handler type of the Dispatch this came from.
+  pub unsafe fn recover<T>(self) -> Dispatch<T>
+  where
+    T: EventHandler,
+  {
+    Dispatch::new(NonNull::new_unchecked(self.0))
   }
 }
 
-impl<T> Drop for Dispatch<T> {
+// The handler can be looked at while it's being set up. Once its OVERLAPPED
+// has been passed to the OS, it may be completed and freed on another thread
+// at any moment, so this must not be used anymore from then on.
+impl<T> AsRef<T> for Dispatch<T>
+where
+  T: EventHandler,
+{
+  fn as_ref(&self) -> &T {
+    debug_assert!(self.is_pending(), "Dispatch::as_ref() on a placeholder");
+    let state =
+      unsafe { EventState::from_overlapped(self.overlapped.unwrap()) };
+    state.handler_as::<T>().unwrap()
+  }
+}
+
+// A placeholder that doesn't guard anything yet, for struct fields that are
+// initialized before the dispatch happens. It can be dropped freely, but the
+// settling methods panic on it.
+impl<T: ?Sized> Default for Dispatch<T> {
+  fn default() -> Self {
+    Self {
+      overlapped: None,
+      erased_type_id: None,
+      _phantom: PhantomData,
+    }
+  }
+}
+
+impl<T: ?Sized> Drop for Dispatch<T> {
   fn drop(&mut self) {
-    if self.overlapped.is_some() {
-      panic!("Either Dispatch::pending() or Dispatch::failed() must be called after dispatching an EventState.");
+    if let Some(overlapped) = self.overlapped.take() {
+      if thread::panicking() {
+        // We're unwinding from a panic that happened before the OS operation
+        // was started, so no completion will ever arrive. Reclaim and drop the
+        // handler instead of panicking again (which would abort the process).
+        unsafe { EventState::abandon_event_handler(overlapped) };
+      } else if let Some((type_id, type_name)) = COMPLETING.with(Cell::get) {
+        panic!("Either Dispatch::pending() or Dispatch::failed() must be called after dispatching an EventState. The unsettled Dispatch was dropped inside the complete() of {} ({:?}).", type_name, type_id);
+      } else {
+        panic!("Either Dispatch::pending() or Dispatch::failed() must be called after dispatching an EventState.");
+      }
     }
   }
 }
 
+thread_local! {
+  // The type of the handler whose `complete()` is running on this thread.
+  static COMPLETING: Cell<Option<(TypeId, &'static str)>> =
+    const { Cell::new(None) };
+}
+
+struct CompletingGuard(Option<(TypeId, &'static str)>);
+
+impl CompletingGuard {
+  fn enter(completing: (TypeId, &'static str)) -> Self {
+    Self(COMPLETING.with(|c| c.replace(Some(completing))))
+  }
+}
+
+impl Drop for CompletingGuard {
+  fn drop(&mut self) {
+    COMPLETING.with(|c| c.set(self.0));
+  }
+}
+
 // IOCP 'plug-ins' like wepoll, mio_named_pipes, etc... implement this trait.
 pub trait EventHandler
 where
   Self: Any + Send + 'static,
 {
   fn state(&mut self) -> &mut EventState;
-  fn complete(self: Box<Self>) -> ();
+
+  // By the time this is called, the handler owns its EventState again, and
+  // the OVERLAPPED in it is still intact. So the handler can read the result
+  // and any other fields it set up (e.g. the file offset) from `state()`.
+  fn complete(self: Box<Self>);
+
+  // Called instead of `complete()` when the operation was cancelled because
+  // a deadline set with `EventState::schedule_timeout()` passed. The result
+  // is STATUS_CANCELLED, which `EventState::read_completion()` reports as
+  // `CancelReason::TimedOut`. By default the handler is simply completed.
+  fn timed_out(self: Box<Self>) {
+    self.complete()
+  }
+
+  // Completes a batch of handlers of the same type at once; see
+  // `EventState::complete_batch()`. `results` holds the outcome of each
+  // handler's operation, in the same order. By default every handler is
+  // completed on its own, but types that can process their completions as a
+  // group (e.g. to coalesce vectored writes) can do better.
+  fn batch_complete(handlers: Vec<Box<Self>>, results: Vec<IoResult>)
+  where
+    Self: Sized,
+  {
+    assert_eq!(handlers.len(), results.len());
+    for (mut handler, result) in handlers.into_iter().zip(results) {
+      handler.state().set_result(result);
+      handler.complete()
+    }
+  }
+
+  // Called by `CompletionPort::associate()` when the handle this handler
+  // performs I/O on is associated with a port, so it can hold on to the
+  // completion key.
+  fn on_completion_port_associate(&mut self, _key: usize) {}
+
+  // Uniform teardown point, lighter than a stored closure. Called once per
+  // dispatch, when the handler is done with the operation: after
+  // `complete_in_place()` returns, right before the handler is dropped;
+  // right before `complete()` or `timed_out()`, which take the handler with
+  // them (or before it's handed off to be completed elsewhere); when it's
+  // handed back by `Dispatch::failed()` or `detach_event_handler()`; and when
+  // it's abandoned: dropped without ever being completed, e.g. when a
+  // Dispatch is dropped during a panic.
+  fn on_free(&mut self) {}
+
+  // For event loops with thread affinity, where a connection's completions
+  // must always run on the same thread: that thread. Completing the handler
+  // anywhere else panics in debug builds.
+  fn expected_thread(&self) -> Option<ThreadId> {
+    None
+  }
+
+  // Handlers that implement ReusableEventHandler return `Some(self)` here.
+  fn as_reusable(&mut self) -> Option<&mut dyn ReusableEventHandler> {
+    None
+  }
+
+  // Handlers that implement InPlaceEventHandler return `Some(self)` here.
+  fn as_in_place(&mut self) -> Option<&mut dyn InPlaceEventHandler> {
+    None
+  }
+
+  // Called instead of `complete()` by `EventState::complete_entry()`, with
+  // the packet the operation completed with. Handlers that implement
+  // DecodingEventHandler forward this to `decode_and_complete()`.
+  fn decode_dyn(self: Box<Self>, _raw: &OVERLAPPED_ENTRY) {
+    self.complete()
+  }
+
+  // For diagnostics.
+  fn type_name(&self) -> &'static str {
+    type_name::<Self>()
+  }
+
+  // Number of bytes tied up while this handler is outstanding. Handlers that
+  // own buffers beyond their own size should add those in, so memory
+  // accounting and admission control can budget by bytes rather than count
+  // (see `CompletionPort::try_dispatch()`).
+  fn resource_cost(&self) -> usize {
+    size_of_val(self)
+  }
+
+  // Expected size of the I/O result, if the protocol can predict it (e.g. a
+  // fixed-size header read). Recorded in the EventState at dispatch time so
+  // result buffers can be allocated up front. Zero means "unknown".
+  fn size_hint(&self) -> usize {
+    0
+  }
+
+  // Bounds on the number of bytes the operation is expected to transfer, like
+  // `Iterator::size_hint()`: a lower bound, and an upper bound if there is
+  // one. For adapters that can predict it, e.g. vectored reads. In debug
+  // builds `EventState::complete_batch()` checks that the upper bound held.
+  fn io_size_hint(&self) -> (usize, Option<usize>) {
+    (0, None)
+  }
+
+  // The buffer the OS should read into or write from, for handlers that own
+  // one. Helpers like `dispatch_read()` use this to issue the OS call
+  // themselves. The buffer must stay put until the operation completes.
+  fn io_buffer(&mut self) -> Option<(*mut u8, usize)> {
+    None
+  }
+}
+
+// For handlers that read straight into a buffer they don't own, e.g. one
+// supplied by the caller, so the data doesn't have to be copied. They report
+// that buffer through `EventHandler::io_buffer()`, and it must stay valid
+// until they're completed. The usual completion path then calls
+// `complete_in_place()` with it instead of `complete()`, and drops the handler
+// afterwards. `EventState::complete_in_place()` passes a buffer of its own.
+pub trait InPlaceEventHandler: EventHandler {
+  fn complete_in_place(&mut self, buf: &mut [u8], bytes: u32);
+}
+
+// For handlers whose protocol wants a result of its own type rather than an
+// IoResult, decoded from the raw completion packet. Since `Completion` differs
+// per handler, the completion path can't call these methods through a
+// `dyn EventHandler`; implementors also override `EventHandler::decode_dyn()`
+// to call `decode_and_complete()`, which can.
+pub trait DecodingEventHandler: EventHandler {
+  type Completion;
+  fn decode(raw: &OVERLAPPED_ENTRY) -> Self::Completion;
+  fn complete_with(self: Box<Self>, completion: Self::Completion);
+}
+
+pub fn decode_and_complete<T>(handler: Box<T>, raw: &OVERLAPPED_ENTRY)
+where
+  T: DecodingEventHandler,
+{
+  let completion = T::decode(raw);
+  handler.complete_with(completion)
+}
+
+// For handlers that are completed without giving up their allocation; see
+// `EventState::complete_reusable()`.
+pub trait ReusableEventHandler: EventHandler {
+  fn complete_mut(&mut self);
+}
+
+// Implements EventHandler for a struct, generating the `state()` accessor for
+// the EventState field named in braces. The remaining trait items (at least
+// `complete()`) go in the block after the arrow, e.g.
+// `event_handler! { AfdPoll { state } => { fn complete(self: Box<Self>) {} } }`.
+// Naming a field that doesn't exist, or isn't an EventState, doesn't compile.
+#[macro_export]
+macro_rules! event_handler {
+  ($ty:ty { $state:ident } => { $($body:tt)* }) => {
+    impl $crate::iocp::EventHandler for $ty {
+      fn state(&mut self) -> &mut $crate::iocp::EventState {
+        // Spelled out, so that pointing the macro at a field that is not an
+        // EventState produces a clear type error.
+        let state: &mut $crate::iocp::EventState = &mut self.$state;
+        state
+      }
+
+      $($body)*
+    }
+  };
+}
+
+// Receives completed event handlers, for event loops that route completions
+// through e.g. a channel instead of calling `EventHandler::complete()` on the
+// thread that dequeued them. Whoever ends up owning the handler is expected to
+// call `complete()` on it eventually.
+pub trait CompletionSink {
+  fn post(&self, event_handler: Box<dyn EventHandler>);
+}
+
+// Any function that takes the handler is a sink, e.g. a closure that sends it
+// down a channel: `|handler| tx.send(handler).unwrap()`.
+impl<F> CompletionSink for F
+where
+  F: Fn(Box<dyn EventHandler>),
+{
+  fn post(&self, event_handler: Box<dyn EventHandler>) {
+    self(event_handler)
+  }
+}
+
+// For the std channels, which are multi-producer. If the receiving end is
+// gone, the handler is dropped without being completed; use
+// `EventState::complete_via_channel()` to get it back instead.
+impl CompletionSink for Sender<Box<dyn EventHandler>> {
+  fn post(&self, event_handler: Box<dyn EventHandler>) {
+    let _ = self.send(event_handler);
+  }
+}
+
+impl CompletionSink for SyncSender<Box<dyn EventHandler>> {
+  fn post(&self, event_handler: Box<dyn EventHandler>) {
+    let _ = self.send(event_handler);
+  }
 }
 
 // Helper trait that allows the user to call `dispatch()` on any object that
 // implements EventHandler.
-pub trait EventDispatch<T> {
+pub trait Dispatchable<T> {
   #[must_use]
   fn dispatch(self: Box<Self>) -> Dispatch<T>;
 }
-impl<T> EventDispatch<T> for T
+impl<T> Dispatchable<T> for T
 where
   T: EventHandler,
 {
@@ -183,3 +2171,78 @@ where
     EventState::dispatch(self)
   }
 }
+
+// The name this trait went by before 0.2. A re-export rather than a subtrait,
+// so importing it still brings `dispatch()` into scope. (rustc doesn't warn
+// about deprecated re-exports yet; the attribute shows up in the docs.)
+#[deprecated(since = "0.2.0", note = "renamed to `Dispatchable`")]
+pub use self::Dispatchable as EventDispatch;
+
+// Dispatches `event_handler` and starts a read into the buffer it reports via
+// `io_buffer()`. The `read` callback makes the actual OS call (e.g. ReadFile on
+// some handle), and returns whether it was started successfully. If it wasn't,
+// the handler is handed back.
+pub fn dispatch_read<T, F>(
+  mut event_handler: Box<T>,
+  read: F,
+) -> Result<(), Box<T>>
+where
+  T: EventHandler,
+  F: FnOnce(*mut u8, usize, *mut OVERLAPPED) -> bool,
+{
+  let (buf, len) = event_handler
+    .io_buffer()
+    .expect("dispatch_read() requires a handler that provides an io_buffer()");
+  let mut dispatch = event_handler.dispatch();
+  if read(buf, len, dispatch.overlapped()) {
+    dispatch.pending();
+    Ok(())
+  } else {
+    Err(dispatch.failed())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  struct Nop {
+    state: EventState,
+    // Varies the size of the allocation, so handlers land at all kinds of
+    // addresses and alignments in the heap.
+    _padding: Vec<u8>,
+  }
+
+  impl EventHandler for Nop {
+    fn state(&mut self) -> &mut EventState {
+      &mut self.state
+    }
+
+    fn complete(self: Box<Self>) {}
+  }
+
+  // Meant to be run under Miri too (`cargo miri test round_trip`), which
+  // checks the pointer arithmetic as well as the results.
+  #[test]
+  fn every_handler_round_trips_through_its_overlapped() {
+    let mut dispatches: Vec<_> = (0..1000)
+      .map(|i| {
+        Box::new(Nop {
+          state: EventState::new(),
+          _padding: vec![0; i % 97],
+        })
+        .dispatch()
+      })
+      .collect();
+    for dispatch in &mut dispatches {
+      let overlapped = dispatch.overlapped();
+      let state = unsafe { EventState::container_of_mut(&mut *overlapped) };
+      assert!(state.round_trips());
+      assert_eq!(state.as_overlapped().as_ptr(), overlapped);
+    }
+    for dispatch in dispatches {
+      let mut handler = dispatch.failed();
+      assert!(handler.state().round_trips());
+    }
+  }
+}
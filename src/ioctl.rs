@@ -0,0 +1,79 @@
+use std::mem::size_of_val;
+
+use crate::iocp::{EventHandler, EventState, IoResult};
+
+type IoctlCallback = Box<dyn FnOnce(IoResult, &[u8]) + Send>;
+
+// Event handler for overlapped DeviceIoControl() calls, such as the
+// IOCTL_AFD_POLL request that AFD polling is built on. It owns the input and
+// output buffers, which must stay put until the operation completes; since
+// the handler is boxed and the buffers are never resized, they do.
+pub struct IoctlState {
+  state: EventState,
+  control_code: u32,
+  input: Box<[u8]>,
+  output: Box<[u8]>,
+  on_complete: Option<IoctlCallback>,
+}
+
+impl IoctlState {
+  // `on_complete` receives the result and the part of the output buffer the
+  // driver filled in.
+  pub fn new<F>(
+    control_code: u32,
+    input: Vec<u8>,
+    output_len: usize,
+    on_complete: F,
+  ) -> Box<Self>
+  where
+    F: FnOnce(IoResult, &[u8]) + Send + 'static,
+  {
+    Box::new(Self {
+      state: EventState::new(),
+      control_code,
+      input: input.into_boxed_slice(),
+      output: vec![0; output_len].into_boxed_slice(),
+      on_complete: Some(Box::new(on_complete)),
+    })
+  }
+
+  pub fn control_code(&self) -> u32 {
+    self.control_code
+  }
+
+  // The lpInBuffer and nInBufferSize arguments for DeviceIoControl().
+  pub fn in_buffer(&mut self) -> (*mut u8, u32) {
+    (self.input.as_mut_ptr(), self.input.len() as u32)
+  }
+
+  // The lpOutBuffer and nOutBufferSize arguments for DeviceIoControl().
+  pub fn out_buffer(&mut self) -> (*mut u8, u32) {
+    (self.output.as_mut_ptr(), self.output.len() as u32)
+  }
+
+  // The part of the output buffer that was filled in. Only meaningful once
+  // the operation has completed.
+  pub fn output(&self) -> &[u8] {
+    let len = self.state.result().bytes_transferred as usize;
+    &self.output[..len.min(self.output.len())]
+  }
+}
+
+impl EventHandler for IoctlState {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(mut self: Box<Self>) {
+    let on_complete = self.on_complete.take().unwrap();
+    on_complete(self.state.result(), self.output())
+  }
+
+  fn resource_cost(&self) -> usize {
+    size_of_val(self) + self.input.len() + self.output.len()
+  }
+
+  fn io_buffer(&mut self) -> Option<(*mut u8, usize)> {
+    Some((self.output.as_mut_ptr(), self.output.len()))
+  }
+}
@@ -0,0 +1,39 @@
+// The safety requirements of unsafe functions are spelled out in the plain
+// comments above them, like everything else.
+#![allow(clippy::missing_safety_doc)]
+
+pub mod apc;
+pub mod arc_dispatch;
+pub mod buf_array;
+pub mod completion_kind;
+pub mod completion_port;
+pub mod completion_queue;
+pub mod container_of;
+pub mod dispatch_batch;
+mod handler_slot;
+pub mod hooks;
+pub mod iocp;
+pub mod ioctl;
+pub mod map_dispatcher;
+pub mod non_send;
+pub mod pipe_connect;
+pub mod poll_registration;
+pub mod pool;
+pub mod raw;
+pub mod registered_buf;
+pub mod registry;
+pub mod result_cache;
+pub mod slab;
+pub mod socket_connect;
+pub mod submission_queue;
+pub mod timer_queue;
+#[cfg(feature = "trace-hooks")]
+pub mod trace_context;
+pub mod transmit_file;
+pub mod vtable;
+pub mod waker_set;
+pub mod winapi;
+
+pub use crate::iocp::{
+  Dispatch, Dispatchable, EventHandler, EventState, IoResult,
+};
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::default::Default;
+
+use crate::iocp::{read_overlapped_result, EventHandler, EventState};
+use crate::winapi::OVERLAPPED;
+
+// Alternative to embedding handlers in their own OVERLAPPED, for integrators
+// whose OVERLAPPEDs are allocated by foreign code. Handlers are kept in a side
+// table keyed by the OVERLAPPED's address instead, at the cost of a hash
+// lookup per completion. Handlers still embed an EventState; the result of
+// the foreign OVERLAPPED is copied into it before `complete()` is called.
+#[derive(Default)]
+pub struct MapDispatcher {
+  handlers: HashMap<usize, Box<dyn EventHandler>>,
+}
+
+impl MapDispatcher {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn len(&self) -> usize {
+    self.handlers.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.handlers.is_empty()
+  }
+
+  // Associates `event_handler` with the operation that uses `overlapped`.
+  // Panics if another handler is registered for it already.
+  pub fn register(
+    &mut self,
+    overlapped: *mut OVERLAPPED,
+    event_handler: Box<dyn EventHandler>,
+  ) {
+    let previous = self.handlers.insert(overlapped as usize, event_handler);
+    assert!(previous.is_none());
+  }
+
+  // Takes back the handler, e.g. because the operation failed to start.
+  pub fn unregister(
+    &mut self,
+    overlapped: *mut OVERLAPPED,
+  ) -> Option<Box<dyn EventHandler>> {
+    self.handlers.remove(&(overlapped as usize))
+  }
+
+  // Completes the handler registered for `overlapped`, the way
+  // `EventState::complete()` does for embedded ones (hooks, wakers and all).
+  // Returns false if there isn't one, so the caller can deal with the stray
+  // completion.
+  pub unsafe fn complete_by_ptr(
+    &mut self,
+    overlapped: *mut OVERLAPPED,
+  ) -> bool {
+    let mut handler = match self.unregister(overlapped) {
+      Some(handler) => handler,
+      None => return false,
+    };
+    let result = read_overlapped_result(overlapped);
+    handler.state().set_result(result);
+    EventState::run_complete(handler);
+    true
+  }
+}
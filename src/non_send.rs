@@ -0,0 +1,55 @@
+use std::any::Any;
+use std::thread::{self, ThreadId};
+
+use crate::iocp::{EventHandler, EventState};
+
+// Like EventHandler, but without the Send requirement. For single-threaded
+// runtimes (e.g. a GUI app that runs completions on its UI thread). Such
+// handlers are dispatched with `EventState::try_embed_non_send()`.
+pub trait LocalEventHandler
+where
+  Self: Any + 'static,
+{
+  fn state(&mut self) -> &mut EventState;
+  fn complete(self: Box<Self>);
+}
+
+// Smuggles a value that isn't Send into a place that requires it. Only sound
+// if the value never actually leaves the thread it was created on, which is
+// remembered, so completing it anywhere else is caught in debug builds.
+pub struct NonSend<T> {
+  value: T,
+  thread: ThreadId,
+}
+
+unsafe impl<T> Send for NonSend<T> {}
+
+impl<T> NonSend<T> {
+  pub unsafe fn new(value: T) -> Self {
+    Self {
+      value,
+      thread: thread::current().id(),
+    }
+  }
+
+  pub fn into_inner(self) -> T {
+    self.value
+  }
+}
+
+impl<T> EventHandler for NonSend<Box<T>>
+where
+  T: LocalEventHandler,
+{
+  fn state(&mut self) -> &mut EventState {
+    self.value.state()
+  }
+
+  fn complete(self: Box<Self>) {
+    self.value.complete()
+  }
+
+  fn expected_thread(&self) -> Option<ThreadId> {
+    Some(self.thread)
+  }
+}
@@ -0,0 +1,77 @@
+use std::ptr::NonNull;
+
+use crate::iocp::{Dispatchable, EventHandler, EventState, IoResult};
+use crate::raw::{from_handle, to_handle, RawHandle};
+use crate::winapi::{
+  DWORD, ERROR_IO_PENDING, ERROR_PIPE_CONNECTED, OVERLAPPED, STATUS_SUCCESS,
+};
+
+type PipeConnectCallback = Box<dyn FnOnce(IoResult, RawHandle) + Send>;
+
+// Event handler for overlapped ConnectNamedPipe() calls, with which the
+// server end of a named pipe waits for a client to attach.
+pub struct PipeConnectState {
+  state: EventState,
+  // A RawHandle isn't Send on every platform, so it's stored as an address.
+  pipe: usize,
+  on_complete: Option<PipeConnectCallback>,
+}
+
+impl PipeConnectState {
+  // `on_complete` receives the result and the pipe handle.
+  pub fn new<F>(pipe: RawHandle, on_complete: F) -> Box<Self>
+  where
+    F: FnOnce(IoResult, RawHandle) + Send + 'static,
+  {
+    Box::new(Self {
+      state: EventState::new(),
+      pipe: to_handle(pipe) as usize,
+      on_complete: Some(Box::new(on_complete)),
+    })
+  }
+
+  pub fn pipe(&self) -> RawHandle {
+    from_handle(self.pipe as _)
+  }
+
+  // Dispatches the handler and starts waiting for a client. The `connect`
+  // callback makes the actual ConnectNamedPipe() call and returns the error
+  // it failed with, as reported by GetLastError(); Ok(()) if it returned
+  // TRUE. ERROR_IO_PENDING means a completion packet will follow. But if a
+  // client connected between CreateNamedPipe() and ConnectNamedPipe(), it
+  // fails with ERROR_PIPE_CONNECTED, and no packet is coming: the handler is
+  // completed right here instead. On any other error, the handler is handed
+  // back.
+  pub fn connect<F>(self: Box<Self>, connect: F) -> Result<(), Box<Self>>
+  where
+    F: FnOnce(RawHandle, *mut OVERLAPPED) -> Result<(), DWORD>,
+  {
+    let pipe = self.pipe();
+    let mut dispatch = self.dispatch();
+    let overlapped = dispatch.overlapped();
+    match connect(pipe, overlapped) {
+      Ok(()) | Err(ERROR_IO_PENDING) => {
+        dispatch.pending();
+        Ok(())
+      }
+      Err(ERROR_PIPE_CONNECTED) => {
+        dispatch.pending();
+        let overlapped = NonNull::new(overlapped).unwrap();
+        unsafe { EventState::complete_sync(overlapped, 0, STATUS_SUCCESS) };
+        Ok(())
+      }
+      Err(_) => Err(dispatch.failed()),
+    }
+  }
+}
+
+impl EventHandler for PipeConnectState {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(mut self: Box<Self>) {
+    let on_complete = self.on_complete.take().unwrap();
+    on_complete(self.state.result(), self.pipe())
+  }
+}
@@ -0,0 +1,256 @@
+use std::mem::take;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::iocp::{Dispatchable, EventHandler, EventState, IoResult};
+use crate::raw::RawSocket;
+use crate::winapi::{DWORD, ERROR_IO_PENDING, OVERLAPPED, STATUS_CANCELLED};
+
+type PollIssuer = Box<
+  dyn Fn(RawSocket, u32, *mut u32, *mut OVERLAPPED) -> Result<(), DWORD>
+    + Send
+    + Sync,
+>;
+type PollCanceller = Box<dyn Fn(*mut OVERLAPPED) + Send + Sync>;
+type PollCallback = Box<dyn Fn(IoResult, u32) + Send + Sync>;
+
+// A long-lived poll registration on a socket, for wepoll-style reactors built
+// on IOCTL_AFD_POLL. An AFD poll can't be changed once it's issued, so when
+// the set of interesting events changes, the outstanding poll is cancelled
+// and a new one is issued with the new bits. Polls are one-shot: after one
+// reports, `rearm()` issues the next.
+//
+// The OS calls are made by closures given to `new()`:
+// - `issue` starts a poll for the given event bits with the OVERLAPPED, and
+//   returns the error it failed with, or Ok(()) if it succeeded right away.
+//   The events the poll reports must be written to the `*mut u32`, which
+//   stays valid until the poll completes.
+// - `cancel` cancels the poll with the given OVERLAPPED (i.e. CancelIoEx()).
+// - `on_event` receives the result and the reported events, masked by the
+//   current interests.
+//
+// A poll that's cancelled because the interests changed is superseded: its
+// completion is ignored, unless it had reported events before the cancel
+// took effect. Those are still passed on, as far as they're of interest.
+// Clones refer to the same registration.
+//
+// The closures are called without any lock held, so a poll may complete
+// inline, and `on_event` may call back into the registration.
+#[derive(Clone)]
+pub struct PollRegistration {
+  shared: Arc<Shared>,
+}
+
+struct Shared {
+  socket: RawSocket,
+  issue: PollIssuer,
+  cancel: PollCanceller,
+  on_event: PollCallback,
+  state: Mutex<PollState>,
+}
+
+struct PollState {
+  interests: u32,
+  // Bumped whenever the interests change, so superseded polls can be told
+  // apart from the current one.
+  generation: u64,
+  // The generation and OVERLAPPED (as an address) of the current poll.
+  outstanding: Option<(u64, usize)>,
+  // The generations of polls whose OVERLAPPED is being passed to `issue` or
+  // `cancel` with the lock released. Should one of those complete meanwhile,
+  // it's parked in `retired` instead of being freed, so the OVERLAPPED stays
+  // valid until the call returns.
+  in_use: Vec<u64>,
+  // Boxed, since it's the address that has to stay put.
+  #[allow(clippy::vec_box)]
+  retired: Vec<Box<PollOp>>,
+}
+
+struct PollOp {
+  state: EventState,
+  shared: Arc<Shared>,
+  generation: u64,
+  events: u32,
+}
+
+impl PollRegistration {
+  pub fn new<I, C, E>(
+    socket: RawSocket,
+    issue: I,
+    cancel: C,
+    on_event: E,
+  ) -> Self
+  where
+    I: Fn(RawSocket, u32, *mut u32, *mut OVERLAPPED) -> Result<(), DWORD>
+      + Send
+      + Sync
+      + 'static,
+    C: Fn(*mut OVERLAPPED) + Send + Sync + 'static,
+    E: Fn(IoResult, u32) + Send + Sync + 'static,
+  {
+    Self {
+      shared: Arc::new(Shared {
+        socket,
+        issue: Box::new(issue),
+        cancel: Box::new(cancel),
+        on_event: Box::new(on_event),
+        state: Mutex::new(PollState {
+          interests: 0,
+          generation: 0,
+          outstanding: None,
+          in_use: Vec::new(),
+          retired: Vec::new(),
+        }),
+      }),
+    }
+  }
+
+  pub fn socket(&self) -> RawSocket {
+    self.shared.socket
+  }
+
+  pub fn interests(&self) -> u32 {
+    self.shared.state.lock().unwrap().interests
+  }
+
+  pub fn is_outstanding(&self) -> bool {
+    self.shared.state.lock().unwrap().outstanding.is_some()
+  }
+
+  // Switches to polling for `interests`. The outstanding poll, if any, is
+  // cancelled, and unless `interests` is zero, a new one is issued. Does
+  // nothing if a poll for the same interests is outstanding already.
+  pub fn update_interest(&self, interests: u32) -> Result<(), DWORD> {
+    let mut state = self.shared.state.lock().unwrap();
+    if interests == state.interests && state.outstanding.is_some() {
+      return Ok(());
+    }
+    state.interests = interests;
+    state.generation += 1;
+    if let Some((generation, overlapped)) = state.outstanding.take() {
+      state.in_use.push(generation);
+      drop(state);
+      (self.shared.cancel)(overlapped as *mut OVERLAPPED);
+      state = self.shared.state.lock().unwrap();
+      state.release(generation);
+    }
+    self.shared.arm(state)
+  }
+
+  // Issues the next poll after one has reported, with the same interests.
+  // Does nothing if a poll is outstanding already.
+  pub fn rearm(&self) -> Result<(), DWORD> {
+    self.shared.arm(self.shared.state.lock().unwrap())
+  }
+
+  // Stops polling, e.g. before the socket is closed.
+  pub fn deregister(&self) {
+    self.update_interest(0).unwrap()
+  }
+}
+
+impl Shared {
+  // Issues a poll for the current interests, unless there's one outstanding
+  // already. (Changing the interests takes the outstanding poll away.)
+  fn arm(
+    self: &Arc<Self>,
+    mut state: MutexGuard<PollState>,
+  ) -> Result<(), DWORD> {
+    if state.interests == 0 || state.outstanding.is_some() {
+      return Ok(());
+    }
+    let generation = state.generation;
+    let interests = state.interests;
+    let mut op = Box::new(PollOp {
+      state: EventState::new(),
+      shared: self.clone(),
+      generation,
+      events: 0,
+    });
+    let events: *mut u32 = &mut op.events;
+    let mut dispatch = op.dispatch();
+    let overlapped = dispatch.overlapped();
+    state.outstanding = Some((generation, overlapped as usize));
+    state.in_use.push(generation);
+    drop(state);
+
+    let issued = (self.issue)(self.socket, interests, events, overlapped);
+    let mut state = self.state.lock().unwrap();
+    match issued {
+      Ok(()) | Err(ERROR_IO_PENDING) => {
+        dispatch.pending();
+        // If the interests changed while the poll was being issued, the
+        // cancel may have come too early to take effect. Unless the poll is
+        // done already, cancel it again.
+        let done = state.retired.iter().any(|op| op.generation == generation);
+        if state.generation != generation && !done {
+          drop(state);
+          (self.cancel)(overlapped);
+          state = self.state.lock().unwrap();
+        }
+        state.release(generation);
+        Ok(())
+      }
+      Err(error) => {
+        if state.outstanding == Some((generation, overlapped as usize)) {
+          state.outstanding = None;
+        }
+        // The poll never started, but its OVERLAPPED may still be in the
+        // hands of a concurrent `cancel`.
+        let op = dispatch.failed();
+        if state.in_use.iter().filter(|&&g| g == generation).count() > 1 {
+          state.retired.push(op);
+        }
+        state.release(generation);
+        Err(error)
+      }
+    }
+  }
+}
+
+impl PollState {
+  // Done passing the OVERLAPPED of the poll of `generation` on. If that was
+  // the last such call and the poll has completed meanwhile, it's freed now.
+  fn release(&mut self, generation: u64) {
+    let index = self.in_use.iter().position(|&g| g == generation).unwrap();
+    self.in_use.swap_remove(index);
+    if !self.in_use.contains(&generation) {
+      let retired = take(&mut self.retired);
+      self.retired = retired
+        .into_iter()
+        .filter(|op| op.generation != generation)
+        .collect();
+    }
+  }
+}
+
+impl EventHandler for PollOp {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {
+    let result = self.state.result();
+    let (current, events) = {
+      let mut state = self.shared.state.lock().unwrap();
+      let current = matches!(
+        state.outstanding,
+        Some((generation, _)) if generation == self.generation
+      );
+      if current {
+        state.outstanding = None;
+      }
+      (current, self.events & state.interests)
+    };
+    // A superseded poll is normally cancelled as intended. Only if it beat
+    // the cancel, and reported something that's still of interest, is that
+    // passed on.
+    if current || (result.status != STATUS_CANCELLED && events != 0) {
+      (self.shared.on_event)(result, events)
+    }
+    let shared = self.shared.clone();
+    let mut state = shared.state.lock().unwrap();
+    if state.in_use.contains(&self.generation) {
+      state.retired.push(self);
+    }
+  }
+}
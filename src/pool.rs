@@ -0,0 +1,242 @@
+use std::any::{type_name, Any};
+use std::mem::take;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use crate::iocp::{Dispatch, Dispatchable, EventHandler, EventState};
+use crate::winapi::OVERLAPPED;
+
+// Like EventHandler, but for handlers that are recycled through a HandlerPool
+// instead of being dropped after every operation, which saves an allocation
+// per I/O for high-frequency short-lived operations (e.g. UDP datagrams).
+// `complete()` borrows the handler; afterwards `reset()` clears whatever
+// per-operation state it holds, and it goes back into the pool.
+pub trait PoolableEventHandler
+where
+  Self: Any + Send + 'static,
+{
+  fn state(&mut self) -> &mut EventState;
+  fn complete(&mut self);
+  fn reset(&mut self);
+}
+
+// A dispatched handler that returns to its pool when it completes.
+pub type PooledDispatch<T> = Dispatch<Pooled<T>>;
+
+// A handler together with the pool it returns to. This is what is actually
+// dispatched; it derefs to the handler. The pool is referenced weakly, since
+// it owns the handlers in its free list; if it's gone by the time the handler
+// completes, the handler is simply dropped.
+pub struct Pooled<T> {
+  handler: T,
+  pool: Weak<Inner<T>>,
+  // Whether the handler counts towards the pool's `outstanding()`.
+  outstanding: bool,
+}
+
+impl<T> Pooled<T> {
+  pub fn pool(&self) -> Option<HandlerPool<T>> {
+    self.pool.upgrade().map(|inner| HandlerPool { inner })
+  }
+
+  // Stops counting the handler as outstanding. Idempotent, so whichever way
+  // the handler leaves (completion, recycling, or being dropped), it's only
+  // subtracted once.
+  fn retire(&mut self) {
+    if !take(&mut self.outstanding) {
+      return;
+    }
+    if let Some(inner) = self.pool.upgrade() {
+      inner.outstanding.fetch_sub(1, Ordering::AcqRel);
+    }
+  }
+}
+
+impl<T> Drop for Pooled<T> {
+  fn drop(&mut self) {
+    self.retire();
+  }
+}
+
+impl<T> Deref for Pooled<T> {
+  type Target = T;
+  fn deref(&self) -> &T {
+    &self.handler
+  }
+}
+
+impl<T> DerefMut for Pooled<T> {
+  fn deref_mut(&mut self) -> &mut T {
+    &mut self.handler
+  }
+}
+
+impl<T> EventHandler for Pooled<T>
+where
+  T: PoolableEventHandler,
+{
+  fn state(&mut self) -> &mut EventState {
+    self.handler.state()
+  }
+
+  fn complete(mut self: Box<Self>) {
+    self.handler.complete();
+    self.retire();
+    if let Some(pool) = self.pool() {
+      pool.recycle(self);
+    }
+  }
+
+  fn type_name(&self) -> &'static str {
+    type_name::<T>()
+  }
+}
+
+// A bounded free list of handlers. Handlers beyond `capacity` are dropped
+// rather than recycled, so a burst of operations doesn't pin its memory
+// forever. Cloning the pool yields another reference to the same free list.
+// The crate has no dependencies, so the free list is a Mutex<Vec> rather than
+// a lock-free queue like crossbeam's ArrayQueue; the lock is only held for a
+// single push or pop, never while a handler runs.
+pub struct HandlerPool<T> {
+  inner: Arc<Inner<T>>,
+}
+
+struct Inner<T> {
+  free: Mutex<Vec<Box<Pooled<T>>>>,
+  capacity: usize,
+  outstanding: AtomicUsize,
+}
+
+impl<T> Clone for HandlerPool<T> {
+  fn clone(&self) -> Self {
+    Self {
+      inner: self.inner.clone(),
+    }
+  }
+}
+
+impl<T> HandlerPool<T>
+where
+  T: PoolableEventHandler,
+{
+  pub fn with_capacity(capacity: usize) -> Self {
+    Self {
+      inner: Arc::new(Inner {
+        free: Mutex::new(Vec::with_capacity(capacity)),
+        capacity,
+        outstanding: AtomicUsize::new(0),
+      }),
+    }
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.inner.capacity
+  }
+
+  // The number of handlers waiting to be reused.
+  pub fn len(&self) -> usize {
+    self.inner.free.lock().unwrap().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  // The number of handlers dispatched with `dispatch()` whose operations
+  // haven't ended yet.
+  pub fn outstanding(&self) -> usize {
+    self.inner.outstanding.load(Ordering::Acquire)
+  }
+
+  // Dispatches a handler from this pool, counting it as outstanding until it
+  // completes or comes back to the pool some other way.
+  pub fn dispatch(&self, mut handler: Box<Pooled<T>>) -> PooledDispatch<T> {
+    assert!(
+      Weak::ptr_eq(&Arc::downgrade(&self.inner), &handler.pool),
+      "handler dispatched from a pool it didn't come from"
+    );
+    if !handler.outstanding {
+      handler.outstanding = true;
+      self.inner.outstanding.fetch_add(1, Ordering::AcqRel);
+    }
+    handler.dispatch()
+  }
+
+  // For sustained reads, where a handler starts its next operation as soon as
+  // the previous one completes. Completes the handler of `overlapped`, which
+  // must have been dispatched with `dispatch()`, resets it, and re-dispatches
+  // it. `rearm` then makes the OS call for the next operation; it receives
+  // the handler and the OVERLAPPED, and must not touch the handler anymore
+  // once the call has been made. If it fails (e.g. because the handle was
+  // closed), the handler is retired: it's put back into the pool, counts as
+  // outstanding no longer, and the error is passed on.
+  pub unsafe fn complete_and_rearm<F, E>(
+    &self,
+    overlapped: NonNull<OVERLAPPED>,
+    rearm: F,
+  ) -> Result<(), E>
+  where
+    F: FnOnce(&mut T, *mut OVERLAPPED) -> Result<(), E>,
+  {
+    // Completed like any other handler, hooks, wakers and all. A stray
+    // completion the MissingHandlerPolicy let pass leaves nothing to rearm,
+    // and neither does a timeout: the handler's `timed_out()` recycles it.
+    let completed =
+      EventState::complete_reused(overlapped, |mut handler: Box<Pooled<T>>| {
+        handler.handler.complete();
+        handler.on_free();
+        handler
+      });
+    let mut handler = match completed {
+      Some(handler) => handler,
+      None => return Ok(()),
+    };
+    handler.handler.reset();
+    let handler_ptr: *mut T = &mut handler.handler;
+    let mut dispatch = self.dispatch(handler);
+    match rearm(&mut *handler_ptr, dispatch.overlapped()) {
+      Ok(()) => {
+        dispatch.pending();
+        Ok(())
+      }
+      Err(error) => {
+        self.recycle(dispatch.failed());
+        Err(error)
+      }
+    }
+  }
+
+  // Takes a handler out of the pool, or creates one with `new` if the pool is
+  // empty. Handlers that come out of the pool have been reset.
+  pub fn get_or_else<F>(&self, new: F) -> Box<Pooled<T>>
+  where
+    F: FnOnce() -> T,
+  {
+    let recycled = self.inner.free.lock().unwrap().pop();
+    recycled.unwrap_or_else(|| {
+      Box::new(Pooled {
+        handler: new(),
+        pool: Arc::downgrade(&self.inner),
+        outstanding: false,
+      })
+    })
+  }
+
+  // Resets `handler` and puts it back into the pool. Only needed for handlers
+  // that didn't complete, e.g. the ones handed back by `Dispatch::failed()`.
+  pub fn recycle(&self, mut handler: Box<Pooled<T>>) {
+    assert!(
+      Weak::ptr_eq(&Arc::downgrade(&self.inner), &handler.pool),
+      "handler recycled into a pool it didn't come from"
+    );
+    handler.retire();
+    handler.handler.reset();
+    let mut free = self.inner.free.lock().unwrap();
+    if free.len() < self.inner.capacity {
+      free.push(handler);
+    }
+  }
+}
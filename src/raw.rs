@@ -0,0 +1,74 @@
+// Raw OS handle and socket types. On Windows these are the std types; on
+// other platforms, where this crate is only built to develop and test against,
+// they are opaque newtypes around a usize. Either way, the only conversions
+// are the explicit ones below.
+
+use crate::winapi::{HANDLE, SOCKET};
+
+#[cfg(windows)]
+pub use std::os::windows::io::{RawHandle, RawSocket};
+
+#[cfg(not(windows))]
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RawHandle(usize);
+
+#[cfg(not(windows))]
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RawSocket(usize);
+
+#[cfg(not(windows))]
+impl RawHandle {
+  pub const fn from_raw(raw: usize) -> Self {
+    Self(raw)
+  }
+
+  pub const fn as_raw(self) -> usize {
+    self.0
+  }
+}
+
+#[cfg(not(windows))]
+impl RawSocket {
+  pub const fn from_raw(raw: usize) -> Self {
+    Self(raw)
+  }
+
+  pub const fn as_raw(self) -> usize {
+    self.0
+  }
+}
+
+// RawHandle -> the HANDLE win32 apis take.
+#[cfg(windows)]
+pub fn to_handle(handle: RawHandle) -> HANDLE {
+  handle as HANDLE
+}
+
+#[cfg(not(windows))]
+pub fn to_handle(handle: RawHandle) -> HANDLE {
+  handle.as_raw() as HANDLE
+}
+
+// HANDLE -> RawHandle.
+#[cfg(windows)]
+pub fn from_handle(handle: HANDLE) -> RawHandle {
+  handle as RawHandle
+}
+
+#[cfg(not(windows))]
+pub fn from_handle(handle: HANDLE) -> RawHandle {
+  RawHandle::from_raw(handle as usize)
+}
+
+// RawSocket -> the SOCKET winsock apis take.
+#[cfg(windows)]
+pub fn to_socket(socket: RawSocket) -> SOCKET {
+  socket as SOCKET
+}
+
+#[cfg(not(windows))]
+pub fn to_socket(socket: RawSocket) -> SOCKET {
+  socket.as_raw() as SOCKET
+}
@@ -0,0 +1,139 @@
+use std::cell::UnsafeCell;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::iocp::{EventHandler, EventState, IoResult};
+
+type RegisteredBufCallback = Box<dyn FnOnce(IoResult, usize) + Send>;
+
+// A table of I/O buffers that are allocated (and e.g. locked in memory) once,
+// up front, and then read into over and over. Slots are identified by index.
+// Each slot records how much of it holds valid data, i.e. how many bytes the
+// last read into it transferred. The buffers never move or get resized.
+pub struct RegisteredBuffers {
+  slots: Box<[Slot]>,
+  size: usize,
+}
+
+struct Slot {
+  // Written to by the OS while a read into the slot is outstanding. The
+  // pointer handed to the OS is derived from the cells, never from a
+  // reference to the bytes, so it doesn't alias anything.
+  buf: Box<[UnsafeCell<u8>]>,
+  valid_len: AtomicUsize,
+  // Set while a RegisteredBufHandler for the slot exists.
+  claimed: AtomicBool,
+}
+
+// A slot's buffer is only written to by the OS, for the one handler that has
+// claimed the slot, and only read by whoever the caller of `valid()`
+// guarantees has no read outstanding.
+unsafe impl Sync for RegisteredBuffers {}
+
+impl RegisteredBuffers {
+  pub fn new(count: usize, size: usize) -> Arc<Self> {
+    let slots = (0..count)
+      .map(|_| Slot {
+        buf: (0..size).map(|_| UnsafeCell::new(0)).collect(),
+        valid_len: AtomicUsize::new(0),
+        claimed: AtomicBool::new(false),
+      })
+      .collect();
+    Arc::new(Self { slots, size })
+  }
+
+  pub fn len(&self) -> usize {
+    self.slots.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.slots.is_empty()
+  }
+
+  // The size of each slot.
+  pub fn size(&self) -> usize {
+    self.size
+  }
+
+  pub fn valid_len(&self, index: usize) -> usize {
+    self.slots[index].valid_len.load(Ordering::Acquire)
+  }
+
+  // The valid part of slot `index`. No read into the slot may be outstanding.
+  pub unsafe fn valid(&self, index: usize) -> &[u8] {
+    let (ptr, _) = self.raw(index);
+    slice::from_raw_parts(ptr, self.valid_len(index))
+  }
+
+  fn raw(&self, index: usize) -> (*mut u8, usize) {
+    let buf = &self.slots[index].buf;
+    (UnsafeCell::raw_get(buf.as_ptr()), buf.len())
+  }
+}
+
+// Event handler for reads straight into a slot of a RegisteredBuffers table,
+// so no buffer is allocated or copied per operation. It reports the slot as
+// its `io_buffer()`, so it can be started with `dispatch_read()`. Only one
+// read into a slot may be outstanding at a time, so only one handler may
+// exist per slot: the handler claims its slot until it's dropped.
+pub struct RegisteredBufHandler {
+  state: EventState,
+  buffers: Arc<RegisteredBuffers>,
+  index: usize,
+  on_complete: Option<RegisteredBufCallback>,
+}
+
+impl RegisteredBufHandler {
+  // When the read completes, the slot's valid length is set to the number of
+  // bytes transferred, and then `on_complete` receives the result and the
+  // slot index. Panics if another handler has claimed the slot.
+  pub fn new<F>(
+    buffers: Arc<RegisteredBuffers>,
+    index: usize,
+    on_complete: F,
+  ) -> Box<Self>
+  where
+    F: FnOnce(IoResult, usize) + Send + 'static,
+  {
+    assert!(index < buffers.len(), "no registered buffer {}", index);
+    let claimed = buffers.slots[index].claimed.swap(true, Ordering::AcqRel);
+    assert!(!claimed, "registered buffer {} is in use", index);
+    Box::new(Self {
+      state: EventState::new(),
+      buffers,
+      index,
+      on_complete: Some(Box::new(on_complete)),
+    })
+  }
+
+  pub fn index(&self) -> usize {
+    self.index
+  }
+}
+
+impl EventHandler for RegisteredBufHandler {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(mut self: Box<Self>) {
+    let result = self.state.result();
+    let len = (result.bytes_transferred as usize).min(self.buffers.size);
+    let slot = &self.buffers.slots[self.index];
+    slot.valid_len.store(len, Ordering::Release);
+    let on_complete = self.on_complete.take().unwrap();
+    on_complete(result, self.index)
+  }
+
+  fn io_buffer(&mut self) -> Option<(*mut u8, usize)> {
+    Some(self.buffers.raw(self.index))
+  }
+}
+
+impl Drop for RegisteredBufHandler {
+  fn drop(&mut self) {
+    let slot = &self.buffers.slots[self.index];
+    slot.claimed.store(false, Ordering::Release);
+  }
+}
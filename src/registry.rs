@@ -0,0 +1,137 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::default::Default;
+use std::panic::Location;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::completion_port::CompletionPortStats;
+use crate::winapi::OVERLAPPED;
+
+// What the registry knows about an outstanding operation.
+#[derive(Clone, Copy, Debug)]
+pub struct OpRecord {
+  pub type_id: TypeId,
+  pub type_name: &'static str,
+  pub location: &'static Location<'static>,
+  pub dispatched_at: Instant,
+  // The address of the handle the operation was started on, if it was
+  // dispatched with `CompletionPort::dispatch_on()`.
+  pub handle: Option<usize>,
+  // The handler's `resource_cost()` when it was dispatched.
+  pub cost: usize,
+}
+
+// The operations that are outstanding on a completion port, keyed by the
+// address of their OVERLAPPED. Operations are added when they are dispatched
+// through the port, and removed when their handler leaves its EventState,
+// however that happens. The registry also keeps the port's statistics, which
+// are updated along the way, and the total `resource_cost()` of the
+// outstanding operations.
+#[derive(Default)]
+pub struct Registry {
+  ops: Mutex<HashMap<usize, OpRecord>>,
+  stats: CompletionPortStats,
+  bytes: AtomicUsize,
+}
+
+impl Registry {
+  // Adds `cost` to the bytes tied up in outstanding operations, unless that
+  // would exceed `budget`. Returns whether it was added. The cost is given
+  // back when the operation it was charged for is removed again.
+  pub(crate) fn charge(&self, cost: usize, budget: Option<usize>) -> bool {
+    self
+      .bytes
+      .fetch_update(Ordering::AcqRel, Ordering::Acquire, |bytes| {
+        let bytes = bytes.checked_add(cost)?;
+        match budget {
+          Some(budget) if bytes > budget => None,
+          _ => Some(bytes),
+        }
+      })
+      .is_ok()
+  }
+
+  fn refund(&self, record: &OpRecord) {
+    self.bytes.fetch_sub(record.cost, Ordering::AcqRel);
+  }
+
+  // The operation's cost must have been charged already.
+  pub(crate) fn insert(&self, overlapped: *mut OVERLAPPED, record: OpRecord) {
+    self.reinsert(overlapped, record);
+    self.stats.record_submitted();
+  }
+
+  // Like `insert()`, for an operation that has been counted as submitted
+  // already, e.g. one whose handler was swapped by `Dispatch::map()`.
+  pub(crate) fn reinsert(&self, overlapped: *mut OVERLAPPED, record: OpRecord) {
+    let mut ops = self.ops.lock().unwrap();
+    let previous = ops.insert(overlapped as usize, record);
+    assert!(previous.is_none());
+  }
+
+  pub(crate) fn remove(&self, overlapped: *mut OVERLAPPED) -> Option<OpRecord> {
+    let record = self.ops.lock().unwrap().remove(&(overlapped as usize))?;
+    self.refund(&record);
+    Some(record)
+  }
+
+  pub(crate) fn remove_many(&self, overlappeds: &[*mut OVERLAPPED]) {
+    let mut ops = self.ops.lock().unwrap();
+    for &overlapped in overlappeds {
+      if let Some(record) = ops.remove(&(overlapped as usize)) {
+        self.refund(&record);
+      }
+    }
+  }
+
+  // Removes all operations at once, e.g. to move them to another registry.
+  pub(crate) fn take_all(&self) -> Vec<(*mut OVERLAPPED, OpRecord)> {
+    let mut ops = self.ops.lock().unwrap();
+    ops
+      .drain()
+      .map(|(addr, record)| {
+        self.refund(&record);
+        (addr as *mut OVERLAPPED, record)
+      })
+      .collect()
+  }
+
+  pub fn stats(&self) -> &CompletionPortStats {
+    &self.stats
+  }
+
+  // The sum of the `resource_cost()` of the outstanding operations.
+  pub fn bytes(&self) -> usize {
+    self.bytes.load(Ordering::Acquire)
+  }
+
+  // Whether the operation that uses `overlapped` is outstanding.
+  pub fn contains(&self, overlapped: *mut OVERLAPPED) -> bool {
+    self
+      .ops
+      .lock()
+      .unwrap()
+      .contains_key(&(overlapped as usize))
+  }
+
+  pub fn len(&self) -> usize {
+    self.ops.lock().unwrap().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  // The outstanding operations, oldest first.
+  pub fn snapshot(&self) -> Vec<(*mut OVERLAPPED, OpRecord)> {
+    let ops = self.ops.lock().unwrap();
+    let mut snapshot: Vec<_> = ops
+      .iter()
+      .map(|(&addr, &record)| (addr as *mut OVERLAPPED, record))
+      .collect();
+    snapshot.sort_by_key(|(_, record)| record.dispatched_at);
+    snapshot
+  }
+}
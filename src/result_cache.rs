@@ -0,0 +1,70 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+
+// Remembers which logical operations have had their completion processed
+// already, so a handler can tell a duplicate completion from a new one and
+// ignore it. That happens with at-least-once semantics, e.g. when an
+// operation that seemed to fail is retried while the original actually
+// completed. Operations are identified by an idempotency key the caller
+// picks, and gives every attempt at the same logical operation (e.g. a
+// request id). Not by `EventState::sequence()`, since a retry is a new
+// dispatch, with a sequence id of its own. Opt-in: handlers that want it
+// check `record()` at the start of `complete()`.
+//
+// Only the most recent `capacity` keys are kept, so a duplicate that shows up
+// after that many other completions is no longer recognized.
+pub struct ResultCache {
+  inner: Mutex<Inner>,
+  capacity: usize,
+}
+
+struct Inner {
+  seen: HashSet<u64>,
+  // The keys in `seen`, oldest first, for evicting them in order.
+  order: VecDeque<u64>,
+}
+
+impl ResultCache {
+  pub fn with_capacity(capacity: usize) -> Self {
+    assert!(capacity > 0, "ResultCache capacity must be nonzero");
+    Self {
+      inner: Mutex::new(Inner {
+        seen: HashSet::with_capacity(capacity),
+        order: VecDeque::with_capacity(capacity),
+      }),
+      capacity,
+    }
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  pub fn len(&self) -> usize {
+    self.inner.lock().unwrap().order.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  // Records that the completion of the operation with idempotency key `key`
+  // is being processed. Returns false if it was recorded before, i.e. if this
+  // is a duplicate the caller should ignore.
+  pub fn record(&self, key: u64) -> bool {
+    let mut inner = self.inner.lock().unwrap();
+    if !inner.seen.insert(key) {
+      return false;
+    }
+    if inner.order.len() == self.capacity {
+      let oldest = inner.order.pop_front().unwrap();
+      inner.seen.remove(&oldest);
+    }
+    inner.order.push_back(key);
+    true
+  }
+
+  pub fn contains(&self, key: u64) -> bool {
+    self.inner.lock().unwrap().seen.contains(&key)
+  }
+}
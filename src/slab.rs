@@ -0,0 +1,96 @@
+use std::cell::UnsafeCell;
+use std::mem::size_of;
+
+use crate::iocp::{read_overlapped_result, IoResult};
+use crate::winapi::OVERLAPPED;
+
+// Storage for slab-based reactors, which identify outstanding operations by
+// an index rather than by a boxed event handler. Handlers are stored by value
+// and don't need an EventState; each slot has its own OVERLAPPED, and the
+// index of the slot follows from the OVERLAPPED's address. The slab never
+// grows, so those addresses stay put while operations are outstanding.
+pub struct HandlerSlab<T> {
+  slots: Box<[Slot<T>]>,
+  free: Vec<usize>,
+}
+
+struct Slot<T> {
+  // Written to by the OS while the operation is outstanding.
+  overlapped: UnsafeCell<OVERLAPPED>,
+  handler: Option<T>,
+}
+
+impl<T> HandlerSlab<T> {
+  pub fn with_capacity(capacity: usize) -> Self {
+    let slots = (0..capacity)
+      .map(|_| Slot {
+        overlapped: UnsafeCell::new(OVERLAPPED::default()),
+        handler: None,
+      })
+      .collect();
+    Self {
+      slots,
+      free: (0..capacity).rev().collect(),
+    }
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.slots.len()
+  }
+
+  // The number of outstanding operations.
+  pub fn len(&self) -> usize {
+    self.slots.len() - self.free.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  // Stores `handler` in a free slot. Returns the slot index and the OVERLAPPED
+  // to pass to the OS call, or hands the handler back if the slab is full. If
+  // the OS call fails, the slot must be released again with `complete()`.
+  pub fn dispatch(
+    &mut self,
+    handler: T,
+  ) -> Result<(usize, *mut OVERLAPPED), T> {
+    let index = match self.free.pop() {
+      Some(index) => index,
+      None => return Err(handler),
+    };
+    let slot = &mut self.slots[index];
+    *slot.overlapped.get_mut() = OVERLAPPED::default();
+    slot.handler = Some(handler);
+    Ok((index, slot.overlapped.get()))
+  }
+
+  // Maps an OVERLAPPED that was handed out by `dispatch()` back to its index.
+  pub fn index_of(&self, overlapped: *mut OVERLAPPED) -> Option<usize> {
+    let base = self.slots.as_ptr() as usize;
+    let offset = (overlapped as usize).checked_sub(base)?;
+    let index = offset / size_of::<Slot<T>>();
+    let slot = self.slots.get(index)?;
+    if slot.overlapped.get() != overlapped {
+      return None;
+    }
+    Some(index)
+  }
+
+  // Takes the handler out of the slot that `overlapped` belongs to, together
+  // with the result of its operation, and frees up the slot.
+  pub fn complete(
+    &mut self,
+    overlapped: *mut OVERLAPPED,
+  ) -> Option<(T, IoResult)> {
+    let index = self.index_of(overlapped)?;
+    self.complete_by_index(index)
+  }
+
+  pub fn complete_by_index(&mut self, index: usize) -> Option<(T, IoResult)> {
+    let slot = self.slots.get_mut(index)?;
+    let handler = slot.handler.take()?;
+    let result = unsafe { read_overlapped_result(slot.overlapped.get()) };
+    self.free.push(index);
+    Some((handler, result))
+  }
+}
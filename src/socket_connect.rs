@@ -0,0 +1,105 @@
+use std::io;
+
+use crate::completion_kind::status_to_io_error;
+use crate::iocp::{Dispatchable, EventHandler, EventState};
+use crate::raw::RawSocket;
+use crate::winapi::{
+  DWORD, ERROR_IO_PENDING, NTSTATUS, OVERLAPPED, STATUS_SUCCESS,
+};
+
+type ConnectCallback = Box<dyn FnOnce(io::Result<RawSocket>) + Send>;
+
+// Event handler for overlapped ConnectEx() calls, which connect a bound socket
+// to a remote address. A socket connected that way has to be told so with
+// SO_UPDATE_CONNECT_CONTEXT before most other socket functions work on it;
+// this is done before the handler reports back, so what it reports is either
+// a ready-to-use socket or an error.
+pub struct ConnectState {
+  state: EventState,
+  socket: RawSocket,
+  on_complete: Option<ConnectCallback>,
+}
+
+impl ConnectState {
+  // `on_complete` receives the connected socket, or the error connecting
+  // failed with (e.g. ErrorKind::ConnectionRefused).
+  pub fn new<F>(socket: RawSocket, on_complete: F) -> Box<Self>
+  where
+    F: FnOnce(io::Result<RawSocket>) + Send + 'static,
+  {
+    Box::new(Self {
+      state: EventState::new(),
+      socket,
+      on_complete: Some(Box::new(on_complete)),
+    })
+  }
+
+  pub fn socket(&self) -> RawSocket {
+    self.socket
+  }
+
+  // Dispatches the handler and starts connecting. The `connect_ex` callback
+  // makes the actual ConnectEx() call and returns the error it failed with,
+  // as reported by WSAGetLastError(); Ok(()) if it returned TRUE. Unless it
+  // failed with something other than ERROR_IO_PENDING, a completion packet
+  // will follow. Otherwise the handler is handed back.
+  pub fn connect<F>(self: Box<Self>, connect_ex: F) -> Result<(), Box<Self>>
+  where
+    F: FnOnce(RawSocket, *mut OVERLAPPED) -> Result<(), DWORD>,
+  {
+    let socket = self.socket;
+    let mut dispatch = self.dispatch();
+    match connect_ex(socket, dispatch.overlapped()) {
+      Ok(()) | Err(ERROR_IO_PENDING) => {
+        dispatch.pending();
+        Ok(())
+      }
+      Err(_) => Err(dispatch.failed()),
+    }
+  }
+}
+
+impl EventHandler for ConnectState {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(mut self: Box<Self>) {
+    let on_complete = self.on_complete.take().unwrap();
+    let socket = self.socket;
+    let connected = connect_result(self.state.result().status)
+      .and_then(|()| update_connect_context(socket))
+      .map(|()| socket);
+    on_complete(connected)
+  }
+}
+
+fn connect_result(status: NTSTATUS) -> io::Result<()> {
+  match status {
+    STATUS_SUCCESS => Ok(()),
+    status => Err(status_to_io_error(status)),
+  }
+}
+
+#[cfg(windows)]
+fn update_connect_context(socket: RawSocket) -> io::Result<()> {
+  use std::ptr::null;
+
+  use crate::raw::to_socket;
+  use crate::winapi::{setsockopt, SOL_SOCKET, SO_UPDATE_CONNECT_CONTEXT};
+
+  let socket = to_socket(socket);
+  let result = unsafe {
+    setsockopt(socket, SOL_SOCKET, SO_UPDATE_CONNECT_CONTEXT, null(), 0)
+  };
+  if result != 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+// There are no sockets to update off Windows.
+#[cfg(not(windows))]
+fn update_connect_context(_socket: RawSocket) -> io::Result<()> {
+  Ok(())
+}
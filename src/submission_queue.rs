@@ -0,0 +1,61 @@
+use std::default::Default;
+
+use crate::iocp::{Dispatch, Dispatchable, EventHandler};
+use crate::winapi::OVERLAPPED;
+
+// Starts the OS operation for a staged handler. Must return true if the
+// operation was started (it succeeded or returned ERROR_IO_PENDING), and false
+// if it failed outright.
+type SubmitFn = Box<dyn FnOnce(*mut OVERLAPPED) -> bool>;
+
+// Stages overlapped operations so they can be issued together. Handlers are
+// dispatched when queued, but their Dispatch guards are held until `flush()`
+// issues the operations and settles each of them as pending or failed.
+pub struct SubmissionQueue<T> {
+  staged: Vec<(Dispatch<T>, SubmitFn)>,
+}
+
+impl<T> SubmissionQueue<T>
+where
+  T: EventHandler,
+{
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn len(&self) -> usize {
+    self.staged.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.staged.is_empty()
+  }
+
+  pub fn queue<F>(&mut self, event_handler: Box<T>, submit: F)
+  where
+    F: FnOnce(*mut OVERLAPPED) -> bool + 'static,
+  {
+    let dispatch = event_handler.dispatch();
+    self.staged.push((dispatch, Box::new(submit)));
+  }
+
+  // Issues all staged operations in the order they were queued. The ones that
+  // failed to start are rolled back, and their handlers are returned.
+  pub fn flush(&mut self) -> Vec<Box<T>> {
+    let mut failed = Vec::new();
+    for (mut dispatch, submit) in self.staged.drain(..) {
+      if submit(dispatch.overlapped()) {
+        dispatch.pending();
+      } else {
+        failed.push(dispatch.failed());
+      }
+    }
+    failed
+  }
+}
+
+impl<T> Default for SubmissionQueue<T> {
+  fn default() -> Self {
+    Self { staged: Vec::new() }
+  }
+}
@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+use std::default::Default;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::iocp::EventState;
+use crate::winapi::OVERLAPPED;
+
+// Identifies a timer scheduled on a TimerQueue, so it can be cancelled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerId {
+  deadline: Instant,
+  seq: u64,
+}
+
+// Deadlines for outstanding overlapped operations. The event loop is expected
+// to call `fire_expired()` whenever `next_deadline()` has passed; operations
+// that are still outstanding at that point are cancelled, and their handlers
+// get `timed_out()` once the cancellation comes back through the port.
+// Clones share the same timers.
+#[derive(Clone, Default)]
+pub struct TimerQueue {
+  inner: Arc<Timers>,
+}
+
+#[derive(Default)]
+struct Timers {
+  timers: Mutex<BTreeMap<TimerId, usize>>,
+  next_seq: AtomicU64,
+}
+
+impl TimerQueue {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub(crate) fn schedule(
+    &self,
+    deadline: Instant,
+    overlapped: *mut OVERLAPPED,
+  ) -> TimerId {
+    let seq = self.inner.next_seq.fetch_add(1, Ordering::Relaxed);
+    let id = TimerId { deadline, seq };
+    let addr = overlapped as usize;
+    self.inner.timers.lock().unwrap().insert(id, addr);
+    id
+  }
+
+  // Returns false if the timer already fired or was cancelled before.
+  pub fn cancel(&self, id: TimerId) -> bool {
+    self.inner.timers.lock().unwrap().remove(&id).is_some()
+  }
+
+  pub fn next_deadline(&self) -> Option<Instant> {
+    let timers = self.inner.timers.lock().unwrap();
+    timers.keys().next().map(|id| id.deadline)
+  }
+
+  pub fn len(&self) -> usize {
+    self.inner.timers.lock().unwrap().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  // Fires all timers whose deadline is at or before `now`, and returns how
+  // many there were. Each operation is marked as timed out, and `cancel_io`
+  // is called with its OVERLAPPED to make the actual OS call (i.e.
+  // CancelIoEx() on the handle the operation was started on). The handler
+  // stays embedded until the operation completes.
+  //
+  // Completing an operation drops its timer, which waits for this call to
+  // finish, so the OVERLAPPEDs stay valid while `cancel_io` runs. For the
+  // same reason, `cancel_io` must not complete the operation itself.
+  pub unsafe fn fire_expired<F>(&self, now: Instant, mut cancel_io: F) -> usize
+  where
+    F: FnMut(*mut OVERLAPPED),
+  {
+    let mut timers = self.inner.timers.lock().unwrap();
+    let mut fired = 0;
+    while let Some(&id) = timers.keys().next() {
+      if id.deadline > now {
+        break;
+      }
+      let overlapped = timers.remove(&id).unwrap() as *mut OVERLAPPED;
+      EventState::mark_timed_out(overlapped);
+      cancel_io(overlapped);
+      fired += 1;
+    }
+    fired
+  }
+}
@@ -0,0 +1,89 @@
+use std::cell::Cell;
+
+// The trace a piece of work is attributed to, for services that propagate
+// trace context across process boundaries (e.g. a W3C trace id).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TraceId(pub [u8; 16]);
+
+thread_local! {
+  static CURRENT: Cell<Option<TraceId>> = const { Cell::new(None) };
+}
+
+// The trace the current thread is working on behalf of. Dispatching an
+// operation records this in its EventState, and it is made current again
+// while the operation's handler is completed.
+pub fn current() -> Option<TraceId> {
+  CURRENT.with(Cell::get)
+}
+
+// Makes `trace_id` the current trace until the guard is dropped, at which
+// point the previous one is restored.
+pub fn enter(trace_id: Option<TraceId>) -> TraceGuard {
+  TraceGuard(CURRENT.with(|current| current.replace(trace_id)))
+}
+
+pub struct TraceGuard(Option<TraceId>);
+
+impl Drop for TraceGuard {
+  fn drop(&mut self) {
+    CURRENT.with(|current| current.set(self.0));
+  }
+}
+
+// A unit of work that is entered and exited, like a `tracing::Span`. An
+// operation dispatched with `EventState::dispatch_with_span()` keeps its span
+// until it completes, and its completion runs inside it, so the span covers
+// the whole operation. This crate doesn't depend on `tracing`, so it has no
+// implementation for `tracing::Span`; wrap one in a type of your own whose
+// `enter()` and `exit()` pass the span's id to the subscriber (see
+// `tracing::Span::with_subscriber()`).
+pub trait Span: Send {
+  fn enter(&self);
+  fn exit(&self);
+
+  // Called instead when the operation ends without being completed, e.g.
+  // when its handler is dropped after a failed dispatch or at shutdown, so
+  // the span can be closed (with an 'abandoned' status, say) rather than
+  // leaked.
+  fn abandon(&self) {}
+}
+
+// Holds the span an operation was dispatched with. Unless it's taken out to
+// run the completion in, the span is abandoned when the slot is dropped,
+// along with the handler.
+#[derive(Default)]
+pub(crate) struct SpanSlot(Option<Box<dyn Span>>);
+
+impl SpanSlot {
+  pub(crate) fn new(span: Box<dyn Span>) -> Self {
+    Self(Some(span))
+  }
+
+  pub(crate) fn take(&mut self) -> Option<Box<dyn Span>> {
+    self.0.take()
+  }
+}
+
+impl Drop for SpanSlot {
+  fn drop(&mut self) {
+    if let Some(span) = self.0.take() {
+      span.abandon();
+    }
+  }
+}
+
+// Exits the span when dropped.
+pub struct Entered<'a>(&'a dyn Span);
+
+impl<'a> Entered<'a> {
+  pub fn new(span: &'a dyn Span) -> Self {
+    span.enter();
+    Self(span)
+  }
+}
+
+impl Drop for Entered<'_> {
+  fn drop(&mut self) {
+    self.0.exit();
+  }
+}
@@ -0,0 +1,101 @@
+use std::mem::size_of_val;
+use std::ptr;
+
+use crate::iocp::{EventHandler, EventState, IoResult};
+use crate::raw::{from_handle, to_handle, RawHandle, RawSocket};
+use crate::winapi::TRANSMIT_FILE_BUFFERS;
+
+type TransmitFileCallback = Box<dyn FnOnce(&TransmitFileState) + Send>;
+
+// Event handler for overlapped TransmitFile() calls, which send a file (with
+// optional head and tail data) over a socket, so the operation involves two
+// handles. Both are kept here for when it completes. The handler owns the
+// head and tail buffers, which must stay put until the operation completes;
+// since the handler is boxed and the buffers are never resized, they do.
+pub struct TransmitFileState {
+  state: EventState,
+  // A RawHandle isn't Send on every platform, so it's stored as an address.
+  file: usize,
+  socket: RawSocket,
+  head: Box<[u8]>,
+  tail: Box<[u8]>,
+  on_complete: Option<TransmitFileCallback>,
+}
+
+impl TransmitFileState {
+  // `on_complete` receives the handler itself, so it can get at the result as
+  // well as the handles and buffers.
+  pub fn new<F>(
+    file: RawHandle,
+    socket: RawSocket,
+    head: Vec<u8>,
+    tail: Vec<u8>,
+    on_complete: F,
+  ) -> Box<Self>
+  where
+    F: FnOnce(&TransmitFileState) + Send + 'static,
+  {
+    Box::new(Self {
+      state: EventState::new(),
+      file: to_handle(file) as usize,
+      socket,
+      head: head.into_boxed_slice(),
+      tail: tail.into_boxed_slice(),
+      on_complete: Some(Box::new(on_complete)),
+    })
+  }
+
+  pub fn file(&self) -> RawHandle {
+    from_handle(self.file as _)
+  }
+
+  pub fn socket(&self) -> RawSocket {
+    self.socket
+  }
+
+  pub fn head(&self) -> &[u8] {
+    &self.head
+  }
+
+  pub fn tail(&self) -> &[u8] {
+    &self.tail
+  }
+
+  // The outcome of the operation. Only meaningful once it has completed.
+  pub fn result(&self) -> IoResult {
+    self.state.result()
+  }
+
+  // The lpTransmitBuffers argument for TransmitFile(). Empty buffers are
+  // passed as null, which TransmitFile() takes to mean 'none'.
+  pub fn transmit_buffers(&mut self) -> TRANSMIT_FILE_BUFFERS {
+    fn raw(buf: &mut [u8]) -> *mut u8 {
+      if buf.is_empty() {
+        ptr::null_mut()
+      } else {
+        buf.as_mut_ptr()
+      }
+    }
+    TRANSMIT_FILE_BUFFERS {
+      Head: raw(&mut self.head).cast(),
+      HeadLength: self.head.len() as u32,
+      Tail: raw(&mut self.tail).cast(),
+      TailLength: self.tail.len() as u32,
+    }
+  }
+}
+
+impl EventHandler for TransmitFileState {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(mut self: Box<Self>) {
+    let on_complete = self.on_complete.take().unwrap();
+    on_complete(&self)
+  }
+
+  fn resource_cost(&self) -> usize {
+    size_of_val(self) + self.head.len() + self.tail.len()
+  }
+}
@@ -0,0 +1,94 @@
+use std::any::{Any, TypeId};
+use std::ptr;
+
+use crate::iocp::EventState;
+
+// Like EventHandler, but for handlers that don't live in a Box, e.g. ones on
+// the stack or in an arena, so dispatching them costs no heap allocation.
+// They're embedded with `EventState::embed_vtable()`. Once the operation is
+// over, the handler is dropped in place; its storage is then free to reuse.
+pub trait UnboxedEventHandler
+where
+  Self: Any + Send + 'static,
+{
+  fn state(&mut self) -> &mut EventState;
+  fn complete(&mut self);
+}
+
+// A hand-rolled fat pointer: the handler's address plus the functions the
+// completion path needs, without a `Box<dyn EventHandler>`. The layout is
+// fixed so it can be stored type-erased, as a `Vtable<()>`.
+#[repr(C)]
+pub struct Vtable<T> {
+  handler: *mut T,
+  state: unsafe fn(*mut T) -> *mut EventState,
+  complete: unsafe fn(*mut T),
+  type_id: fn() -> TypeId,
+  drop: unsafe fn(*mut T),
+}
+
+// The handler behind it is Send.
+unsafe impl<T: Send> Send for Vtable<T> {}
+
+impl<T> Clone for Vtable<T> {
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<T> Copy for Vtable<T> {}
+
+impl<T> Vtable<T>
+where
+  T: UnboxedEventHandler,
+{
+  // `handler` must stay put, and must not be touched by anything else, from
+  // the moment it is embedded until it has been dropped in place.
+  pub unsafe fn new(handler: *mut T) -> Self {
+    unsafe fn state<T: UnboxedEventHandler>(
+      handler: *mut T,
+    ) -> *mut EventState {
+      (*handler).state()
+    }
+    unsafe fn complete<T: UnboxedEventHandler>(handler: *mut T) {
+      (*handler).complete()
+    }
+    Self {
+      handler,
+      state: state::<T>,
+      complete: complete::<T>,
+      type_id: TypeId::of::<T>,
+      drop: ptr::drop_in_place::<T>,
+    }
+  }
+
+  pub(crate) fn erase(self) -> Vtable<()> {
+    // Only the pointee type differs, and the layout is repr(C).
+    unsafe { ptr::read(&self as *const Self as *const Vtable<()>) }
+  }
+}
+
+impl<T> Vtable<T> {
+  pub fn handler(&self) -> *mut T {
+    self.handler
+  }
+
+  pub(crate) unsafe fn state(&self) -> *mut EventState {
+    (self.state)(self.handler)
+  }
+
+  pub fn type_id(&self) -> TypeId {
+    (self.type_id)()
+  }
+
+  // Completes the handler and then drops it in place.
+  pub(crate) unsafe fn complete(self) {
+    (self.complete)(self.handler);
+    (self.drop)(self.handler)
+  }
+
+  // Drops the handler in place without completing it.
+  pub(crate) unsafe fn drop_handler(self) {
+    (self.drop)(self.handler)
+  }
+}
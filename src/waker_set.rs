@@ -0,0 +1,67 @@
+use std::default::Default;
+use std::mem::take;
+use std::sync::Mutex;
+use std::task::Waker;
+
+// The tasks waiting for a single overlapped operation. More than one task can
+// await the same operation (e.g. a shared connect); all of them are woken
+// once it completes. Wakers may come and go while the operation is
+// outstanding, so the set is guarded by a lock.
+#[derive(Default)]
+pub struct WakerSet {
+  wakers: Mutex<Vec<Waker>>,
+}
+
+impl WakerSet {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  // Adds `waker`, unless a waker that wakes the same task is already in the
+  // set. Returns whether it was added.
+  pub fn register(&self, waker: &Waker) -> bool {
+    let mut wakers = self.wakers.lock().unwrap();
+    if wakers.iter().any(|w| w.will_wake(waker)) {
+      return false;
+    }
+    wakers.push(waker.clone());
+    true
+  }
+
+  // Removes the waker that wakes the same task as `waker`, if it's there.
+  pub fn deregister(&self, waker: &Waker) -> bool {
+    let mut wakers = self.wakers.lock().unwrap();
+    let len = wakers.len();
+    wakers.retain(|w| !w.will_wake(waker));
+    wakers.len() != len
+  }
+
+  pub fn len(&self) -> usize {
+    self.wakers.lock().unwrap().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  // Removes all wakers from the set and wakes them.
+  pub fn wake_all(&self) {
+    let wakers = take(&mut *self.wakers.lock().unwrap());
+    wakers.into_iter().for_each(Waker::wake);
+  }
+
+  // Wakes the set once the returned guard is dropped, which also happens
+  // when the completion the tasks are waiting for panics; otherwise they'd
+  // wait forever.
+  pub fn wake_on_drop(self) -> WakeOnDrop {
+    WakeOnDrop(self)
+  }
+}
+
+pub struct WakeOnDrop(WakerSet);
+
+impl Drop for WakeOnDrop {
+  fn drop(&mut self) {
+    self.0.wake_all()
+  }
+}
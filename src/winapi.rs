@@ -1,7 +1,104 @@
 // Not the real thing, but writing this on mac...
-#[derive(Default)]
+#![allow(non_camel_case_types, non_snake_case, clippy::upper_case_acronyms)]
+
+use std::ffi::c_void;
+use std::mem::zeroed;
+
+pub type DWORD = u32;
+pub type ULONG_PTR = usize;
+pub type HANDLE = *mut c_void;
+pub type NTSTATUS = i32;
+pub type SOCKET = usize;
+pub type BOOL = i32;
+
+pub const INVALID_HANDLE_VALUE: HANDLE = -1isize as HANDLE;
+
+pub const ERROR_SUCCESS: DWORD = 0;
+pub const ERROR_PIPE_CONNECTED: DWORD = 535;
+pub const ERROR_OPERATION_ABORTED: DWORD = 995;
+pub const ERROR_IO_PENDING: DWORD = 997;
+
+pub const STATUS_SUCCESS: NTSTATUS = 0x0000_0000;
+pub const STATUS_TIMEOUT: NTSTATUS = 0x0000_0102;
+pub const STATUS_PENDING: NTSTATUS = 0x0000_0103;
+pub const STATUS_UNSUCCESSFUL: NTSTATUS = 0xc000_0001_u32 as NTSTATUS;
+pub const STATUS_END_OF_FILE: NTSTATUS = 0xc000_0011_u32 as NTSTATUS;
+pub const STATUS_IO_TIMEOUT: NTSTATUS = 0xc000_00b5_u32 as NTSTATUS;
+pub const STATUS_CANCELLED: NTSTATUS = 0xc000_0120_u32 as NTSTATUS;
+pub const STATUS_PIPE_BROKEN: NTSTATUS = 0xc000_014b_u32 as NTSTATUS;
+pub const STATUS_CONNECTION_REFUSED: NTSTATUS = 0xc000_0236_u32 as NTSTATUS;
+
+pub const SOL_SOCKET: i32 = 0xffff;
+pub const SO_UPDATE_CONNECT_CONTEXT: i32 = 0x7010;
+
+#[repr(C)]
 pub struct OVERLAPPED {
-  _foo: i64,
-  _bar: i32,
+  pub Internal: ULONG_PTR,
+  pub InternalHigh: ULONG_PTR,
+  pub Offset: DWORD,
+  pub OffsetHigh: DWORD,
+  pub hEvent: HANDLE,
 }
 unsafe impl Send for OVERLAPPED {}
+
+impl Default for OVERLAPPED {
+  fn default() -> Self {
+    unsafe { zeroed() }
+  }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct OVERLAPPED_ENTRY {
+  pub lpCompletionKey: ULONG_PTR,
+  pub lpOverlapped: *mut OVERLAPPED,
+  pub Internal: ULONG_PTR,
+  pub dwNumberOfBytesTransferred: DWORD,
+}
+unsafe impl Send for OVERLAPPED_ENTRY {}
+
+pub type LPOVERLAPPED_COMPLETION_ROUTINE = Option<
+  unsafe extern "system" fn(
+    dwErrorCode: DWORD,
+    dwNumberOfBytesTransfered: DWORD,
+    lpOverlapped: *mut OVERLAPPED,
+  ),
+>;
+
+#[repr(C)]
+pub struct TRANSMIT_FILE_BUFFERS {
+  pub Head: *mut c_void,
+  pub HeadLength: DWORD,
+  pub Tail: *mut c_void,
+  pub TailLength: DWORD,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct WSABUF {
+  pub len: u32,
+  pub buf: *mut u8,
+}
+unsafe impl Send for WSABUF {}
+
+#[cfg(windows)]
+extern "system" {
+  pub fn GetOverlappedResult(
+    hFile: HANDLE,
+    lpOverlapped: *mut OVERLAPPED,
+    lpNumberOfBytesTransferred: *mut DWORD,
+    bWait: BOOL,
+  ) -> BOOL;
+}
+
+#[cfg(windows)]
+#[link(name = "ws2_32")]
+extern "system" {
+  pub fn setsockopt(
+    s: SOCKET,
+    level: i32,
+    optname: i32,
+    optval: *const std::ffi::c_char,
+    optlen: i32,
+  ) -> i32;
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use std::ptr::NonNull;
+use std::time::Duration;
+
+use miox::completion_port::CompletionPort;
+use miox::iocp::AbortResult;
+use miox::winapi::{STATUS_CANCELLED, STATUS_SUCCESS};
+use miox::IoResult;
+
+use common::{Counters, Probe};
+
+const CANCELLED: IoResult = IoResult {
+  status: STATUS_CANCELLED,
+  bytes_transferred: 0,
+};
+
+#[test]
+fn abort_waits_for_the_cancelled_operation() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let mut other = port.dispatch(Probe::new(&counters));
+  let other_overlapped = NonNull::new(other.overlapped()).unwrap();
+  other.pending();
+  // Another operation completed first; it's run along the way.
+  unsafe { port.complete_io(0, other_overlapped, CANCELLED) };
+
+  let dispatch = port.dispatch(Probe::new(&counters));
+  let aborted = dispatch.abort(&port, None, |overlapped| unsafe {
+    port.complete_io(0, NonNull::new(overlapped).unwrap(), CANCELLED)
+  });
+  assert_eq!(aborted, AbortResult::Cancelled);
+  assert_eq!(counters.completed(), 2);
+  port.assert_no_leaks();
+}
+
+#[test]
+fn abort_reports_an_operation_that_finished_first() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let finished = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 9,
+  };
+  let dispatch = port.dispatch(Probe::new(&counters));
+  let timeout = Some(Duration::from_secs(5));
+  let aborted = dispatch.abort(&port, timeout, |overlapped| unsafe {
+    // Too late to cancel: the packet is queued already.
+    port.complete_io(0, NonNull::new(overlapped).unwrap(), finished)
+  });
+  assert_eq!(aborted, AbortResult::Completed(finished));
+  assert_eq!(counters.results(), [finished]);
+  port.assert_no_leaks();
+}
+
+#[test]
+fn abort_gives_up_after_the_timeout() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let mut dispatch = port.dispatch(Probe::new(&counters));
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  let timeout = Some(Duration::from_millis(30));
+  // The cancellation request gets lost.
+  let aborted = dispatch.abort(&port, timeout, |_| {});
+  assert_eq!(aborted, AbortResult::TimedOut);
+  assert_eq!(counters.completed(), 0);
+  assert_eq!(port.registry().len(), 1);
+
+  // The operation is still outstanding, and completes the usual way.
+  unsafe { port.complete_io(0, overlapped, CANCELLED) };
+  port.run_one(Some(Duration::from_secs(0))).unwrap();
+  assert_eq!(counters.completed(), 1);
+  port.assert_no_leaks();
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use std::ptr::{self, NonNull};
+
+use miox::apc::ApcBackend;
+use miox::winapi::{ERROR_OPERATION_ABORTED, ERROR_SUCCESS, STATUS_CANCELLED};
+use miox::{Dispatchable, EventHandler, EventState, IoResult};
+
+use common::{Counters, Probe};
+
+#[test]
+fn completion_routine_completes_the_handler() {
+  let counters = Counters::new();
+  let mut dispatch = Probe::new(&counters).dispatch();
+  let overlapped = dispatch.overlapped();
+  dispatch.pending();
+
+  // What an alertable wait does once a ReadFileEx() has finished.
+  let routine = ApcBackend::ROUTINE.unwrap();
+  unsafe { routine(ERROR_SUCCESS, 12, overlapped) };
+  assert_eq!(counters.results()[0].bytes_transferred, 12);
+  assert_eq!(counters.freed(), 1);
+}
+
+#[test]
+fn aborted_operation_is_reported_as_cancelled() {
+  let counters = Counters::new();
+  let mut dispatch = Probe::new(&counters).dispatch();
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+
+  unsafe { ApcBackend::complete(ERROR_OPERATION_ABORTED, 0, overlapped) };
+  let cancelled = IoResult {
+    status: STATUS_CANCELLED,
+    bytes_transferred: 0,
+  };
+  assert_eq!(counters.results(), vec![cancelled]);
+}
+
+#[test]
+fn completion_routine_does_not_unwind_into_the_kernel() {
+  struct Panicky {
+    state: EventState,
+  }
+
+  impl EventHandler for Panicky {
+    fn state(&mut self) -> &mut EventState {
+      &mut self.state
+    }
+
+    fn complete(self: Box<Self>) {
+      panic!("bug in complete()");
+    }
+  }
+
+  let handler = Box::new(Panicky {
+    state: EventState::new(),
+  });
+  let mut dispatch = handler.dispatch();
+  let overlapped = dispatch.overlapped();
+  dispatch.pending();
+
+  // If either of these unwound, the test process would abort.
+  let routine = ApcBackend::ROUTINE.unwrap();
+  unsafe { routine(ERROR_SUCCESS, 0, overlapped) };
+  unsafe { routine(ERROR_SUCCESS, 0, ptr::null_mut()) };
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use std::ptr::NonNull;
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use miox::arc_dispatch::ArcDispatch;
+use miox::completion_port::CompletionPort;
+use miox::EventState;
+
+use common::{Counters, Probe};
+
+#[test]
+fn settled_on_another_thread() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let dispatch = ArcDispatch::new(port.dispatch(Probe::new(&counters)));
+  let overlapped = dispatch.overlapped() as usize;
+
+  let worker = dispatch.clone();
+  let settled = thread::spawn(move || {
+    // Stands in for the OS call, issued by the worker.
+    assert_eq!(worker.overlapped() as usize, overlapped);
+    worker.pending()
+  })
+  .join()
+  .unwrap();
+  assert!(settled);
+  assert!(dispatch.is_settled());
+  // The first settlement won.
+  assert!(!dispatch.pending());
+  assert!(dispatch.failed().is_none());
+  drop(dispatch);
+
+  let overlapped = NonNull::new(overlapped as *mut _).unwrap();
+  unsafe { EventState::complete(overlapped) };
+  assert_eq!(counters.completed(), 1);
+  port.assert_no_leaks();
+}
+
+#[test]
+fn only_one_clone_gets_the_handler_back() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let dispatch = ArcDispatch::new(port.dispatch(Probe::new(&counters)));
+  let barrier = Arc::new(Barrier::new(4));
+  let threads: Vec<_> = (0..4)
+    .map(|_| {
+      let dispatch = dispatch.clone();
+      let barrier = barrier.clone();
+      thread::spawn(move || {
+        barrier.wait();
+        dispatch.failed().is_some()
+      })
+    })
+    .collect();
+  let reclaimed = threads
+    .into_iter()
+    .map(|t| t.join().unwrap())
+    .filter(|&reclaimed| reclaimed)
+    .count();
+  assert_eq!(reclaimed, 1);
+  assert_eq!(counters.completed(), 0);
+  port.assert_no_leaks();
+}
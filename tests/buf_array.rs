@@ -0,0 +1,31 @@
+use std::slice;
+
+use miox::buf_array::BufArray;
+
+#[test]
+fn descriptors_point_at_the_owned_buffers() {
+  let mut array = BufArray::<2>::new(vec![b"head".to_vec(), vec![0; 16]]);
+  let (wsabufs, count) = array.wsabufs();
+  assert_eq!(count, 2);
+  let wsabufs = unsafe { slice::from_raw_parts(wsabufs, 2) };
+  assert_eq!(wsabufs[0].len, 4);
+  assert_eq!(wsabufs[1].len, 16);
+  assert_eq!(unsafe { slice::from_raw_parts(wsabufs[0].buf, 4) }, b"head");
+
+  // The buffers stay where the descriptors say when the array moves, e.g.
+  // into the handler that's about to be boxed.
+  let first = wsabufs[0].buf as *const u8;
+  let moved = Box::new(array);
+  assert_eq!(moved.descriptors()[0].buf as *const u8, first);
+  assert_eq!(moved.buf(0).as_ptr(), first);
+  assert_eq!(moved.buf(1), &[0; 16][..]);
+
+  let bufs = moved.into_bufs();
+  assert_eq!(&*bufs[0], b"head");
+}
+
+#[test]
+#[should_panic(expected = "BufArray<2> needs 2 buffers")]
+fn buffer_count_must_match() {
+  BufArray::<2>::new(vec![vec![0; 4]]);
+}
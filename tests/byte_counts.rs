@@ -0,0 +1,54 @@
+mod common;
+
+use std::ptr::NonNull;
+use std::time::Duration;
+
+use miox::completion_port::CompletionPort;
+use miox::iocp::read_overlapped_result;
+use miox::winapi::{DWORD, OVERLAPPED, STATUS_SUCCESS};
+use miox::IoResult;
+
+use common::{Counters, Probe};
+
+// Byte counts are DWORDs on every target, whatever the width of the
+// ULONG_PTR they're stored in.
+const NEAR_THE_LIMIT: [DWORD; 4] =
+  [0x7fff_ffff, 0x8000_0000, DWORD::MAX - 1, DWORD::MAX];
+
+#[test]
+fn byte_counts_survive_the_overlapped() {
+  for bytes in NEAR_THE_LIMIT.iter().copied() {
+    let mut overlapped = OVERLAPPED {
+      Internal: STATUS_SUCCESS as usize,
+      InternalHigh: bytes as usize,
+      ..Default::default()
+    };
+    let result = unsafe { read_overlapped_result(&mut overlapped) };
+    let transferred: u32 = result.bytes_transferred;
+    assert_eq!(transferred, bytes);
+  }
+}
+
+#[test]
+fn byte_counts_survive_the_completion_port() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  for bytes in NEAR_THE_LIMIT.iter().copied() {
+    let mut dispatch = port.dispatch(Probe::new(&counters));
+    let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+    dispatch.pending();
+    let result = IoResult {
+      status: STATUS_SUCCESS,
+      bytes_transferred: bytes,
+    };
+    unsafe { port.complete_io(0, overlapped, result) };
+    let entry = port.run_one(Some(Duration::from_secs(0))).unwrap();
+    assert_eq!(entry.dwNumberOfBytesTransferred, bytes);
+  }
+  let results: Vec<DWORD> = counters
+    .results()
+    .iter()
+    .map(|result| result.bytes_transferred)
+    .collect();
+  assert_eq!(results, NEAR_THE_LIMIT);
+}
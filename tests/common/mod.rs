@@ -0,0 +1,66 @@
+// Helpers shared by the integration tests. Not every test file uses all of
+// them.
+#![allow(dead_code)]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use miox::{EventHandler, EventState, IoResult};
+
+// Records what happens to the Probes that share it.
+#[derive(Default)]
+pub struct Counters {
+  completed: AtomicUsize,
+  freed: AtomicUsize,
+  results: Mutex<Vec<IoResult>>,
+}
+
+impl Counters {
+  pub fn new() -> Arc<Self> {
+    Default::default()
+  }
+
+  pub fn completed(&self) -> usize {
+    self.completed.load(Ordering::SeqCst)
+  }
+
+  pub fn freed(&self) -> usize {
+    self.freed.load(Ordering::SeqCst)
+  }
+
+  // The results the probes were completed with, in completion order.
+  pub fn results(&self) -> Vec<IoResult> {
+    self.results.lock().unwrap().clone()
+  }
+}
+
+// An event handler that only reports back to its Counters.
+pub struct Probe {
+  pub state: EventState,
+  pub counters: Arc<Counters>,
+}
+
+impl Probe {
+  pub fn new(counters: &Arc<Counters>) -> Box<Self> {
+    Box::new(Self {
+      state: EventState::new(),
+      counters: counters.clone(),
+    })
+  }
+}
+
+impl EventHandler for Probe {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {
+    let result = self.state.result();
+    self.counters.results.lock().unwrap().push(result);
+    self.counters.completed.fetch_add(1, Ordering::SeqCst);
+  }
+
+  fn on_free(&mut self) {
+    self.counters.freed.fetch_add(1, Ordering::SeqCst);
+  }
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use miox::completion_port::CompletionPort;
+use miox::iocp::InvalidOverlapped;
+use miox::winapi::{OVERLAPPED, OVERLAPPED_ENTRY, STATUS_SUCCESS};
+use miox::EventState;
+
+use common::{Counters, Probe};
+
+fn entry(key: usize, overlapped: *mut OVERLAPPED) -> OVERLAPPED_ENTRY {
+  OVERLAPPED_ENTRY {
+    lpCompletionKey: key,
+    lpOverlapped: overlapped,
+    Internal: 0,
+    dwNumberOfBytesTransferred: 0,
+  }
+}
+
+// What the kernel records when the operation succeeds.
+unsafe fn succeed(overlapped: *mut OVERLAPPED, bytes: u32) {
+  (*overlapped).Internal = STATUS_SUCCESS as usize;
+  (*overlapped).InternalHigh = bytes as usize;
+}
+
+#[test]
+fn complete_all_hands_foreign_entries_to_the_fallback() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  // OVERLAPPEDs allocated by foreign code, with nothing around them.
+  let mut foreign: Vec<Box<OVERLAPPED>> =
+    (0..3).map(|_| Box::default()).collect();
+
+  let mut entries = Vec::new();
+  for (key, raw) in foreign.iter_mut().enumerate() {
+    let mut dispatch = port.dispatch(Probe::new(&counters));
+    let overlapped = dispatch.overlapped();
+    dispatch.pending();
+    unsafe { succeed(overlapped, 0) };
+    entries.push(entry(2 * key, overlapped));
+    entries.push(entry(2 * key + 1, &mut **raw));
+  }
+
+  let mut passed_on = Vec::new();
+  unsafe {
+    EventState::complete_all(&entries, port.registry(), |entry| {
+      passed_on.push(entry.lpCompletionKey)
+    })
+  };
+  assert_eq!(counters.completed(), 3);
+  assert_eq!(passed_on, vec![1, 3, 5]);
+  port.assert_no_leaks();
+}
+
+#[test]
+fn complete_checked_rejects_foreign_overlappeds() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let mut foreign = Box::<OVERLAPPED>::default();
+  assert_eq!(
+    unsafe { EventState::complete_checked(&mut *foreign, port.registry()) },
+    Err(InvalidOverlapped::Foreign)
+  );
+
+  let mut dispatch = port.dispatch(Probe::new(&counters));
+  let overlapped = dispatch.overlapped();
+  dispatch.pending();
+  assert!(EventState::is_ours(overlapped, port.registry()));
+  unsafe { succeed(overlapped, 0) };
+  assert_eq!(
+    unsafe { EventState::complete_checked(overlapped, port.registry()) },
+    Ok(())
+  );
+  // Once completed, the operation isn't outstanding anymore.
+  assert!(!EventState::is_ours(overlapped, port.registry()));
+  assert_eq!(counters.completed(), 1);
+}
+
+#[test]
+fn complete_all_round_robin_takes_turns_between_handles() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  // A busy handle (key 1) with three completions in the batch, and a quieter
+  // one (key 2) with two. The byte counts tell the completions apart.
+  let batch = [(1, 1), (1, 2), (2, 10), (1, 3), (2, 20)];
+  let entries: Vec<_> = batch
+    .iter()
+    .map(|&(key, bytes)| {
+      let mut dispatch = port.dispatch(Probe::new(&counters));
+      let overlapped = dispatch.overlapped();
+      dispatch.pending();
+      unsafe { succeed(overlapped, bytes) };
+      entry(key, overlapped)
+    })
+    .collect();
+
+  unsafe {
+    EventState::complete_all_round_robin(&entries, port.registry(), |_| {
+      panic!("no foreign entries in the batch")
+    })
+  };
+  let order: Vec<u32> = counters
+    .results()
+    .iter()
+    .map(|result| result.bytes_transferred)
+    .collect();
+  assert_eq!(order, vec![1, 10, 2, 20, 3]);
+  port.assert_no_leaks();
+}
+
+#[test]
+fn completion_lists_built_from_dispatches_complete_in_order() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let mut entries = Vec::new();
+  for bytes in 1..=3 {
+    let mut dispatch = port.dispatch(Probe::new(&counters));
+    let entry = dispatch.overlapped_entry(bytes as usize, bytes);
+    assert_eq!(entry.lpOverlapped, dispatch.overlapped());
+    assert_eq!(entry.lpCompletionKey, bytes as usize);
+    assert_eq!(entry.dwNumberOfBytesTransferred, bytes);
+    dispatch.pending();
+    unsafe { succeed(entry.lpOverlapped, bytes) };
+    entries.push(entry);
+  }
+
+  unsafe {
+    EventState::complete_all(&entries, port.registry(), |_| {
+      panic!("foreign entry")
+    })
+  };
+  let bytes: Vec<_> = counters
+    .results()
+    .iter()
+    .map(|result| result.bytes_transferred)
+    .collect();
+  assert_eq!(bytes, [1, 2, 3]);
+  port.assert_no_leaks();
+}
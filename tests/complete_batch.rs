@@ -0,0 +1,167 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::task::{Wake, Waker};
+use std::thread::{self, ThreadId};
+use std::time::Instant;
+
+use miox::container_of::ContainerOf;
+use miox::iocp::write_overlapped_result;
+use miox::timer_queue::TimerQueue;
+use miox::winapi::{OVERLAPPED, STATUS_CANCELLED, STATUS_SUCCESS};
+use miox::{Dispatchable, EventHandler, EventState, IoResult};
+
+// What `Read::timed_out()` reports instead of a byte count.
+const TIMED_OUT: u32 = u32::MAX;
+
+// Reads into a fixed-size buffer, so it can't transfer more than that.
+struct Read {
+  state: EventState,
+  len: usize,
+  done: Sender<u32>,
+  thread: Option<ThreadId>,
+}
+
+impl Read {
+  fn new(len: usize, done: &Sender<u32>) -> Box<Self> {
+    Box::new(Self {
+      state: EventState::new(),
+      len,
+      done: done.clone(),
+      thread: None,
+    })
+  }
+}
+
+impl EventHandler for Read {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {
+    self
+      .done
+      .send(self.state.result().bytes_transferred)
+      .unwrap();
+  }
+
+  fn timed_out(self: Box<Self>) {
+    self.done.send(TIMED_OUT).unwrap();
+  }
+
+  fn io_size_hint(&self) -> (usize, Option<usize>) {
+    (0, Some(self.len))
+  }
+
+  fn expected_thread(&self) -> Option<ThreadId> {
+    self.thread
+  }
+}
+
+fn finish(overlapped: NonNull<OVERLAPPED>, status: i32, bytes: u32) {
+  let result = IoResult {
+    status,
+    bytes_transferred: bytes,
+  };
+  // Stands in for the kernel recording the outcome.
+  unsafe { write_overlapped_result(overlapped.as_ptr(), result) };
+}
+
+fn dispatch_read(read: Box<Read>, bytes: u32) -> NonNull<OVERLAPPED> {
+  let mut dispatch = read.dispatch();
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  finish(overlapped, STATUS_SUCCESS, bytes);
+  overlapped
+}
+
+fn dispatch(len: usize, bytes: u32, done: &Sender<u32>) -> NonNull<OVERLAPPED> {
+  dispatch_read(Read::new(len, done), bytes)
+}
+
+#[test]
+fn complete_batch_completes_every_handler() {
+  let (tx, rx) = channel();
+  let overlappeds = [dispatch(8, 8, &tx), dispatch(8, 3, &tx)];
+  unsafe { EventState::complete_batch::<Read>(&overlappeds) };
+  assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![8, 3]);
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn complete_batch_checks_the_io_size_hint() {
+  let (tx, rx) = channel();
+  let overlappeds = [dispatch(8, 16, &tx)];
+  let panicked = catch_unwind(AssertUnwindSafe(|| unsafe {
+    EventState::complete_batch::<Read>(&overlappeds)
+  }));
+  let message = *panicked.unwrap_err().downcast::<String>().unwrap();
+  assert!(
+    message.contains("more than its io_size_hint() of 8"),
+    "{}",
+    message
+  );
+  assert!(rx.try_recv().is_err());
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn complete_batch_checks_the_expected_thread() {
+  let (tx, rx) = channel();
+  let mut read = Read::new(8, &tx);
+  read.thread = Some(thread::spawn(|| thread::current().id()).join().unwrap());
+  let overlappeds = [dispatch_read(read, 8)];
+  let panicked = catch_unwind(AssertUnwindSafe(|| unsafe {
+    EventState::complete_batch::<Read>(&overlappeds)
+  }));
+  let message = *panicked.unwrap_err().downcast::<String>().unwrap();
+  assert!(message.contains("but belongs to"), "{}", message);
+  assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn complete_batch_wakes_the_tasks_of_every_handler() {
+  #[derive(Default)]
+  struct Task(AtomicUsize);
+
+  impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+      self.0.fetch_add(1, Ordering::SeqCst);
+    }
+  }
+
+  let (tx, _rx) = channel();
+  let tasks: Vec<Arc<Task>> = (0..2).map(|_| Arc::default()).collect();
+  let overlappeds: Vec<_> = tasks
+    .iter()
+    .map(|task| {
+      let overlapped = dispatch(8, 1, &tx);
+      let state = unsafe { EventState::container_of(&*overlapped.as_ptr()) };
+      state.wakers().register(&Waker::from(task.clone()));
+      overlapped
+    })
+    .collect();
+  unsafe { EventState::complete_batch::<Read>(&overlappeds) };
+  assert!(tasks.iter().all(|task| task.0.load(Ordering::SeqCst) == 1));
+}
+
+#[test]
+fn timed_out_handlers_are_left_out_of_the_batch() {
+  let (tx, rx) = channel();
+  let timer = TimerQueue::new();
+  let now = Instant::now();
+  let mut read = Read::new(8, &tx).dispatch();
+  let timed_out = NonNull::new(read.overlapped()).unwrap();
+  unsafe { EventState::schedule_timeout(timed_out, now, &timer) };
+  read.pending();
+  unsafe { timer.fire_expired(now, |_| {}) };
+  finish(timed_out, STATUS_CANCELLED, 0);
+
+  let overlappeds = [dispatch(8, 4, &tx), timed_out];
+  unsafe { EventState::complete_batch::<Read>(&overlappeds) };
+  let mut outcomes: Vec<_> = rx.try_iter().collect();
+  outcomes.sort_unstable();
+  assert_eq!(outcomes, vec![4, TIMED_OUT]);
+}
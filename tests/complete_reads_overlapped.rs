@@ -0,0 +1,52 @@
+use std::ptr::NonNull;
+use std::sync::mpsc::{channel, Sender};
+
+use miox::completion_port::CompletionPort;
+use miox::winapi::STATUS_SUCCESS;
+use miox::{EventHandler, EventState, IoResult};
+
+struct FileRead {
+  state: EventState,
+  done: Sender<(u64, IoResult, usize)>,
+}
+
+impl EventHandler for FileRead {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  // The handler owns its EventState again, OVERLAPPED and all.
+  fn complete(mut self: Box<Self>) {
+    let state = self.state();
+    let offset = state.get_file_offset();
+    let result = state.result();
+    let internal = state.Internal;
+    self.done.send((offset, result, internal)).unwrap();
+  }
+}
+
+#[test]
+fn complete_reads_the_file_offset() {
+  let (tx, rx) = channel();
+  let port = CompletionPort::new();
+  let mut read = Box::new(FileRead {
+    state: EventState::new(),
+    done: tx,
+  });
+  read.state().set_file_offset(0x1_0000_0200);
+
+  let mut dispatch = port.dispatch(read);
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 512,
+  };
+  unsafe { port.complete_io(0, overlapped, result) };
+  port.run_one(None).unwrap();
+
+  let (offset, reported, internal) = rx.try_recv().unwrap();
+  assert_eq!(offset, 0x1_0000_0200);
+  assert_eq!(reported, result);
+  assert_eq!(internal, STATUS_SUCCESS as usize);
+}
@@ -0,0 +1,124 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr::NonNull;
+use std::sync::mpsc::{channel, Sender};
+
+use miox::iocp::ReusableEventHandler;
+use miox::winapi::{OVERLAPPED, STATUS_SUCCESS};
+use miox::{Dispatchable, EventHandler, EventState, IoResult};
+
+#[derive(Clone, Copy)]
+enum Reentry {
+  None,
+  CompleteReusable,
+  Complete,
+  Panic,
+}
+
+// Sustained reader that keeps its allocation across completions.
+struct Reader {
+  state: EventState,
+  reads: Sender<u32>,
+  reentry: Reentry,
+}
+
+impl Reader {
+  fn new(reads: &Sender<u32>, reentry: Reentry) -> Box<Self> {
+    Box::new(Self {
+      state: EventState::new(),
+      reads: reads.clone(),
+      reentry,
+    })
+  }
+}
+
+impl EventHandler for Reader {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {
+    self.reads.send(u32::MAX).unwrap();
+  }
+
+  fn as_reusable(&mut self) -> Option<&mut dyn ReusableEventHandler> {
+    Some(self)
+  }
+}
+
+impl ReusableEventHandler for Reader {
+  fn complete_mut(&mut self) {
+    let bytes = self.state.result().bytes_transferred;
+    self.reads.send(bytes).unwrap();
+    // Stands in for the re-armed operation completing before this returns.
+    let overlapped = NonNull::from(&mut *self.state as &mut OVERLAPPED);
+    match self.reentry {
+      Reentry::None => {}
+      Reentry::CompleteReusable => unsafe {
+        EventState::complete_reusable(overlapped, read(0))
+      },
+      Reentry::Complete => unsafe { EventState::complete(overlapped) },
+      Reentry::Panic => panic!("complete_mut() failed"),
+    }
+  }
+}
+
+fn read(bytes: u32) -> IoResult {
+  IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: bytes,
+  }
+}
+
+fn dispatched(reader: Box<Reader>) -> NonNull<OVERLAPPED> {
+  let mut dispatch = reader.dispatch();
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  overlapped
+}
+
+#[test]
+fn complete_reusable_then_rearm() {
+  let (tx, rx) = channel();
+  let overlapped = dispatched(Reader::new(&tx, Reentry::None));
+  for bytes in 1..=3 {
+    // The handler is still embedded, so the same OVERLAPPED goes straight
+    // into the next read.
+    unsafe { EventState::complete_reusable(overlapped, read(bytes)) };
+    assert_eq!(rx.try_recv().unwrap(), bytes);
+  }
+  // The last read is completed the usual way, which frees the handler.
+  unsafe { EventState::complete(overlapped) };
+  assert_eq!(rx.try_recv().unwrap(), u32::MAX);
+}
+
+#[test]
+#[should_panic(expected = "re-entrant EventState::complete_reusable()")]
+fn complete_reusable_rejects_reentry() {
+  let (tx, _rx) = channel();
+  let overlapped = dispatched(Reader::new(&tx, Reentry::CompleteReusable));
+  unsafe { EventState::complete_reusable(overlapped, read(1)) };
+}
+
+#[test]
+#[should_panic(
+  expected = "EventState::complete() while complete_reusable() runs"
+)]
+fn complete_is_rejected_while_complete_reusable_runs() {
+  let (tx, _rx) = channel();
+  let overlapped = dispatched(Reader::new(&tx, Reentry::Complete));
+  unsafe { EventState::complete_reusable(overlapped, read(1)) };
+}
+
+#[test]
+fn complete_reusable_recovers_from_a_panicking_complete_mut() {
+  let (tx, rx) = channel();
+  let overlapped = dispatched(Reader::new(&tx, Reentry::Panic));
+  let panicked = catch_unwind(AssertUnwindSafe(|| unsafe {
+    EventState::complete_reusable(overlapped, read(1))
+  }));
+  assert!(panicked.is_err());
+  assert_eq!(rx.try_recv().unwrap(), 1);
+  // The handler is back in place, and no longer counts as being completed.
+  unsafe { EventState::complete(overlapped) };
+  assert_eq!(rx.try_recv().unwrap(), u32::MAX);
+}
@@ -0,0 +1,108 @@
+// The inline completion limit applies to the whole process, so this is the
+// only test in its binary.
+
+mod common;
+
+use std::cell::Cell;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+use miox::winapi::STATUS_SUCCESS;
+use miox::{Dispatchable, EventHandler, EventState};
+
+use common::{Counters, Probe};
+
+thread_local! {
+  static DEPTH: Cell<usize> = const { Cell::new(0) };
+  static MAX_DEPTH: Cell<usize> = const { Cell::new(0) };
+  static CHAINED: Cell<usize> = const { Cell::new(0) };
+}
+
+// Dispatches `handler` and completes it synchronously.
+fn run_sync<T: EventHandler>(handler: Box<T>) {
+  let mut dispatch = handler.dispatch();
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  unsafe { EventState::complete_sync(overlapped, 0, STATUS_SUCCESS) };
+}
+
+// Re-arms itself from `complete()` until `remaining` runs out, like a reader
+// whose reads keep finishing synchronously.
+struct Chain {
+  state: EventState,
+  remaining: usize,
+}
+
+impl EventHandler for Chain {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {
+    let depth = DEPTH.with(|d| d.get() + 1);
+    DEPTH.with(|d| d.set(depth));
+    MAX_DEPTH.with(|m| m.set(m.get().max(depth)));
+    CHAINED.with(|c| c.set(c.get() + 1));
+    if self.remaining > 0 {
+      run_sync(Box::new(Chain {
+        state: EventState::new(),
+        remaining: self.remaining - 1,
+      }));
+    }
+    DEPTH.with(|d| d.set(depth - 1));
+  }
+}
+
+// Completes `children` synchronously from its own `complete()`, and then
+// panics.
+struct Panicking {
+  state: EventState,
+  children: usize,
+  counters: Arc<Counters>,
+}
+
+impl EventHandler for Panicking {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {
+    for _ in 0..self.children {
+      run_sync(Probe::new(&self.counters));
+    }
+    // With a limit of one, none of them has run yet.
+    assert_eq!(self.counters.completed(), 0);
+    panic!("handler failed");
+  }
+}
+
+#[test]
+fn complete_sync_defers_past_the_inline_limit() {
+  EventState::set_inline_completion_limit(1);
+
+  run_sync(Box::new(Chain {
+    state: EventState::new(),
+    remaining: 10,
+  }));
+  assert_eq!(CHAINED.with(Cell::get), 11);
+  assert_eq!(MAX_DEPTH.with(Cell::get), 1);
+
+  // The completions queued before the outermost handler panicked still run,
+  // and don't linger for the next `complete_sync()` on this thread.
+  let counters = Counters::new();
+  let panicked = catch_unwind(AssertUnwindSafe(|| {
+    run_sync(Box::new(Panicking {
+      state: EventState::new(),
+      children: 3,
+      counters: counters.clone(),
+    }))
+  }));
+  assert!(panicked.is_err());
+  assert_eq!(counters.completed(), 3);
+
+  let counters = Counters::new();
+  run_sync(Probe::new(&counters));
+  assert_eq!(counters.completed(), 1);
+  EventState::set_inline_completion_limit(32);
+}
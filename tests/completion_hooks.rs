@@ -0,0 +1,122 @@
+// The installed hooks apply to the whole process, so this is the only test in
+// its binary.
+
+mod common;
+
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use miox::completion_port::CompletionPort;
+use miox::hooks::CompletionHooks;
+use miox::pool::{HandlerPool, PoolableEventHandler, Pooled};
+use miox::winapi::{OVERLAPPED, OVERLAPPED_ENTRY, STATUS_SUCCESS};
+use miox::EventState;
+
+use common::{Counters, Probe};
+
+fn dispatched(
+  port: &CompletionPort,
+  counters: &Arc<Counters>,
+) -> *mut OVERLAPPED {
+  let mut dispatch = port.dispatch(Probe::new(counters));
+  let overlapped = dispatch.overlapped();
+  dispatch.pending();
+  // What the kernel records when the operation succeeds.
+  unsafe {
+    (*overlapped).Internal = STATUS_SUCCESS as usize;
+    (*overlapped).InternalHigh = 0;
+  }
+  overlapped
+}
+
+struct Read {
+  state: EventState,
+  reads: usize,
+}
+
+impl PoolableEventHandler for Read {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(&mut self) {
+    self.reads += 1;
+  }
+
+  fn reset(&mut self) {}
+}
+
+#[test]
+fn installed_hooks_run_on_every_completion_path() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let hooked = Arc::new(AtomicUsize::new(0));
+  let mut hooks = CompletionHooks::new();
+  let count = hooked.clone();
+  hooks.register::<Probe, _>(move |info| {
+    assert_eq!(info.result.status, STATUS_SUCCESS);
+    count.fetch_add(1, Ordering::SeqCst);
+  });
+  let pooled = Arc::new(AtomicUsize::new(0));
+  let count = pooled.clone();
+  hooks.register::<Pooled<Read>, _>(move |_| {
+    count.fetch_add(1, Ordering::SeqCst);
+  });
+  EventState::set_completion_hooks(Some(hooks));
+
+  let overlapped = NonNull::new(dispatched(&port, &counters)).unwrap();
+  unsafe { EventState::complete(overlapped) };
+  assert_eq!(hooked.load(Ordering::SeqCst), 1);
+
+  let entries: Vec<OVERLAPPED_ENTRY> = (0..2)
+    .map(|_| OVERLAPPED_ENTRY {
+      lpCompletionKey: 0,
+      lpOverlapped: dispatched(&port, &counters),
+      Internal: 0,
+      dwNumberOfBytesTransferred: 0,
+    })
+    .collect();
+  unsafe {
+    EventState::complete_all(&entries, port.registry(), |_| {
+      panic!("foreign entry")
+    })
+  };
+  assert_eq!(hooked.load(Ordering::SeqCst), 3);
+
+  // Hooks passed in for a single completion run besides the installed ones.
+  let extra = Arc::new(AtomicUsize::new(0));
+  let mut one_off = CompletionHooks::new();
+  let count = extra.clone();
+  one_off.register::<Probe, _>(move |_| {
+    count.fetch_add(1, Ordering::SeqCst);
+  });
+  let overlapped = NonNull::new(dispatched(&port, &counters)).unwrap();
+  unsafe { EventState::complete_with_hooks(overlapped, &one_off) };
+  assert_eq!(extra.load(Ordering::SeqCst), 1);
+  assert_eq!(hooked.load(Ordering::SeqCst), 4);
+
+  // Pooled handlers that are re-armed right away are completed the same way.
+  let pool = HandlerPool::with_capacity(1);
+  let mut dispatch = pool.dispatch(pool.get_or_else(|| Read {
+    state: EventState::new(),
+    reads: 0,
+  }));
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  let rearmed = unsafe {
+    pool.complete_and_rearm(overlapped, |read, _| {
+      assert_eq!(read.reads, 1);
+      Err(())
+    })
+  };
+  assert_eq!(rearmed, Err(()));
+  assert_eq!(pooled.load(Ordering::SeqCst), 1);
+  assert_eq!(hooked.load(Ordering::SeqCst), 4);
+
+  EventState::set_completion_hooks(None);
+  let overlapped = NonNull::new(dispatched(&port, &counters)).unwrap();
+  unsafe { EventState::complete(overlapped) };
+  assert_eq!(hooked.load(Ordering::SeqCst), 4);
+  assert_eq!(counters.completed(), 5);
+}
@@ -0,0 +1,143 @@
+use std::io::{self, ErrorKind, Read};
+
+use miox::completion_kind::{status_to_io_error, CancelReason, CompletionKind};
+use miox::winapi::{
+  NTSTATUS, STATUS_CANCELLED, STATUS_CONNECTION_REFUSED, STATUS_END_OF_FILE,
+  STATUS_IO_TIMEOUT, STATUS_PIPE_BROKEN, STATUS_SUCCESS, STATUS_TIMEOUT,
+  STATUS_UNSUCCESSFUL,
+};
+use miox::IoResult;
+
+fn result(status: NTSTATUS, bytes_transferred: u32) -> IoResult {
+  IoResult {
+    status,
+    bytes_transferred,
+  }
+}
+
+#[test]
+fn successful_transfers_are_complete_or_partial() {
+  let full = result(STATUS_SUCCESS, 8);
+  assert!(matches!(
+    CompletionKind::of_read(full, Some(8)),
+    CompletionKind::Complete(8)
+  ));
+  assert!(matches!(
+    CompletionKind::of_write(full, None),
+    CompletionKind::Complete(8)
+  ));
+  let short = result(STATUS_SUCCESS, 3);
+  assert!(matches!(
+    CompletionKind::of_read(short, Some(8)),
+    CompletionKind::Partial(3)
+  ));
+  assert!(matches!(
+    CompletionKind::of_write(short, Some(8)),
+    CompletionKind::Partial(3)
+  ));
+}
+
+#[test]
+fn reads_detect_eof() {
+  for &status in &[STATUS_END_OF_FILE, STATUS_PIPE_BROKEN] {
+    assert!(matches!(
+      CompletionKind::of_read(result(status, 0), Some(8)),
+      CompletionKind::Eof
+    ));
+  }
+  let empty = result(STATUS_SUCCESS, 0);
+  assert!(matches!(
+    CompletionKind::of_read(empty, Some(8)),
+    CompletionKind::Eof
+  ));
+  assert!(matches!(
+    CompletionKind::of_read(empty, None),
+    CompletionKind::Eof
+  ));
+  // A zero-byte read only waits for the data to arrive.
+  assert!(matches!(
+    CompletionKind::of_read(empty, Some(0)),
+    CompletionKind::Complete(0)
+  ));
+  // Writes never end at EOF.
+  assert!(matches!(
+    CompletionKind::of_write(empty, Some(8)),
+    CompletionKind::Partial(0)
+  ));
+  match CompletionKind::of_write(result(STATUS_PIPE_BROKEN, 0), Some(8)) {
+    CompletionKind::Error(error) => {
+      assert_eq!(error.kind(), ErrorKind::BrokenPipe)
+    }
+    kind => panic!("{:?}", kind),
+  }
+}
+
+#[test]
+fn cancellations_carry_their_reason() {
+  assert!(matches!(
+    CompletionKind::of_read(result(STATUS_CANCELLED, 0), Some(8)),
+    CompletionKind::Cancelled(CancelReason::Cancelled)
+  ));
+  // Whether it was cancelled by its timer is up to the EventState to tell.
+  assert!(matches!(
+    CompletionKind::of_write(result(STATUS_CANCELLED, 0), Some(8)),
+    CompletionKind::Cancelled(CancelReason::Cancelled)
+  ));
+}
+
+#[test]
+fn statuses_are_classified_by_severity() {
+  // STATUS_TIMEOUT is informational: the operation went through.
+  assert!(matches!(
+    CompletionKind::of_read(result(STATUS_TIMEOUT, 8), Some(8)),
+    CompletionKind::Complete(8)
+  ));
+  assert!(matches!(
+    CompletionKind::of_write(result(STATUS_TIMEOUT, 3), Some(8)),
+    CompletionKind::Partial(3)
+  ));
+  // STATUS_BUFFER_OVERFLOW is a warning, which doesn't count as success.
+  let overflow = 0x8000_0005_u32 as NTSTATUS;
+  assert!(matches!(
+    CompletionKind::of_read(result(overflow, 8), Some(8)),
+    CompletionKind::Error(_)
+  ));
+}
+
+#[test]
+fn failures_are_io_errors() {
+  let kind_of = |status| match CompletionKind::of_read(result(status, 0), None)
+  {
+    CompletionKind::Error(error) => error.kind(),
+    kind => panic!("{:?}", kind),
+  };
+  assert_eq!(
+    kind_of(STATUS_CONNECTION_REFUSED),
+    ErrorKind::ConnectionRefused
+  );
+  assert_eq!(kind_of(STATUS_IO_TIMEOUT), ErrorKind::TimedOut);
+  assert_eq!(kind_of(STATUS_UNSUCCESSFUL), ErrorKind::Other);
+}
+
+#[test]
+fn cancelled_operations_are_not_retried() {
+  struct CancelledRead;
+
+  impl Read for CancelledRead {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+      Err(status_to_io_error(STATUS_CANCELLED))
+    }
+  }
+
+  // read_exact() gives up, rather than retrying forever.
+  let error = CancelledRead.read_exact(&mut [0; 8]).unwrap_err();
+  assert_eq!(error.kind(), ErrorKind::Other);
+  assert_eq!(
+    error.to_string(),
+    "operation was cancelled with status 0xc0000120"
+  );
+  assert_eq!(
+    status_to_io_error(STATUS_END_OF_FILE).kind(),
+    ErrorKind::UnexpectedEof
+  );
+}
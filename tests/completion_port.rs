@@ -0,0 +1,297 @@
+mod common;
+
+use std::mem::size_of_val;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr::NonNull;
+use std::time::Duration;
+
+use miox::completion_port::CompletionPort;
+use miox::winapi::STATUS_SUCCESS;
+use miox::{EventHandler, EventState, IoResult};
+
+use common::{Counters, Probe};
+
+const SUCCESS: IoResult = IoResult {
+  status: STATUS_SUCCESS,
+  bytes_transferred: 0,
+};
+
+// A read with a 64KB buffer, which is what it ties up while outstanding.
+struct BigRead {
+  state: EventState,
+  buf: Vec<u8>,
+}
+
+impl BigRead {
+  fn new() -> Box<Self> {
+    Box::new(Self {
+      state: EventState::new(),
+      buf: vec![0; 64 * 1024],
+    })
+  }
+}
+
+impl EventHandler for BigRead {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {}
+
+  fn resource_cost(&self) -> usize {
+    size_of_val(self) + self.buf.len()
+  }
+}
+
+#[test]
+fn try_dispatch_stays_within_the_byte_budget() {
+  let port = CompletionPort::new();
+  port.set_byte_budget(Some(100 * 1024));
+  let cost = BigRead::new().resource_cost();
+  assert!(cost > 64 * 1024);
+
+  let mut first = port.try_dispatch(BigRead::new()).ok().unwrap();
+  assert_eq!(port.bytes_outstanding(), cost);
+  // Another 64KB doesn't fit, so the handler comes back.
+  let second = match port.try_dispatch(BigRead::new()) {
+    Ok(_) => panic!("dispatched beyond the byte budget"),
+    Err(handler) => handler,
+  };
+  assert_eq!(port.bytes_outstanding(), cost);
+
+  let overlapped = NonNull::new(first.overlapped()).unwrap();
+  first.pending();
+  unsafe { port.complete_io(0, overlapped, SUCCESS) };
+  port.run_one(Some(Duration::from_secs(0))).unwrap();
+  assert_eq!(port.bytes_outstanding(), 0);
+
+  // With the first one done, there's room again.
+  let second = port.try_dispatch(second).ok().unwrap();
+  assert_eq!(port.bytes_outstanding(), cost);
+  let _ = second.failed();
+  assert_eq!(port.bytes_outstanding(), 0);
+}
+
+#[test]
+fn assert_no_leaks_lists_the_outstanding_operations() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let mut leaked = port.dispatch(Probe::new(&counters));
+  let line = line!() - 1;
+  let overlapped = NonNull::new(leaked.overlapped()).unwrap();
+  leaked.pending();
+
+  let panicked = catch_unwind(AssertUnwindSafe(|| port.assert_no_leaks()));
+  let payload = panicked.unwrap_err();
+  let message = payload.downcast_ref::<String>().unwrap();
+  assert!(
+    message.starts_with("1 dispatched operation(s)"),
+    "{}",
+    message
+  );
+  assert!(message.contains("common::Probe"), "{}", message);
+  let location = format!("{}:{}", file!(), line);
+  assert!(message.contains(&location), "{}", message);
+
+  unsafe { port.complete_io(0, overlapped, SUCCESS) };
+  port.run_one(Some(Duration::from_secs(0))).unwrap();
+  port.assert_no_leaks();
+  assert_eq!(counters.completed(), 1);
+}
+
+// Re-arms itself `remaining` more times, each of which completes right away.
+struct Echo {
+  state: EventState,
+  port: CompletionPort,
+  remaining: usize,
+}
+
+impl EventHandler for Echo {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {
+    if self.remaining == 0 {
+      return;
+    }
+    let next = Box::new(Echo {
+      state: EventState::new(),
+      port: self.port.clone(),
+      remaining: self.remaining - 1,
+    });
+    let mut dispatch = self.port.dispatch(next);
+    let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+    dispatch.pending();
+    unsafe { self.port.complete_io(0, overlapped, SUCCESS) };
+  }
+}
+
+#[test]
+fn run_until_idle_runs_completions_queued_along_the_way() {
+  let counters = Counters::new();
+  let mut port = CompletionPort::new();
+  for _ in 0..2 {
+    let mut dispatch = port.dispatch(Probe::new(&counters));
+    let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+    dispatch.pending();
+    unsafe { port.complete_io(0, overlapped, SUCCESS) };
+  }
+  let echo = Box::new(Echo {
+    state: EventState::new(),
+    port: port.clone(),
+    remaining: 3,
+  });
+  let mut dispatch = port.dispatch(echo);
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  unsafe { port.complete_io(0, overlapped, SUCCESS) };
+
+  let mut ran = 0;
+  port.run_until_idle(|_| ran += 1);
+  // Two probes, and the echo with its three re-arms.
+  assert_eq!(ran, 6);
+  assert_eq!(counters.completed(), 2);
+  port.assert_no_leaks();
+  // Nothing is left, so this returns right away.
+  port.run_until_idle(|_| unreachable!());
+}
+
+// Only the mock RawHandle can be made up out of thin air.
+#[cfg(not(windows))]
+#[test]
+fn report_orphans_lists_exactly_the_outstanding_operations() {
+  use std::any::TypeId;
+
+  use miox::raw::RawHandle;
+
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let handle = RawHandle::from_raw(0x1234);
+  let mut first = port.dispatch_on(handle, Probe::new(&counters));
+  let first_line = line!() - 1;
+  let outstanding = NonNull::new(first.overlapped()).unwrap();
+  first.pending();
+  let mut done = port.dispatch(Probe::new(&counters));
+  let overlapped = NonNull::new(done.overlapped()).unwrap();
+  done.pending();
+  let second = port.dispatch(BigRead::new());
+  let second_line = line!() - 1;
+
+  unsafe { port.complete_io(0, overlapped, SUCCESS) };
+  port.run_one(Some(Duration::from_secs(0))).unwrap();
+
+  let orphans = port.report_orphans();
+  assert_eq!(orphans.len(), 2);
+  // Oldest first.
+  let (probe, read) = (&orphans[0], &orphans[1]);
+  assert_eq!(probe.type_id, TypeId::of::<Probe>());
+  assert!(probe.type_name.ends_with("common::Probe"));
+  assert_eq!(probe.handle, Some(handle));
+  assert_eq!(
+    (probe.location.file(), probe.location.line()),
+    (file!(), first_line)
+  );
+  assert_eq!(read.type_id, TypeId::of::<BigRead>());
+  assert_eq!(read.handle, None);
+  assert_eq!(read.location.line(), second_line);
+  assert!(probe.dispatched_at <= read.dispatched_at);
+  assert!(probe.age >= read.age);
+  // Reporting leaves them outstanding.
+  assert_eq!(port.report_orphans().len(), 2);
+
+  let _ = second.failed();
+  let orphans = port.report_orphans();
+  assert_eq!(orphans.len(), 1);
+  assert_eq!(orphans[0].type_id, TypeId::of::<Probe>());
+
+  unsafe { port.complete_io(0, outstanding, SUCCESS) };
+  port.run_one(Some(Duration::from_secs(0))).unwrap();
+  assert!(port.report_orphans().is_empty());
+}
+
+#[test]
+fn drain_handlers_returns_exactly_the_outstanding_handlers() {
+  use miox::completion_port::StatsSnapshot;
+
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let mut dispatched = Vec::new();
+  for _ in 0..3 {
+    let probe = Probe::new(&counters);
+    let address = &*probe as *const Probe as usize;
+    let mut dispatch = port.dispatch(probe);
+    let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+    dispatch.pending();
+    dispatched.push((address, overlapped));
+  }
+  // The first completes as usual; the second's packet is queued, but never
+  // dequeued; the third is still in progress.
+  unsafe { port.complete_io(0, dispatched[0].1, SUCCESS) };
+  port.run_one(Some(Duration::from_secs(0))).unwrap();
+  unsafe { port.complete_io(0, dispatched[1].1, SUCCESS) };
+
+  let drained = unsafe { port.drain_handlers() };
+  let mut addresses: Vec<_> = drained
+    .iter()
+    .map(|handler| &**handler as *const dyn EventHandler as *const () as usize)
+    .collect();
+  addresses.sort_unstable();
+  let mut outstanding = vec![dispatched[1].0, dispatched[2].0];
+  outstanding.sort_unstable();
+  assert_eq!(addresses, outstanding);
+  assert!(port.registry().is_empty());
+  port.assert_no_leaks();
+  // The queued packet went with its operation.
+  assert!(port.run_one(Some(Duration::from_secs(0))).is_none());
+
+  // The drained handlers are handed back alive, and counted as cancelled.
+  assert_eq!(counters.completed(), 1);
+  assert_eq!(counters.freed(), 1);
+  let stats = port.stats().snapshot();
+  assert_eq!(
+    stats,
+    StatsSnapshot {
+      submitted: 3,
+      completed: 1,
+      failed: 0,
+      cancelled: 2,
+    }
+  );
+  assert_eq!(stats.outstanding(), 0);
+  drop(drained);
+  assert_eq!(counters.freed(), 1);
+}
+
+#[test]
+fn stats_count_operations_that_never_complete() {
+  use miox::completion_port::StatsSnapshot;
+  use miox::container_of::ContainerOf;
+
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  // Unwinding from a panic before the OS call: the operation never started.
+  let panicked = catch_unwind(AssertUnwindSafe(|| {
+    let _dispatch = port.dispatch(Probe::new(&counters));
+    panic!("before the OS call");
+  }));
+  assert!(panicked.is_err());
+
+  // Taken out of its EventState while in progress: given up on.
+  let mut dispatch = port.dispatch(Probe::new(&counters));
+  let overlapped = dispatch.overlapped();
+  dispatch.pending();
+  let state = unsafe { EventState::container_of_mut(&mut *overlapped) };
+  let handler = state.detach_event_handler().unwrap();
+
+  assert_eq!(
+    port.stats().snapshot(),
+    StatsSnapshot {
+      submitted: 2,
+      completed: 0,
+      failed: 1,
+      cancelled: 1,
+    }
+  );
+  drop(handler);
+}
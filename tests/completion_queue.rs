@@ -0,0 +1,95 @@
+mod common;
+
+use std::ptr::NonNull;
+use std::thread;
+
+use miox::completion_queue::CompletionQueue;
+use miox::winapi::STATUS_SUCCESS;
+use miox::{Dispatchable, EventState};
+
+use common::{Counters, Probe};
+
+const PRODUCERS: u32 = 2;
+const CONSUMERS: u32 = 3;
+const PER_PRODUCER: u32 = 300;
+
+#[test]
+fn completions_cross_threads_exactly_once() {
+  let counters = Counters::new();
+  // Much smaller than the number of completions, so producers have to wait
+  // for the consumers.
+  let queue = CompletionQueue::with_capacity(4);
+
+  let producers: Vec<_> = (0..PRODUCERS)
+    .map(|p| {
+      let counters = counters.clone();
+      let queue = queue.clone();
+      thread::spawn(move || {
+        for i in 0..PER_PRODUCER {
+          let mut dispatch = Probe::new(&counters).dispatch();
+          let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+          dispatch.pending();
+          // Stands in for the kernel recording the outcome; the byte count
+          // tells the completions apart.
+          unsafe {
+            (*overlapped.as_ptr()).Internal = STATUS_SUCCESS as usize;
+            (*overlapped.as_ptr()).InternalHigh =
+              (p * PER_PRODUCER + i) as usize;
+          }
+          queue
+            .push(unsafe { EventState::take_completion(overlapped) }.unwrap());
+        }
+      })
+    })
+    .collect();
+
+  let total = PRODUCERS * PER_PRODUCER;
+  let consumers: Vec<_> = (0..CONSUMERS)
+    .map(|_| {
+      let queue = queue.clone();
+      thread::spawn(move || {
+        for _ in 0..total / CONSUMERS {
+          queue.pop().finish();
+        }
+      })
+    })
+    .collect();
+
+  for thread in producers.into_iter().chain(consumers) {
+    thread.join().unwrap();
+  }
+  assert!(queue.is_empty());
+
+  let mut seen: Vec<u32> = counters
+    .results()
+    .iter()
+    .map(|result| result.bytes_transferred)
+    .collect();
+  seen.sort_unstable();
+  assert_eq!(seen, (0..total).collect::<Vec<_>>());
+  assert_eq!(counters.completed(), total as usize);
+  assert_eq!(counters.freed(), total as usize);
+}
+
+#[test]
+fn try_push_hands_the_completion_back_when_full() {
+  let counters = Counters::new();
+  let queue = CompletionQueue::with_capacity(1);
+  let take = || {
+    let mut dispatch = Probe::new(&counters).dispatch();
+    let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+    dispatch.pending();
+    unsafe { EventState::take_completion(overlapped) }.unwrap()
+  };
+
+  assert!(queue.try_push(take()).is_ok());
+  let rejected = queue.try_push(take()).expect_err("queue isn't full");
+  assert_eq!(queue.len(), 1);
+  // The dequeue thread completes what didn't fit inline.
+  rejected.finish();
+  assert_eq!(counters.completed(), 1);
+
+  queue.try_pop().unwrap().finish();
+  assert!(queue.try_pop().is_none());
+  assert_eq!(counters.completed(), 2);
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use std::ptr::NonNull;
+use std::sync::mpsc::{channel, sync_channel};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use miox::iocp::CompletionSink;
+use miox::winapi::OVERLAPPED;
+use miox::{Dispatchable, EventHandler, EventState};
+
+use common::{Counters, Probe};
+
+fn dispatched(counters: &Arc<Counters>) -> NonNull<OVERLAPPED> {
+  let mut dispatch = Probe::new(counters).dispatch();
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  overlapped
+}
+
+#[test]
+fn complete_to_a_channel_completes_on_the_receiving_thread() {
+  let counters = Counters::new();
+  let (tx, rx) = channel::<Box<dyn EventHandler>>();
+  let worker = thread::spawn(move || {
+    for handler in rx {
+      handler.complete();
+    }
+  });
+  for _ in 0..3 {
+    let overlapped = dispatched(&counters);
+    unsafe { EventState::complete_to(overlapped, &tx) };
+  }
+  drop(tx);
+  worker.join().unwrap();
+  assert_eq!(counters.completed(), 3);
+}
+
+#[test]
+fn complete_to_a_sync_channel() {
+  let counters = Counters::new();
+  let (tx, rx) = sync_channel::<Box<dyn EventHandler>>(1);
+  unsafe { EventState::complete_to(dispatched(&counters), &tx) };
+  assert_eq!(counters.completed(), 0);
+  rx.recv().unwrap().complete();
+  assert_eq!(counters.completed(), 1);
+}
+
+#[test]
+fn complete_to_a_closure() {
+  let counters = Counters::new();
+  let posted = Mutex::new(Vec::new());
+  let sink =
+    |handler: Box<dyn EventHandler>| posted.lock().unwrap().push(handler);
+  unsafe { EventState::complete_to(dispatched(&counters), &sink) };
+  // Also as a trait object.
+  let sink: &dyn CompletionSink = &sink;
+  unsafe { EventState::complete_to(dispatched(&counters), sink) };
+
+  let posted = posted.into_inner().unwrap();
+  assert_eq!(posted.len(), 2);
+  posted.into_iter().for_each(EventHandler::complete);
+  assert_eq!(counters.completed(), 2);
+}
+
+#[test]
+fn complete_via_channel_delivers_the_handler_to_the_receiver() {
+  let counters = Counters::new();
+  let probe = Probe::new(&counters);
+  let address = &*probe as *const Probe as *const ();
+  let mut dispatch = probe.dispatch();
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+
+  let (tx, rx) = channel();
+  unsafe { EventState::complete_via_channel(overlapped, &tx) }.unwrap();
+  let handler = rx.try_recv().unwrap();
+  assert!(std::ptr::eq(
+    &*handler as *const dyn EventHandler as *const (),
+    address
+  ));
+  assert_eq!(counters.completed(), 0);
+  handler.complete();
+  assert_eq!(counters.completed(), 1);
+}
+
+#[test]
+fn complete_via_channel_without_a_receiver_hands_the_handler_back() {
+  let counters = Counters::new();
+  let (tx, rx) = channel();
+  drop(rx);
+  let returned =
+    unsafe { EventState::complete_via_channel(dispatched(&counters), &tx) }
+      .unwrap_err()
+      .0;
+  assert_eq!(Arc::strong_count(&counters), 2);
+  // Dropping it is all it takes to free it; nothing is left behind.
+  drop(returned);
+  assert_eq!(Arc::strong_count(&counters), 1);
+  assert_eq!(counters.completed(), 0);
+}
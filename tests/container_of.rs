@@ -0,0 +1,58 @@
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use miox::container_of::CastNonNull;
+
+#[test]
+fn box_round_trips_through_non_null() {
+  let ptr = Box::new(7u32).into_non_null();
+  // Box is getting an inherent `from_non_null()` of its own.
+  let boxed: Box<u32> = unsafe { CastNonNull::from_non_null(ptr) };
+  assert_eq!(*boxed, 7);
+}
+
+#[test]
+fn arc_round_trip_keeps_the_reference_count() {
+  let arc = Arc::new(Mutex::new(7u32));
+  let ptr = arc.clone().into_non_null();
+  // The pointer holds on to the reference it was made from.
+  assert_eq!(Arc::strong_count(&arc), 2);
+  assert!(std::ptr::eq(ptr.as_ptr(), &*arc));
+
+  let back = unsafe { Arc::from_non_null(ptr) };
+  assert_eq!(Arc::strong_count(&arc), 2);
+  *back.lock().unwrap() += 1;
+  drop(back);
+  assert_eq!(Arc::strong_count(&arc), 1);
+  assert_eq!(*arc.lock().unwrap(), 8);
+}
+
+#[test]
+fn rc_round_trip_keeps_the_reference_count() {
+  let rc = Rc::new(7u32);
+  let ptr = rc.clone().into_non_null();
+  assert_eq!(Rc::strong_count(&rc), 2);
+
+  let back = unsafe { Rc::from_non_null(ptr) };
+  assert_eq!(*back, 7);
+  drop(back);
+  assert_eq!(Rc::strong_count(&rc), 1);
+}
+
+#[test]
+fn event_state_overlapped_is_at_offset_zero() {
+  use miox::container_of::ContainerOf;
+  use miox::winapi::OVERLAPPED;
+  use miox::EventState;
+
+  assert_eq!(
+    unsafe { <EventState as ContainerOf<OVERLAPPED>>::member_offset() },
+    0
+  );
+  let state = EventState::new();
+  let overlapped = state.member();
+  assert!(std::ptr::eq(
+    unsafe { EventState::container_of(overlapped) },
+    &state
+  ));
+}
@@ -0,0 +1,22 @@
+use miox::{Dispatchable, EventHandler, EventState};
+
+struct Noop {
+  state: EventState,
+}
+
+impl EventHandler for Noop {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {}
+}
+
+#[test]
+fn dispatch_from_crate_root() {
+  let handler = Box::new(Noop {
+    state: EventState::new(),
+  });
+  let dispatch = handler.dispatch();
+  let _handler: Box<Noop> = dispatch.failed();
+}
@@ -0,0 +1,77 @@
+use std::ptr::NonNull;
+use std::sync::mpsc::{channel, Sender};
+
+use miox::completion_port::CompletionPort;
+use miox::iocp::{decode_and_complete, DecodingEventHandler};
+use miox::winapi::{OVERLAPPED_ENTRY, STATUS_SUCCESS};
+use miox::{EventHandler, EventState, IoResult};
+
+// What a datagram protocol wants to know about a receive.
+#[derive(Debug, PartialEq)]
+struct Datagram {
+  port: usize,
+  len: u32,
+}
+
+struct Receive {
+  state: EventState,
+  done: Sender<Datagram>,
+}
+
+impl EventHandler for Receive {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {
+    unreachable!("completed through decode_dyn()")
+  }
+
+  fn decode_dyn(self: Box<Self>, raw: &OVERLAPPED_ENTRY) {
+    decode_and_complete(self, raw)
+  }
+}
+
+impl DecodingEventHandler for Receive {
+  type Completion = Datagram;
+
+  fn decode(raw: &OVERLAPPED_ENTRY) -> Datagram {
+    Datagram {
+      port: raw.lpCompletionKey,
+      len: raw.dwNumberOfBytesTransferred,
+    }
+  }
+
+  fn complete_with(self: Box<Self>, completion: Datagram) {
+    self.done.send(completion).unwrap();
+  }
+}
+
+#[test]
+fn handler_decodes_its_own_completion() {
+  let (tx, rx) = channel();
+  let port = CompletionPort::new();
+  let receive = Box::new(Receive {
+    state: EventState::new(),
+    done: tx,
+  });
+  let mut dispatch = port.dispatch(receive);
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 28,
+  };
+  unsafe { port.complete_io(5353, overlapped, result) };
+  // `run_one()` hands the dequeued packet to the handler's `decode_dyn()`.
+  port.run_one(None).unwrap();
+  assert_eq!(
+    rx.try_recv().unwrap(),
+    Datagram {
+      port: 5353,
+      len: 28,
+    }
+  );
+  port.assert_no_leaks();
+}
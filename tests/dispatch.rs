@@ -0,0 +1,310 @@
+mod common;
+
+use std::any::TypeId;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr::NonNull;
+
+use miox::completion_port::CompletionPort;
+use miox::iocp::Dispatch;
+use miox::{Dispatchable, EventHandler, EventState};
+
+use common::{Counters, Probe};
+
+#[test]
+fn dispatch_dropped_while_panicking_reclaims_the_handler() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let panicked = catch_unwind(AssertUnwindSafe(|| {
+    let _dispatch = port.dispatch(Probe::new(&counters));
+    panic!("before the OS call");
+  }));
+  assert!(panicked.is_err());
+  // The handler was dropped rather than leaked, without being completed.
+  assert_eq!(counters.freed(), 1);
+  assert_eq!(counters.completed(), 0);
+  port.assert_no_leaks();
+}
+
+#[test]
+fn type_id_survives_erasure() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let dispatch = port.dispatch(Probe::new(&counters));
+  assert_eq!(dispatch.type_id(), TypeId::of::<Probe>());
+  let erased: Dispatch<dyn EventHandler> = dispatch.into_dyn();
+  assert_eq!(erased.type_id(), TypeId::of::<Probe>());
+  let _ = erased.failed_dyn();
+  port.assert_no_leaks();
+}
+
+#[test]
+fn is_pending_until_settled() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let dispatch = port.dispatch(Probe::new(&counters));
+  assert!(dispatch.is_pending());
+  let _ = dispatch.failed();
+  assert!(!Dispatch::<Probe>::default().is_pending());
+  port.assert_no_leaks();
+}
+
+#[test]
+fn type_id_of_a_placeholder() {
+  let placeholder = Dispatch::<Probe>::default();
+  assert_eq!(placeholder.type_id(), TypeId::of::<Probe>());
+  let erased = Dispatch::<dyn EventHandler>::default();
+  assert_eq!(erased.type_id(), TypeId::of::<dyn EventHandler>());
+}
+
+#[test]
+fn type_id_of_an_erased_dispatch_outlives_the_handler() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let mut erased = port.dispatch(Probe::new(&counters)).into_dyn();
+  let overlapped = NonNull::new(erased.overlapped()).unwrap();
+  // The operation completes, and its handler is freed, before the guard is
+  // settled.
+  unsafe { EventState::complete(overlapped) };
+  assert_eq!(counters.completed(), 1);
+  assert_eq!(erased.type_id(), TypeId::of::<Probe>());
+  erased.pending();
+  port.assert_no_leaks();
+}
+
+#[test]
+fn into_overlapped_completes_through_event_state() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let overlapped = port.dispatch(Probe::new(&counters)).into_overlapped();
+  // The guard is gone, but the operation is still outstanding.
+  assert_eq!(counters.freed(), 0);
+  assert_eq!(port.registry().len(), 1);
+
+  // Foreign code hands the pointer back once it's done.
+  unsafe { EventState::complete(NonNull::new(overlapped).unwrap()) };
+  assert_eq!(counters.completed(), 1);
+  assert_eq!(counters.freed(), 1);
+  port.assert_no_leaks();
+}
+
+#[test]
+fn reattached_dispatch_hands_back_the_original_handler() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let probe = Probe::new(&counters);
+  let address = &*probe as *const Probe;
+  let overlapped = port.dispatch(probe).into_overlapped();
+
+  // Later, a callback learns that the OS call didn't go through after all.
+  let dispatch = unsafe { Dispatch::<Probe>::reattach(overlapped) };
+  let handler = dispatch.failed();
+  assert!(std::ptr::eq(&*handler, address));
+  assert_eq!(counters.completed(), 0);
+  port.assert_no_leaks();
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn reattach_checks_the_handler_type() {
+  struct Other {
+    state: EventState,
+  }
+
+  impl EventHandler for Other {
+    fn state(&mut self) -> &mut EventState {
+      &mut self.state
+    }
+
+    fn complete(self: Box<Self>) {}
+  }
+
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let overlapped = port.dispatch(Probe::new(&counters)).into_overlapped();
+  let panicked =
+    catch_unwind(|| unsafe { Dispatch::<Other>::reattach(overlapped) });
+  let message = *panicked.err().unwrap().downcast::<String>().unwrap();
+  assert!(message.starts_with("reattaching a "), "{}", message);
+  assert!(message.ends_with("::Other"), "{}", message);
+
+  let _ = unsafe { Dispatch::<Probe>::reattach(overlapped) }.failed();
+  port.assert_no_leaks();
+}
+
+#[test]
+fn failed_with_hands_the_handler_to_the_closure() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let probe = Probe::new(&counters);
+  let address = &*probe as *const Probe;
+  let mut pool = Vec::new();
+  port
+    .dispatch(probe)
+    .failed_with(|handler| pool.push(handler));
+
+  assert_eq!(pool.len(), 1);
+  assert!(std::ptr::eq(&*pool[0], address));
+  // Settled: the guard went away without panicking, and nothing is left
+  // outstanding or was completed.
+  assert!(port.registry().is_empty());
+  assert_eq!(counters.completed(), 0);
+  assert_eq!(counters.freed(), 1);
+  // The handler was torn down once, when it was handed back; dropping it
+  // doesn't do that again.
+  drop(pool);
+  assert_eq!(counters.freed(), 1);
+  port.assert_no_leaks();
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn dispatch_checks_that_state_is_stable() {
+  // Hands out one of two EventStates, taking turns.
+  struct Fickle {
+    states: [EventState; 2],
+    next: usize,
+  }
+
+  impl EventHandler for Fickle {
+    fn state(&mut self) -> &mut EventState {
+      self.next ^= 1;
+      &mut self.states[self.next]
+    }
+
+    fn complete(self: Box<Self>) {}
+  }
+
+  let fickle = Box::new(Fickle {
+    states: [EventState::new(), EventState::new()],
+    next: 0,
+  });
+  let panicked = catch_unwind(AssertUnwindSafe(|| fickle.dispatch()));
+  let message = *panicked.err().unwrap().downcast::<String>().unwrap();
+  assert!(
+    message.ends_with("::Fickle::state() doesn't return a stable EventState"),
+    "{}",
+    message
+  );
+}
+
+#[test]
+fn into_dyn_keeps_dispatches_of_different_types_together() {
+  struct Other {
+    state: EventState,
+  }
+
+  impl EventHandler for Other {
+    fn state(&mut self) -> &mut EventState {
+      &mut self.state
+    }
+
+    fn complete(self: Box<Self>) {}
+  }
+
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let probe = Probe::new(&counters);
+  let address = &*probe as *const Probe as *const ();
+  let mut dispatches: Vec<Dispatch<dyn EventHandler>> = vec![
+    port.dispatch(probe).into_dyn(),
+    port
+      .dispatch(Box::new(Other {
+        state: EventState::new(),
+      }))
+      .into_dyn(),
+  ];
+  assert!(dispatches.iter().all(Dispatch::is_dispatched));
+  assert_eq!(dispatches[0].type_id(), TypeId::of::<Probe>());
+  assert_eq!(dispatches[1].type_id(), TypeId::of::<Other>());
+
+  // The OS call for the Other went through; the one for the Probe didn't.
+  let overlapped = NonNull::new(dispatches[1].overlapped()).unwrap();
+  dispatches.pop().unwrap().pending();
+  let handler = dispatches.pop().unwrap().failed_dyn();
+  assert!(std::ptr::eq(
+    &*handler as *const dyn EventHandler as *const (),
+    address
+  ));
+  assert!(!Dispatch::<dyn EventHandler>::default().is_dispatched());
+  assert_eq!(port.registry().len(), 1);
+
+  unsafe { EventState::complete(overlapped) };
+  assert_eq!(counters.completed(), 0);
+  port.assert_no_leaks();
+}
+
+#[test]
+fn map_keeps_the_operation_registered_on_its_port() {
+  use std::time::Duration;
+
+  use miox::completion_port::StatsSnapshot;
+  use miox::container_of::ContainerOf;
+  use miox::winapi::STATUS_SUCCESS;
+  use miox::IoResult;
+
+  // Wraps a Probe, and completes it when it's completed itself.
+  struct Wrapper {
+    state: EventState,
+    inner: Box<Probe>,
+  }
+
+  impl EventHandler for Wrapper {
+    fn state(&mut self) -> &mut EventState {
+      &mut self.state
+    }
+
+    fn complete(self: Box<Self>) {
+      let mut inner = self.inner;
+      let result = self.state.result();
+      unsafe { miox::iocp::write_overlapped_result(&mut *inner.state, result) };
+      inner.complete();
+    }
+  }
+
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let mut dispatch = port.dispatch(Probe::new(&counters));
+  let line = line!() - 1;
+  let sequence =
+    unsafe { EventState::container_of(&*dispatch.overlapped()) }.sequence();
+
+  let mut mapped = dispatch.map(|inner| {
+    Box::new(Wrapper {
+      state: EventState::new(),
+      inner,
+    })
+  });
+  let overlapped = NonNull::new(mapped.overlapped()).unwrap();
+  let state = unsafe { EventState::container_of(&*overlapped.as_ptr()) };
+  assert_eq!(state.sequence(), sequence);
+  // Still one operation, now of the new type, dispatched where it was.
+  let orphans = port.report_orphans();
+  assert_eq!(orphans.len(), 1);
+  assert_eq!(orphans[0].type_id, TypeId::of::<Wrapper>());
+  assert_eq!(orphans[0].location.line(), line);
+  assert_eq!(
+    port.stats().snapshot(),
+    StatsSnapshot {
+      submitted: 1,
+      ..Default::default()
+    }
+  );
+
+  mapped.pending();
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 5,
+  };
+  unsafe { port.complete_io(0, overlapped, result) };
+  port.run_one(Some(Duration::from_secs(0))).unwrap();
+  assert_eq!(counters.results(), vec![result]);
+  assert_eq!(
+    port.stats().snapshot(),
+    StatsSnapshot {
+      submitted: 1,
+      completed: 1,
+      ..Default::default()
+    }
+  );
+  port.assert_no_leaks();
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+use miox::completion_port::{CompletionPort, StatsSnapshot};
+use miox::dispatch_batch::DispatchBatch;
+use miox::winapi::{OVERLAPPED, STATUS_SUCCESS};
+use miox::IoResult;
+
+use common::{Counters, Probe};
+
+const SUCCESS: IoResult = IoResult {
+  status: STATUS_SUCCESS,
+  bytes_transferred: 0,
+};
+
+fn batch_of(
+  port: &CompletionPort,
+  counters: &Arc<Counters>,
+  len: usize,
+) -> DispatchBatch<Probe> {
+  let mut batch = DispatchBatch::new();
+  for _ in 0..len {
+    batch.push(port.dispatch(Probe::new(counters)));
+  }
+  batch
+}
+
+#[test]
+fn committed_batches_complete_as_usual() {
+  let counters = Counters::new();
+  let mut port = CompletionPort::new();
+  let mut batch = batch_of(&port, &counters, 3);
+  assert_eq!(batch.len(), 3);
+  let overlappeds = batch.overlappeds();
+  batch.commit_all_pending();
+  assert_eq!(port.registry().len(), 3);
+
+  for overlapped in overlappeds {
+    let overlapped = NonNull::new(overlapped).unwrap();
+    unsafe { port.complete_io(0, overlapped, SUCCESS) };
+  }
+  port.run_until_idle(|_| {});
+  assert_eq!((counters.completed(), counters.freed()), (3, 3));
+  port.assert_no_leaks();
+}
+
+#[test]
+fn rolled_back_batches_hand_back_every_handler_in_order() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let mut batch = batch_of(&port, &counters, 3);
+  let overlappeds = batch.overlappeds();
+  let mut handlers = batch.rollback_all_failed();
+  assert_eq!(handlers.len(), 3);
+  for (handler, overlapped) in handlers.iter_mut().zip(overlappeds) {
+    let state: &mut OVERLAPPED = &mut handler.state;
+    assert_eq!(state as *mut OVERLAPPED, overlapped);
+  }
+  assert_eq!((counters.completed(), counters.freed()), (0, 3));
+  assert!(port.registry().is_empty());
+  port.assert_no_leaks();
+}
+
+#[test]
+fn batches_on_one_port_settle_independently() {
+  let counters = Counters::new();
+  let mut port = CompletionPort::new();
+  let mut started = batch_of(&port, &counters, 2);
+  let failed = batch_of(&port, &counters, 3);
+  let overlappeds = started.overlappeds();
+  started.commit_all_pending();
+  assert_eq!(failed.rollback_all_failed().len(), 3);
+  for overlapped in overlappeds {
+    let overlapped = NonNull::new(overlapped).unwrap();
+    unsafe { port.complete_io(0, overlapped, SUCCESS) };
+  }
+  port.run_until_idle(|_| {});
+
+  assert_eq!(counters.completed(), 2);
+  assert_eq!(
+    port.stats().snapshot(),
+    StatsSnapshot {
+      submitted: 5,
+      completed: 2,
+      failed: 3,
+      cancelled: 0,
+    }
+  );
+  port.assert_no_leaks();
+}
+
+#[test]
+fn empty_batches_need_no_settling() {
+  drop(DispatchBatch::<Probe>::new());
+}
+
+#[test]
+#[should_panic(
+  expected = "DispatchBatch of 2 dispatch(es) dropped without being settled"
+)]
+fn unsettled_batches_panic_when_dropped() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  drop(batch_of(&port, &counters, 2));
+}
+
+#[test]
+fn unsettled_batches_are_abandoned_while_unwinding() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let panicked = catch_unwind(AssertUnwindSafe(|| {
+    let _batch = batch_of(&port, &counters, 2);
+    panic!("before the OS calls");
+  }));
+  assert!(panicked.is_err());
+  assert_eq!((counters.completed(), counters.freed()), (0, 2));
+  port.assert_no_leaks();
+}
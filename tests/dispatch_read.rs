@@ -0,0 +1,65 @@
+use std::ptr::NonNull;
+use std::sync::mpsc::{channel, Sender};
+
+use miox::iocp::dispatch_read;
+use miox::winapi::{OVERLAPPED, STATUS_SUCCESS};
+use miox::{EventHandler, EventState};
+
+struct Read {
+  state: EventState,
+  buf: Vec<u8>,
+  done: Sender<Vec<u8>>,
+}
+
+impl Read {
+  fn new(len: usize, done: &Sender<Vec<u8>>) -> Box<Self> {
+    Box::new(Self {
+      state: EventState::new(),
+      buf: vec![0; len],
+      done: done.clone(),
+    })
+  }
+}
+
+impl EventHandler for Read {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {
+    let len = self.state.result().bytes_transferred as usize;
+    self.done.send(self.buf[..len].to_vec()).unwrap();
+  }
+
+  fn io_buffer(&mut self) -> Option<(*mut u8, usize)> {
+    Some((self.buf.as_mut_ptr(), self.buf.len()))
+  }
+}
+
+#[test]
+fn dispatch_read_reads_into_the_handlers_buffer() {
+  let (tx, rx) = channel();
+  let mut issued = None;
+  let started = dispatch_read(Read::new(8, &tx), |buf, len, overlapped| {
+    assert_eq!(len, 8);
+    // Stands in for a ReadFile() that finishes right away.
+    unsafe { buf.copy_from_nonoverlapping(b"hello".as_ptr(), 5) };
+    issued = NonNull::new(overlapped);
+    true
+  });
+  assert!(started.is_ok());
+
+  let overlapped = issued.unwrap();
+  unsafe { EventState::complete_sync(overlapped, 5, STATUS_SUCCESS) };
+  assert_eq!(rx.try_recv().unwrap(), b"hello");
+}
+
+#[test]
+fn dispatch_read_hands_back_the_handler_on_failure() {
+  let (tx, rx) = channel();
+  let started =
+    dispatch_read(Read::new(8, &tx), |_, _, _: *mut OVERLAPPED| false);
+  let handler = started.err().unwrap();
+  assert_eq!(handler.buf.len(), 8);
+  assert!(rx.try_recv().is_err());
+}
@@ -0,0 +1,27 @@
+// Code written against the name the Dispatchable trait had before 0.2 must
+// keep compiling.
+#![allow(deprecated)]
+
+use miox::iocp::EventDispatch;
+use miox::{EventHandler, EventState};
+
+struct Noop {
+  state: EventState,
+}
+
+impl EventHandler for Noop {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {}
+}
+
+#[test]
+fn dispatch_through_the_old_trait_name() {
+  let handler = Box::new(Noop {
+    state: EventState::new(),
+  });
+  let dispatch = handler.dispatch();
+  let _handler: Box<Noop> = dispatch.failed();
+}
@@ -0,0 +1,45 @@
+use std::ptr::NonNull;
+use std::sync::mpsc::{channel, Sender};
+
+use miox::winapi::STATUS_SUCCESS;
+use miox::{event_handler, Dispatchable, EventHandler, EventState};
+
+struct PipeRead {
+  done: Sender<(u64, u32)>,
+  // Deliberately not the first field.
+  inner_state: EventState,
+}
+
+event_handler! {
+  PipeRead { inner_state } => {
+    fn complete(self: Box<Self>) {
+      let result = self.inner_state.result();
+      let offset = self.inner_state.get_file_offset();
+      self.done.send((offset, result.bytes_transferred)).unwrap();
+    }
+
+    fn size_hint(&self) -> usize {
+      16
+    }
+  }
+}
+
+#[test]
+fn event_handler_macro_wires_up_the_state_field() {
+  let (tx, rx) = channel();
+  let mut read = Box::new(PipeRead {
+    done: tx,
+    inner_state: EventState::new(),
+  });
+  let field: *const EventState = &read.inner_state;
+  assert!(std::ptr::eq(read.state(), field));
+  read.state().set_file_offset(1 << 32);
+  // The other trait items are passed through.
+  assert_eq!(read.size_hint(), 16);
+
+  let mut dispatch = read.dispatch();
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  unsafe { EventState::complete_sync(overlapped, 7, STATUS_SUCCESS) };
+  assert_eq!(rx.try_recv().unwrap(), (1 << 32, 7));
+}
@@ -0,0 +1,237 @@
+mod common;
+
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+use miox::completion_port::CompletionPort;
+use miox::container_of::ContainerOf;
+use miox::iocp::read_overlapped_result;
+use miox::winapi::{OVERLAPPED, STATUS_SUCCESS};
+use miox::{Dispatchable, EventHandler, EventState, IoResult};
+
+use common::{Counters, Probe};
+
+#[test]
+fn detach_event_handler_leaves_the_state_idle() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let mut dispatch = port.dispatch(Probe::new(&counters));
+  let overlapped = dispatch.overlapped();
+  dispatch.pending();
+
+  let state = unsafe { EventState::container_of_mut(&mut *overlapped) };
+  let handler = state.detach_event_handler().expect("no handler embedded");
+  assert!(state.detach_event_handler().is_none());
+  assert!(port.registry().is_empty());
+  // The handler owns the state, so the OVERLAPPED is still there to look at,
+  // but there's nothing left to complete.
+  assert!(!unsafe { EventState::complete_or_ignore(overlapped) });
+
+  drop(handler);
+  assert_eq!(counters.completed(), 0);
+}
+
+#[test]
+fn read_overlapped_result_before_completion() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let mut dispatch = Probe::new(&counters).dispatch();
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 42,
+  };
+  unsafe { port.complete_io(7, overlapped, result) };
+  // The result can be routed on before the handler is taken out.
+  assert_eq!(
+    unsafe { read_overlapped_result(overlapped.as_ptr()) },
+    result
+  );
+  assert_eq!(counters.completed(), 0);
+
+  let entry = port.run_one(None).unwrap();
+  assert_eq!(entry.lpCompletionKey, 7);
+  assert_eq!(counters.results(), vec![result]);
+}
+
+// Neither the hash nor equality looks at the parts of an EventState that have
+// interior mutability.
+#[allow(clippy::mutable_key_type)]
+#[test]
+fn event_states_are_keyed_by_identity() {
+  use std::collections::HashSet;
+
+  let counters = Counters::new();
+  let mut first = Probe::new(&counters).dispatch();
+  let mut second = Probe::new(&counters).dispatch();
+  let (a, b) = unsafe {
+    let a = EventState::container_of(&*first.overlapped());
+    let b = EventState::container_of(&*second.overlapped());
+    (a, b)
+  };
+  assert!(a == a && a != b);
+
+  let mut states = HashSet::new();
+  assert!(states.insert(a));
+  assert!(states.insert(b));
+  assert!(!states.insert(a));
+  assert!(states.contains(a) && states.contains(b));
+
+  drop(states);
+  let _ = first.failed();
+  let _ = second.failed();
+}
+
+#[test]
+fn handler_as_checks_the_handler_type() {
+  struct Other {
+    state: EventState,
+  }
+
+  impl EventHandler for Other {
+    fn state(&mut self) -> &mut EventState {
+      &mut self.state
+    }
+
+    fn complete(self: Box<Self>) {}
+  }
+
+  let counters = Counters::new();
+  let mut dispatch = Probe::new(&counters).dispatch();
+  let state = unsafe { EventState::container_of(&*dispatch.overlapped()) };
+  let probe = state.handler_as::<Probe>().expect("not a Probe");
+  assert!(Arc::ptr_eq(&probe.counters, &counters));
+  assert!(state.handler_as::<Other>().is_none());
+
+  // Once the handler has left the EventState, there's nothing to look at.
+  let handler = dispatch.failed();
+  assert!(handler.state.handler_as::<Probe>().is_none());
+}
+
+#[test]
+fn peek_bytes_transferred_waits_for_the_completion() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let mut dispatch = Probe::new(&counters).dispatch();
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  let state = unsafe { EventState::container_of(&*overlapped.as_ptr()) };
+  assert_eq!(state.peek_bytes_transferred(), None);
+  dispatch.pending();
+
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 42,
+  };
+  unsafe { port.complete_io(7, overlapped, result) };
+  let state = unsafe { EventState::container_of(&*overlapped.as_ptr()) };
+  assert_eq!(state.peek_bytes_transferred(), Some(42));
+  assert_eq!(counters.completed(), 0);
+
+  port.run_one(None).unwrap();
+  assert_eq!(counters.results(), vec![result]);
+}
+
+#[allow(clippy::mutable_key_type)]
+#[test]
+fn undispatched_event_states_hash_by_address() {
+  use std::collections::hash_map::DefaultHasher;
+  use std::collections::HashSet;
+  use std::hash::{Hash, Hasher};
+
+  let hash = |state: &EventState| {
+    let mut hasher = DefaultHasher::new();
+    state.hash(&mut hasher);
+    hasher.finish()
+  };
+  let (a, b) = (EventState::new(), EventState::new());
+  assert_ne!(hash(&a), hash(&b));
+
+  let mut states = HashSet::new();
+  assert!(states.insert(&a));
+  assert!(states.insert(&b));
+  assert_eq!(states.len(), 2);
+}
+
+#[test]
+fn embedded_size_of_reports_the_whole_handler() {
+  use std::mem::size_of;
+
+  // A handler with a payload next to its EventState.
+  struct Padded {
+    state: EventState,
+    _payload: [u8; 100],
+  }
+
+  impl EventHandler for Padded {
+    fn state(&mut self) -> &mut EventState {
+      &mut self.state
+    }
+
+    fn complete(self: Box<Self>) {}
+  }
+
+  let padded = Box::new(Padded {
+    state: EventState::new(),
+    _payload: [0; 100],
+  });
+  assert_eq!(padded.state.embedded_size_of(), 0);
+  let mut dispatch = padded.dispatch();
+  let state = unsafe { EventState::container_of(&*dispatch.overlapped()) };
+  // The EventState itself is part of the handler, along with the payload.
+  assert_eq!(state.embedded_size_of(), size_of::<Padded>());
+  assert!(state.embedded_size_of() >= size_of::<EventState>() + 100);
+
+  let handler = dispatch.failed();
+  assert_eq!(handler.state.embedded_size_of(), 0);
+}
+
+#[test]
+fn embedded_size_of_follows_the_erased_handler() {
+  use std::mem::size_of;
+
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let mut erased = port.dispatch(Probe::new(&counters)).into_dyn();
+  let overlapped = erased.overlapped();
+  let state = unsafe { EventState::container_of(&*overlapped) };
+  // The size of the handler that was dispatched, not of a trait object.
+  assert_eq!(state.embedded_size_of(), size_of::<Probe>());
+  erased.pending();
+
+  unsafe { EventState::complete(NonNull::new(overlapped).unwrap()) };
+  assert_eq!(counters.completed(), 1);
+  port.assert_no_leaks();
+}
+
+#[test]
+fn complete_immediate_completes_without_embedding() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 11,
+  };
+  // The ReadFile() returned TRUE, so nothing was dispatched.
+  EventState::complete_immediate(Probe::new(&counters), result);
+  assert_eq!(counters.results(), [result]);
+  assert_eq!(counters.freed(), 1);
+  assert!(port.registry().is_empty());
+  port.assert_no_leaks();
+}
+
+#[test]
+fn event_states_pin_in_place() {
+  use std::pin::Pin;
+
+  let pinned: Pin<Box<EventState>> = EventState::new().into();
+  let address = &*pinned as *const EventState;
+  assert_eq!(pinned.embedded_size_of(), 0);
+  assert_eq!(pinned.peek_bytes_transferred(), None);
+  // The OVERLAPPED handed to the OS stays where the pin put it, and leads
+  // back to the same EventState.
+  let overlapped: &OVERLAPPED = &pinned;
+  let state = unsafe { EventState::container_of(overlapped) };
+  assert!(std::ptr::eq(state, address));
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use std::sync::Arc;
+
+use miox::completion_port::CompletionPort;
+use miox::iocp::{Dispatch, RawDispatch};
+use miox::{EventHandler, EventState};
+
+use common::{Counters, Probe};
+
+struct Other {
+  state: EventState,
+}
+
+impl EventHandler for Other {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {}
+}
+
+#[test]
+fn fail_many_reclaims_the_whole_batch() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let baseline = port.stats().snapshot();
+
+  let dispatches: Vec<_> = (0..4)
+    .map(|_| port.dispatch(Probe::new(&counters)))
+    .collect();
+  assert_eq!(port.registry().len(), 4);
+  let handlers = Dispatch::fail_many(dispatches);
+  assert_eq!(handlers.len(), 4);
+  assert!(handlers.iter().all(|h| Arc::ptr_eq(&h.counters, &counters)));
+
+  assert!(port.registry().is_empty());
+  let stats = port.stats().snapshot();
+  assert_eq!(stats.outstanding(), baseline.outstanding());
+  assert_eq!((stats.submitted, stats.failed), (4, 4));
+  assert_eq!(counters.completed(), 0);
+}
+
+#[test]
+fn raw_fail_many_reclaims_mixed_handler_types() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let dispatches = vec![
+    port.dispatch(Probe::new(&counters)).into_raw(),
+    port
+      .dispatch(Box::new(Other {
+        state: EventState::new(),
+      }))
+      .into_raw(),
+  ];
+  let handlers = RawDispatch::fail_many(dispatches);
+  let names: Vec<_> = handlers.iter().map(|h| h.type_name()).collect();
+  assert!(names[0].ends_with("Probe") && names[1].ends_with("Other"));
+
+  assert!(port.registry().is_empty());
+  let stats = port.stats().snapshot();
+  assert_eq!((stats.outstanding(), stats.failed), (0, 2));
+}
@@ -0,0 +1,92 @@
+use std::ptr::NonNull;
+use std::sync::mpsc::{channel, Sender};
+use std::time::Duration;
+
+use miox::completion_port::CompletionPort;
+use miox::iocp::InPlaceEventHandler;
+use miox::winapi::STATUS_SUCCESS;
+use miox::{EventHandler, EventState, IoResult};
+
+// Reads into a buffer that belongs to the caller, and reports what arrived.
+struct ReadInto {
+  state: EventState,
+  buf: *mut u8,
+  len: usize,
+  done: Sender<Vec<u8>>,
+}
+
+unsafe impl Send for ReadInto {}
+
+impl EventHandler for ReadInto {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {
+    panic!("completed without the buffer");
+  }
+
+  fn as_in_place(&mut self) -> Option<&mut dyn InPlaceEventHandler> {
+    Some(self)
+  }
+
+  fn io_buffer(&mut self) -> Option<(*mut u8, usize)> {
+    Some((self.buf, self.len))
+  }
+}
+
+impl InPlaceEventHandler for ReadInto {
+  fn complete_in_place(&mut self, buf: &mut [u8], bytes: u32) {
+    assert_eq!(buf.as_mut_ptr(), self.buf);
+    self.done.send(buf[..bytes as usize].to_vec()).unwrap();
+  }
+}
+
+#[test]
+fn run_loops_complete_in_place_handlers_with_their_buffer() {
+  let mut buf = vec![0u8; 16];
+  let (tx, rx) = channel();
+  let port = CompletionPort::new();
+  let mut dispatch = port.dispatch(Box::new(ReadInto {
+    state: EventState::new(),
+    buf: buf.as_mut_ptr(),
+    len: buf.len(),
+    done: tx,
+  }));
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+
+  // The OS reads straight into the caller's buffer.
+  buf[..5].copy_from_slice(b"hello");
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 5,
+  };
+  unsafe { port.complete_io(0, overlapped, result) };
+  port.run_one(Some(Duration::from_secs(0))).unwrap();
+  assert_eq!(rx.try_recv().unwrap(), b"hello");
+  port.assert_no_leaks();
+}
+
+#[test]
+fn complete_takes_the_in_place_path_too() {
+  let mut buf = *b"abc";
+  let (tx, rx) = channel();
+  let mut dispatch = miox::Dispatchable::dispatch(Box::new(ReadInto {
+    state: EventState::new(),
+    buf: buf.as_mut_ptr(),
+    len: buf.len(),
+    done: tx,
+  }));
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 2,
+  };
+  unsafe {
+    miox::iocp::write_overlapped_result(overlapped.as_ptr(), result);
+    EventState::complete(overlapped);
+  }
+  assert_eq!(rx.try_recv().unwrap(), b"ab");
+}
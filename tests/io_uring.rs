@@ -0,0 +1,36 @@
+#![cfg(all(target_os = "linux", feature = "io-uring"))]
+
+mod common;
+
+use miox::completion_port::CompletionPort;
+use miox::winapi::STATUS_SUCCESS;
+use miox::{EventState, IoResult};
+
+use common::{Counters, Probe};
+
+#[test]
+fn cqes_complete_the_handler_their_userdata_names() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let read = port.dispatch(Probe::new(&counters));
+  let read_userdata = read.as_ref().state.get_sqe_userdata();
+  read.pending();
+  let failed = port.dispatch(Probe::new(&counters));
+  let failed_userdata = failed.as_ref().state.get_sqe_userdata();
+  failed.pending();
+
+  // The CQE for the read carries a byte count, the other a negated errno.
+  unsafe { EventState::complete_from_userdata(read_userdata, 12) };
+  unsafe { EventState::complete_from_userdata(failed_userdata, -9) };
+  let results = counters.results();
+  assert_eq!(
+    results[0],
+    IoResult {
+      status: STATUS_SUCCESS,
+      bytes_transferred: 12,
+    }
+  );
+  assert_eq!(results[1].status, -9);
+  assert_eq!(counters.freed(), 2);
+  port.assert_no_leaks();
+}
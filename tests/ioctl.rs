@@ -0,0 +1,45 @@
+use std::ptr::NonNull;
+use std::slice;
+use std::sync::mpsc::channel;
+
+use miox::completion_port::CompletionPort;
+use miox::ioctl::IoctlState;
+use miox::winapi::STATUS_SUCCESS;
+use miox::IoResult;
+
+const IOCTL_AFD_POLL: u32 = 0x0001_2024;
+
+#[test]
+fn output_written_by_the_driver_is_handed_to_the_callback() {
+  let (tx, rx) = channel();
+  let port = CompletionPort::new();
+  let mut ioctl =
+    IoctlState::new(IOCTL_AFD_POLL, vec![1, 2, 3], 16, move |result, out| {
+      tx.send((result, out.to_vec())).unwrap()
+    });
+  assert_eq!(ioctl.control_code(), IOCTL_AFD_POLL);
+  let (input, input_len) = ioctl.in_buffer();
+  let (output, output_len) = ioctl.out_buffer();
+  assert_eq!(output_len, 16);
+
+  let mut dispatch = port.dispatch(ioctl);
+  // Stands in for DeviceIoControl(): the driver reads the input, and fills
+  // in part of the output before the operation completes.
+  unsafe {
+    assert_eq!(slice::from_raw_parts(input, input_len as usize), [1, 2, 3]);
+    output.copy_from_nonoverlapping(b"\xaa\xbb\xcc\xdd".as_ptr(), 4);
+  }
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 4,
+  };
+  unsafe { port.complete_io(0, overlapped, result) };
+  port.run_one(None).unwrap();
+
+  let (reported, out) = rx.try_recv().unwrap();
+  assert_eq!(reported, result);
+  assert_eq!(out, [0xaa, 0xbb, 0xcc, 0xdd]);
+  port.assert_no_leaks();
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Wake, Waker};
+
+use miox::map_dispatcher::MapDispatcher;
+use miox::winapi::{OVERLAPPED, STATUS_SUCCESS};
+use miox::IoResult;
+
+use common::{Counters, Probe};
+
+#[derive(Default)]
+struct Task {
+  woken: AtomicBool,
+}
+
+impl Wake for Task {
+  fn wake(self: Arc<Self>) {
+    self.woken.store(true, Ordering::SeqCst);
+  }
+}
+
+#[test]
+fn complete_by_ptr_runs_the_registered_handler() {
+  let counters = Counters::new();
+  let mut map = MapDispatcher::new();
+  // OVERLAPPEDs allocated by foreign code.
+  let mut first = Box::<OVERLAPPED>::default();
+  let mut second = Box::<OVERLAPPED>::default();
+  map.register(&mut *first, Probe::new(&counters));
+  map.register(&mut *second, Probe::new(&counters));
+  assert_eq!(map.len(), 2);
+
+  // A task awaiting the third operation, which completing it wakes.
+  let task = Arc::new(Task::default());
+  let probe = Probe::new(&counters);
+  probe.state.wakers().register(&Waker::from(task.clone()));
+  let mut third = Box::<OVERLAPPED>::default();
+  map.register(&mut *third, probe);
+
+  first.Internal = STATUS_SUCCESS as usize;
+  first.InternalHigh = 12;
+  assert!(unsafe { map.complete_by_ptr(&mut *first) });
+  assert!(unsafe { map.complete_by_ptr(&mut *third) });
+  assert!(task.woken.load(Ordering::SeqCst));
+  assert_eq!(
+    counters.results()[0],
+    IoResult {
+      status: STATUS_SUCCESS,
+      bytes_transferred: 12,
+    }
+  );
+
+  // A miss is reported rather than panicking.
+  assert!(!unsafe { map.complete_by_ptr(&mut *first) });
+  let mut unknown = OVERLAPPED::default();
+  assert!(!unsafe { map.complete_by_ptr(&mut unknown) });
+
+  assert!(map.unregister(&mut *second).is_some());
+  assert!(map.is_empty());
+  assert_eq!(counters.completed(), 2);
+}
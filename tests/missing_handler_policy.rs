@@ -0,0 +1,93 @@
+// The policy is process-wide, so this file holds a single test.
+
+mod common;
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::mpsc::channel;
+
+use miox::container_of::ContainerOf;
+use miox::hooks::CompletionHooks;
+use miox::iocp::{Dispatch, MissingHandlerPolicy};
+use miox::winapi::OVERLAPPED;
+use miox::{Dispatchable, EventHandler, EventState};
+
+use common::{Counters, Probe};
+
+static HOOKED: AtomicPtr<OVERLAPPED> = AtomicPtr::new(std::ptr::null_mut());
+
+fn hook(overlapped: *mut OVERLAPPED) {
+  HOOKED.store(overlapped, Ordering::SeqCst);
+}
+
+// Dispatches a handler and then takes it back out behind the guard's back,
+// so completing the OVERLAPPED finds no handler. The handler is returned to
+// keep the OVERLAPPED alive.
+fn strip(
+  dispatch: &mut Dispatch<Probe>,
+) -> (NonNull<OVERLAPPED>, Box<dyn EventHandler>) {
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  let state =
+    unsafe { EventState::container_of_mut(&mut *overlapped.as_ptr()) };
+  (overlapped, state.detach_event_handler().unwrap())
+}
+
+#[test]
+fn missing_handler_policies() {
+  let counters = Counters::new();
+
+  // Panic: a stray completion doesn't go unnoticed.
+  EventState::set_missing_handler_policy(MissingHandlerPolicy::Panic);
+  let mut dispatch = Probe::new(&counters).dispatch();
+  let (overlapped, _handler) = strip(&mut dispatch);
+  dispatch.pending();
+  let panicked = catch_unwind(|| unsafe { EventState::complete(overlapped) });
+  assert!(panicked.is_err());
+
+  // LogAndIgnore: both a stray completion and a failed() that comes up empty
+  // are let go.
+  EventState::set_missing_handler_policy(MissingHandlerPolicy::LogAndIgnore);
+  let mut dispatch = Probe::new(&counters).dispatch();
+  let (overlapped, _handler) = strip(&mut dispatch);
+  assert!(dispatch.try_failed().is_none());
+  unsafe { EventState::complete(overlapped) };
+
+  // The other ways of completing or reclaiming go by the policy too.
+  let mut dispatch = Probe::new(&counters).dispatch();
+  let (overlapped, _handler) = strip(&mut dispatch);
+  let (tx, rx) = channel();
+  unsafe {
+    EventState::complete_with_hooks(overlapped, &CompletionHooks::new());
+    EventState::complete_with_callback(overlapped, |_, _| {
+      panic!("no handler to call back with")
+    });
+    EventState::complete_to(overlapped, &tx);
+    EventState::complete_via_channel(overlapped, &tx).unwrap();
+    assert!(EventState::take_completion(overlapped).is_none());
+  }
+  assert!(rx.try_recv().is_err());
+  assert!(dispatch.into_dyn().try_failed_dyn().is_none());
+
+  // Hook: the hook hears about the OVERLAPPED, on either path.
+  EventState::set_missing_handler_policy(MissingHandlerPolicy::Hook(hook));
+  let mut dispatch = Probe::new(&counters).dispatch();
+  let (overlapped, _handler) = strip(&mut dispatch);
+  unsafe { EventState::complete(overlapped) };
+  assert_eq!(
+    HOOKED.swap(std::ptr::null_mut(), Ordering::SeqCst),
+    overlapped.as_ptr()
+  );
+  assert!(dispatch.try_failed().is_none());
+  assert_eq!(HOOKED.load(Ordering::SeqCst), overlapped.as_ptr());
+
+  // failed() has nothing to hand back, so it panics regardless, but only
+  // after the hook has run.
+  let mut dispatch = Probe::new(&counters).dispatch();
+  let (overlapped, _handler) = strip(&mut dispatch);
+  let panicked = catch_unwind(AssertUnwindSafe(|| dispatch.failed()));
+  assert!(panicked.is_err());
+  assert_eq!(HOOKED.load(Ordering::SeqCst), overlapped.as_ptr());
+
+  assert_eq!(counters.completed(), 0);
+}
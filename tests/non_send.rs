@@ -0,0 +1,78 @@
+use std::cell::Cell;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr::NonNull;
+use std::rc::Rc;
+use std::thread;
+
+use miox::non_send::LocalEventHandler;
+use miox::EventState;
+
+// Holds on to thread-local state, so it isn't Send.
+struct Local {
+  state: EventState,
+  completed: Rc<Cell<bool>>,
+}
+
+impl LocalEventHandler for Local {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {
+    self.completed.set(true);
+  }
+}
+
+#[test]
+fn non_send_handlers_complete_on_their_own_thread() {
+  let completed = Rc::new(Cell::new(false));
+  let mut dispatch = unsafe {
+    EventState::try_embed_non_send(Box::new(Local {
+      state: EventState::new(),
+      completed: completed.clone(),
+    }))
+  };
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  unsafe { EventState::complete(overlapped) };
+  assert!(completed.get());
+}
+
+#[test]
+fn non_send_handlers_can_be_reclaimed() {
+  let completed = Rc::new(Cell::new(false));
+  let dispatch = unsafe {
+    EventState::try_embed_non_send(Box::new(Local {
+      state: EventState::new(),
+      completed: completed.clone(),
+    }))
+  };
+  let local = dispatch.failed().into_inner();
+  assert!(Rc::ptr_eq(&local.completed, &completed));
+  assert!(!completed.get());
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn non_send_handlers_are_rejected_on_other_threads() {
+  // Dispatched on another thread, and then completed on this one.
+  let address = thread::spawn(|| {
+    let mut dispatch = unsafe {
+      EventState::try_embed_non_send(Box::new(Local {
+        state: EventState::new(),
+        completed: Rc::new(Cell::new(false)),
+      }))
+    };
+    let overlapped = dispatch.overlapped() as usize;
+    dispatch.pending();
+    overlapped
+  })
+  .join()
+  .unwrap();
+  let overlapped = NonNull::new(address as *mut _).unwrap();
+  let panicked = catch_unwind(AssertUnwindSafe(|| unsafe {
+    EventState::complete(overlapped)
+  }));
+  let message = *panicked.unwrap_err().downcast::<String>().unwrap();
+  assert!(message.contains("but belongs to"), "{}", message);
+}
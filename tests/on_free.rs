@@ -0,0 +1,134 @@
+mod common;
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr::NonNull;
+
+use miox::arc_dispatch::ArcDispatch;
+use miox::completion_port::CompletionPort;
+use miox::container_of::ContainerOf;
+use miox::iocp::InPlaceEventHandler;
+use miox::{EventHandler, EventState};
+
+use common::{Counters, Probe};
+
+#[test]
+fn on_free_runs_once_when_completed() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let mut dispatch = port.dispatch(Probe::new(&counters));
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  unsafe { EventState::complete(overlapped) };
+  assert_eq!((counters.completed(), counters.freed()), (1, 1));
+}
+
+#[test]
+fn on_free_runs_once_per_dispatch_of_a_redispatched_handler() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let handler = port.dispatch(Probe::new(&counters)).failed();
+  // The handler is done with the failed operation, even if it's used again.
+  assert_eq!((counters.completed(), counters.freed()), (0, 1));
+
+  let mut dispatch = port.dispatch(handler);
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  unsafe { EventState::complete(overlapped) };
+  assert_eq!((counters.completed(), counters.freed()), (1, 2));
+  port.assert_no_leaks();
+}
+
+#[test]
+fn on_free_runs_once_when_handed_back() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let handler = port.dispatch(Probe::new(&counters)).try_failed();
+  assert!(handler.is_some());
+  assert_eq!(counters.freed(), 1);
+  let handler = port.dispatch(Probe::new(&counters)).into_dyn().failed_dyn();
+  assert_eq!(counters.freed(), 2);
+
+  let mut dispatch = port.dispatch(Probe::new(&counters));
+  let overlapped = dispatch.overlapped();
+  dispatch.pending();
+  let state = unsafe { EventState::container_of_mut(&mut *overlapped) };
+  let detached = state.detach_event_handler();
+  assert!(detached.is_some());
+  assert_eq!(counters.freed(), 3);
+
+  // Dropping the handlers afterwards doesn't tear them down again.
+  drop((handler, detached));
+  assert_eq!((counters.completed(), counters.freed()), (0, 3));
+  port.assert_no_leaks();
+}
+
+#[test]
+fn on_free_runs_after_complete_in_place() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let mut dispatch = port.dispatch(Box::new(InPlace {
+    probe: *Probe::new(&counters),
+  }));
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  let mut buf = [0u8; 4];
+  unsafe { EventState::complete_in_place(overlapped, &mut buf) };
+  assert_eq!((counters.completed(), counters.freed()), (1, 1));
+  port.assert_no_leaks();
+}
+
+#[test]
+fn on_free_runs_once_when_abandoned() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let panicked = catch_unwind(AssertUnwindSafe(|| {
+    let _dispatch = port.dispatch(Probe::new(&counters));
+    let _shared = ArcDispatch::new(port.dispatch(Probe::new(&counters)));
+    panic!("before the OS call");
+  }));
+  assert!(panicked.is_err());
+  assert_eq!((counters.completed(), counters.freed()), (0, 2));
+
+  // A completion that is taken but never finished is abandoned as well.
+  let mut dispatch = port.dispatch(Probe::new(&counters));
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  drop(unsafe { EventState::take_completion(overlapped) });
+  assert_eq!((counters.completed(), counters.freed()), (0, 3));
+  port.assert_no_leaks();
+}
+
+// Checks that it was completed by the time it's torn down.
+struct InPlace {
+  probe: Probe,
+}
+
+impl EventHandler for InPlace {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.probe.state
+  }
+
+  fn complete(self: Box<Self>) {
+    unreachable!()
+  }
+
+  fn as_in_place(&mut self) -> Option<&mut dyn InPlaceEventHandler> {
+    Some(self)
+  }
+
+  fn on_free(&mut self) {
+    assert_eq!(self.probe.counters.completed(), 1);
+    self.probe.on_free();
+  }
+}
+
+impl InPlaceEventHandler for InPlace {
+  fn complete_in_place(&mut self, _buf: &mut [u8], _bytes: u32) {
+    assert_eq!(self.probe.counters.freed(), 0);
+    Box::new(Probe {
+      state: EventState::new(),
+      counters: self.probe.counters.clone(),
+    })
+    .complete();
+  }
+}
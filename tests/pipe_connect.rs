@@ -0,0 +1,71 @@
+#![cfg(not(windows))]
+
+use std::ptr::NonNull;
+use std::sync::mpsc::channel;
+
+use miox::completion_port::CompletionPort;
+use miox::pipe_connect::PipeConnectState;
+use miox::raw::RawHandle;
+use miox::winapi::{ERROR_IO_PENDING, ERROR_PIPE_CONNECTED, STATUS_SUCCESS};
+use miox::IoResult;
+
+// ERROR_NO_DATA: the client connected and went away again.
+const ERROR_NO_DATA: u32 = 232;
+
+#[test]
+fn pending_connect_completes_through_the_port() {
+  let (tx, rx) = channel();
+  let pipe = RawHandle::from_raw(0x1234);
+  let connect = PipeConnectState::new(pipe, move |result, pipe| {
+    tx.send((result, pipe)).unwrap()
+  });
+
+  let port = CompletionPort::new();
+  let mut issued = None;
+  let started = connect.connect(|handle, overlapped| {
+    assert_eq!(handle, pipe);
+    issued = NonNull::new(overlapped);
+    Err(ERROR_IO_PENDING)
+  });
+  assert!(started.is_ok());
+  // Nothing happens until the client shows up.
+  assert!(rx.try_recv().is_err());
+
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 0,
+  };
+  unsafe { port.complete_io(0, issued.unwrap(), result) };
+  port.run_one(None).unwrap();
+  assert_eq!(rx.try_recv().unwrap(), (result, pipe));
+}
+
+#[test]
+fn already_connected_pipe_completes_inline() {
+  let (tx, rx) = channel();
+  let pipe = RawHandle::from_raw(0x1234);
+  let connect = PipeConnectState::new(pipe, move |result, pipe| {
+    tx.send((result, pipe)).unwrap()
+  });
+
+  // No completion packet follows ERROR_PIPE_CONNECTED, so the handler has run
+  // by the time connect() returns.
+  let started = connect.connect(|_, _| Err(ERROR_PIPE_CONNECTED));
+  assert!(started.is_ok());
+  let (result, handle) = rx.try_recv().unwrap();
+  assert_eq!(result.status, STATUS_SUCCESS);
+  assert_eq!(handle, pipe);
+}
+
+#[test]
+fn failed_connect_hands_the_handler_back() {
+  let (tx, rx) = channel();
+  let pipe = RawHandle::from_raw(0x1234);
+  let connect = PipeConnectState::new(pipe, move |result, pipe| {
+    tx.send((result, pipe)).unwrap()
+  });
+
+  let handler = connect.connect(|_, _| Err(ERROR_NO_DATA)).err().unwrap();
+  assert_eq!(handler.pipe(), pipe);
+  assert!(rx.try_recv().is_err());
+}
@@ -0,0 +1,190 @@
+#![cfg(not(windows))]
+
+use std::ptr::NonNull;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+
+use miox::completion_port::CompletionPort;
+use miox::iocp::write_overlapped_result;
+use miox::poll_registration::PollRegistration;
+use miox::raw::RawSocket;
+use miox::winapi::{
+  ERROR_IO_PENDING, OVERLAPPED, STATUS_CANCELLED, STATUS_SUCCESS,
+};
+use miox::{EventState, IoResult};
+
+const READABLE: u32 = 0x1;
+const WRITABLE: u32 = 0x4;
+
+// A poll that was issued: its event bits, where to report events, and its
+// OVERLAPPED.
+#[derive(Clone, Copy)]
+struct Issued {
+  interests: u32,
+  events: usize,
+  overlapped: NonNull<OVERLAPPED>,
+}
+
+unsafe impl Send for Issued {}
+
+type Log<T> = Arc<Mutex<Vec<T>>>;
+
+// A registration whose polls stay pending until they're completed through a
+// port by hand. Cancelling one records it, without completing it.
+struct Mock {
+  registration: PollRegistration,
+  issued: Log<Issued>,
+  cancelled: Log<usize>,
+  reported: Receiver<(IoResult, u32)>,
+}
+
+impl Mock {
+  fn new() -> Self {
+    let issued: Log<Issued> = Default::default();
+    let cancelled: Log<usize> = Default::default();
+    let (tx, reported) = channel();
+    let registration = {
+      let issued = issued.clone();
+      let cancelled = cancelled.clone();
+      let tx = Mutex::new(tx);
+      PollRegistration::new(
+        RawSocket::from_raw(0x5678),
+        move |_, interests, events, overlapped| {
+          issued.lock().unwrap().push(Issued {
+            interests,
+            events: events as usize,
+            overlapped: NonNull::new(overlapped).unwrap(),
+          });
+          Err(ERROR_IO_PENDING)
+        },
+        move |overlapped| cancelled.lock().unwrap().push(overlapped as usize),
+        move |result, events| {
+          tx.lock().unwrap().send((result, events)).unwrap()
+        },
+      )
+    };
+    Self {
+      registration,
+      issued,
+      cancelled,
+      reported,
+    }
+  }
+
+  fn issued(&self, index: usize) -> Issued {
+    self.issued.lock().unwrap()[index]
+  }
+
+  fn reported(&self) -> Option<u32> {
+    self.reported.try_recv().ok().map(|(_, events)| events)
+  }
+}
+
+fn finish(port: &CompletionPort, poll: Issued, status: i32, events: u32) {
+  let result = IoResult {
+    status,
+    bytes_transferred: 0,
+  };
+  unsafe {
+    *(poll.events as *mut u32) = events;
+    port.complete_io(0, poll.overlapped, result);
+  }
+  port.run_one(None).unwrap();
+}
+
+#[test]
+fn changing_interests_cancels_and_reissues_the_poll() {
+  let port = CompletionPort::new();
+  let mock = Mock::new();
+  let registration = &mock.registration;
+
+  registration.update_interest(READABLE).unwrap();
+  // The same interests again leave the outstanding poll alone.
+  registration.update_interest(READABLE).unwrap();
+  assert_eq!(mock.issued.lock().unwrap().len(), 1);
+  let first = mock.issued(0);
+  assert_eq!(first.interests, READABLE);
+
+  registration.update_interest(READABLE | WRITABLE).unwrap();
+  assert_eq!(registration.interests(), READABLE | WRITABLE);
+  assert_eq!(
+    *mock.cancelled.lock().unwrap(),
+    vec![first.overlapped.as_ptr() as usize]
+  );
+  let second = mock.issued(1);
+  assert_eq!(second.interests, READABLE | WRITABLE);
+
+  // The superseded poll is cancelled as intended, and nothing is reported.
+  finish(&port, first, STATUS_CANCELLED, 0);
+  assert_eq!(mock.reported(), None);
+  assert!(registration.is_outstanding());
+
+  finish(&port, second, STATUS_SUCCESS, WRITABLE);
+  assert_eq!(mock.reported(), Some(WRITABLE));
+  assert!(!registration.is_outstanding());
+
+  registration.rearm().unwrap();
+  let third = mock.issued(2);
+  registration.deregister();
+  assert!(!registration.is_outstanding());
+  finish(&port, third, STATUS_CANCELLED, 0);
+  assert_eq!(mock.reported(), None);
+  assert_eq!(mock.issued.lock().unwrap().len(), 3);
+}
+
+#[test]
+fn events_reported_before_the_cancel_are_passed_on() {
+  let port = CompletionPort::new();
+  let mock = Mock::new();
+  let registration = &mock.registration;
+
+  registration.update_interest(READABLE | WRITABLE).unwrap();
+  registration.update_interest(READABLE).unwrap();
+
+  // The old poll beat the cancel. What it reported is masked by the new
+  // interests, and the new poll is still outstanding.
+  finish(&port, mock.issued(0), STATUS_SUCCESS, READABLE | WRITABLE);
+  assert_eq!(mock.reported(), Some(READABLE));
+  assert!(registration.is_outstanding());
+
+  registration.deregister();
+  finish(&port, mock.issued(1), STATUS_CANCELLED, 0);
+  assert_eq!(mock.reported(), None);
+}
+
+#[test]
+fn polls_that_complete_inline_do_not_deadlock() {
+  let cancelled: Log<usize> = Default::default();
+  let (tx, rx) = channel();
+  let registration = {
+    let cancelled = cancelled.clone();
+    let tx = Mutex::new(tx);
+    PollRegistration::new(
+      RawSocket::from_raw(0x5678),
+      |_, interests, events, overlapped| {
+        // Reports right away, and completes before issue() returns.
+        let result = IoResult {
+          status: STATUS_SUCCESS,
+          bytes_transferred: 0,
+        };
+        unsafe {
+          *events = interests;
+          write_overlapped_result(overlapped, result);
+          EventState::complete(NonNull::new(overlapped).unwrap());
+        }
+        Ok(())
+      },
+      move |overlapped| cancelled.lock().unwrap().push(overlapped as usize),
+      move |_, events| tx.lock().unwrap().send(events).unwrap(),
+    )
+  };
+
+  registration.update_interest(READABLE).unwrap();
+  assert_eq!(rx.try_recv().unwrap(), READABLE);
+  assert!(!registration.is_outstanding());
+
+  // Changing the interests again while nothing's outstanding cancels nothing.
+  registration.update_interest(WRITABLE).unwrap();
+  assert_eq!(rx.try_recv().unwrap(), WRITABLE);
+  assert!(cancelled.lock().unwrap().is_empty());
+}
@@ -0,0 +1,63 @@
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use miox::pool::{HandlerPool, PoolableEventHandler};
+use miox::EventState;
+
+#[derive(Default)]
+struct Calls {
+  completed: AtomicUsize,
+  reset: AtomicUsize,
+}
+
+struct Read {
+  state: EventState,
+  calls: Arc<Calls>,
+}
+
+impl PoolableEventHandler for Read {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(&mut self) {
+    self.calls.completed.fetch_add(1, Ordering::SeqCst);
+  }
+
+  fn reset(&mut self) {
+    self.calls.reset.fetch_add(1, Ordering::SeqCst);
+  }
+}
+
+#[test]
+fn failed_rearm_retires_the_handler_once() {
+  let calls = Arc::new(Calls::default());
+  let pool = HandlerPool::with_capacity(2);
+  let handler = pool.get_or_else(|| Read {
+    state: EventState::new(),
+    calls: calls.clone(),
+  });
+  let mut dispatch = pool.dispatch(handler);
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  assert_eq!(pool.outstanding(), 1);
+
+  // The handle was closed in the meantime, so the next read can't start.
+  let rearmed =
+    unsafe { pool.complete_and_rearm(overlapped, |_, _| Err("handle closed")) };
+  assert_eq!(rearmed, Err("handle closed"));
+  assert_eq!(calls.completed.load(Ordering::SeqCst), 1);
+  // Reset once after completing, and once more when it was recycled.
+  assert_eq!(calls.reset.load(Ordering::SeqCst), 2);
+  // Back in the pool, and no longer outstanding; not counted twice.
+  assert_eq!((pool.outstanding(), pool.len()), (0, 1));
+
+  // The slot is as good as new.
+  let handler = pool.get_or_else(|| unreachable!());
+  assert!(pool.is_empty());
+  let dispatch = pool.dispatch(handler);
+  assert_eq!(pool.outstanding(), 1);
+  pool.recycle(dispatch.failed());
+  assert_eq!((pool.outstanding(), pool.len()), (0, 1));
+}
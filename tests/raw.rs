@@ -0,0 +1,120 @@
+#![cfg(not(windows))]
+
+mod common;
+
+use std::ptr::NonNull;
+use std::thread;
+use std::time::Duration;
+
+use miox::completion_port::CompletionPort;
+use miox::iocp::{write_overlapped_result, RawDispatch};
+use miox::raw::{from_handle, to_handle, to_socket, RawHandle, RawSocket};
+use miox::transmit_file::TransmitFileState;
+use miox::winapi::STATUS_SUCCESS;
+use miox::{EventState, IoResult};
+
+use common::{Counters, Probe};
+
+#[test]
+fn raw_handles_round_trip() {
+  let handle = RawHandle::from_raw(0x1234);
+  assert_eq!(handle.as_raw(), 0x1234);
+  assert_eq!(to_handle(handle) as usize, 0x1234);
+  assert_eq!(from_handle(to_handle(handle)), handle);
+  let socket = RawSocket::from_raw(0x5678);
+  assert_eq!(to_socket(socket), 0x5678);
+}
+
+#[test]
+fn raw_handles_round_trip_through_the_port() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let handle = RawHandle::from_raw(0x1234);
+  port.associate(handle, 7, &mut *Probe::new(&counters));
+  assert_eq!(port.key_of(handle), Some(7));
+  assert_eq!(port.key_of(RawHandle::from_raw(0x4321)), None);
+
+  let dispatch = port.dispatch_on(handle, Probe::new(&counters));
+  let orphans = port.report_orphans();
+  assert_eq!(orphans.len(), 1);
+  assert_eq!(orphans[0].handle, Some(handle));
+  let _ = dispatch.failed();
+}
+
+#[test]
+fn raw_handles_round_trip_through_transmit_file() {
+  let file = RawHandle::from_raw(0x1234);
+  let socket = RawSocket::from_raw(0x5678);
+  let transmit =
+    TransmitFileState::new(file, socket, Vec::new(), Vec::new(), |_| {});
+  assert_eq!(transmit.file(), file);
+  assert_eq!(transmit.socket(), socket);
+}
+
+#[test]
+fn raw_dispatch_recovers_and_completes() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let raw = port.dispatch(Probe::new(&counters)).into_raw();
+  let overlapped = raw.as_ptr();
+  assert_eq!(port.registry().len(), 1);
+
+  let mut dispatch = unsafe { raw.recover::<Probe>() };
+  let entry = dispatch.overlapped_entry(7, 5);
+  assert_eq!(entry.lpOverlapped, overlapped);
+  assert_eq!(entry.lpCompletionKey, 7);
+  assert_eq!(entry.dwNumberOfBytesTransferred, 5);
+  dispatch.pending();
+
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 5,
+  };
+  unsafe { port.complete_io(7, NonNull::new(overlapped).unwrap(), result) };
+  let dequeued = port.run_one(Some(Duration::from_secs(0))).unwrap();
+  assert_eq!(dequeued.lpCompletionKey, 7);
+  assert_eq!(counters.results(), [result]);
+  assert_eq!(counters.freed(), 1);
+  port.assert_no_leaks();
+}
+
+#[test]
+fn raw_dispatches_fail_together() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let raw = vec![
+    port.dispatch(Probe::new(&counters)).into_raw(),
+    port.dispatch(Probe::new(&counters)).into_raw(),
+  ];
+  let handlers = RawDispatch::fail_many(raw);
+  assert_eq!(handlers.len(), 2);
+  assert_eq!((counters.completed(), counters.freed()), (0, 2));
+  port.assert_no_leaks();
+}
+
+#[test]
+fn complete_on_finishes_on_the_pool() {
+  let counters = Counters::new();
+  let port = CompletionPort::new();
+  let mut dispatch = port.dispatch(Probe::new(&counters));
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 3,
+  };
+  unsafe { write_overlapped_result(overlapped.as_ptr(), result) };
+
+  let mut spawned = None;
+  unsafe {
+    EventState::complete_on(overlapped, |job| {
+      spawned = Some(thread::spawn(job))
+    })
+  };
+  // The handler left its EventState right away, but runs on the pool.
+  assert!(port.registry().is_empty());
+  spawned.unwrap().join().unwrap();
+  assert_eq!(counters.results(), [result]);
+  assert_eq!(counters.freed(), 1);
+  port.assert_no_leaks();
+}
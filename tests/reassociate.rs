@@ -0,0 +1,80 @@
+#![cfg(not(windows))]
+
+mod common;
+
+use std::collections::HashMap;
+use std::ptr::NonNull;
+use std::time::Duration;
+
+use miox::completion_port::{CompletionPort, StatsSnapshot};
+use miox::raw::RawHandle;
+use miox::winapi::STATUS_SUCCESS;
+use miox::IoResult;
+
+use common::{Counters, Probe};
+
+#[test]
+fn completions_after_reassociation_route_through_the_new_port() {
+  let counters = Counters::new();
+  let old_port = CompletionPort::new();
+  let new_port = CompletionPort::new();
+  let handle = RawHandle::from_raw(0x1234);
+  let reopened = RawHandle::from_raw(0x5678);
+
+  let mut probe = Probe::new(&counters);
+  old_port.associate(handle, 7, &mut *probe);
+  let mut outstanding = old_port.dispatch_on(handle, probe);
+  let overlapped = NonNull::new(outstanding.overlapped()).unwrap();
+  outstanding.pending();
+  // This one's packet has been posted to the old port already.
+  let mut queued = old_port.dispatch(Probe::new(&counters));
+  let queued_overlapped = NonNull::new(queued.overlapped()).unwrap();
+  queued.pending();
+  let queued_result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 1,
+  };
+  unsafe { old_port.complete_io(3, queued_overlapped, queued_result) };
+
+  let map: HashMap<_, _> = vec![(handle, reopened)].into_iter().collect();
+  unsafe { old_port.reassociate_all(&new_port, &map) };
+
+  assert_eq!(old_port.key_of(handle), None);
+  assert_eq!(new_port.key_of(reopened), Some(7));
+  assert!(old_port.registry().is_empty());
+  let orphans = new_port.report_orphans();
+  assert_eq!(orphans.len(), 2);
+  assert!(orphans.iter().any(|orphan| orphan.handle == Some(reopened)));
+  // Nothing is left to arrive on the old port.
+  assert!(old_port.run_one(Some(Duration::from_secs(0))).is_none());
+  assert_eq!(
+    old_port.stats().snapshot(),
+    StatsSnapshot {
+      submitted: 2,
+      completed: 0,
+      failed: 0,
+      cancelled: 2,
+    }
+  );
+
+  // The packet that was queued moved along.
+  let entry = new_port.run_one(Some(Duration::from_secs(0))).unwrap();
+  assert_eq!(entry.lpCompletionKey, 3);
+  assert_eq!(entry.lpOverlapped, queued_overlapped.as_ptr());
+
+  // The OS delivers the outstanding one's completion to the new port.
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 2,
+  };
+  let key = new_port.key_of(reopened).unwrap();
+  unsafe { new_port.complete_io(key, overlapped, result) };
+  let entry = new_port.run_one(Some(Duration::from_secs(0))).unwrap();
+  assert_eq!(entry.lpCompletionKey, 7);
+  assert_eq!(entry.lpOverlapped, overlapped.as_ptr());
+
+  assert_eq!(counters.results(), vec![queued_result, result]);
+  new_port.assert_no_leaks();
+  let stats = new_port.stats().snapshot();
+  assert_eq!((stats.submitted, stats.completed), (2, 2));
+}
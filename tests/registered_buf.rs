@@ -0,0 +1,63 @@
+use std::ptr::NonNull;
+use std::sync::mpsc::channel;
+
+use miox::iocp::dispatch_read;
+use miox::registered_buf::{RegisteredBufHandler, RegisteredBuffers};
+use miox::winapi::STATUS_SUCCESS;
+use miox::EventState;
+
+#[test]
+fn completion_records_the_length_against_the_right_slot() {
+  let (tx, rx) = channel();
+  let buffers = RegisteredBuffers::new(3, 16);
+  let handler =
+    RegisteredBufHandler::new(buffers.clone(), 1, move |result, index| {
+      tx.send((result, index)).unwrap()
+    });
+
+  let mut issued = None;
+  let started = dispatch_read(handler, |buf, len, overlapped| {
+    assert_eq!(len, 16);
+    // Stands in for a ReadFile() into the slot.
+    unsafe { buf.copy_from_nonoverlapping(b"hello".as_ptr(), 5) };
+    issued = Some((buf as *const u8, NonNull::new(overlapped).unwrap()));
+    true
+  });
+  assert!(started.is_ok());
+  let (buf, overlapped) = issued.unwrap();
+  assert_eq!(buffers.valid_len(1), 0);
+
+  unsafe { EventState::complete_sync(overlapped, 5, STATUS_SUCCESS) };
+  let (result, index) = rx.try_recv().unwrap();
+  assert_eq!((result.bytes_transferred, index), (5, 1));
+  assert_eq!(buffers.valid_len(1), 5);
+  assert_eq!(buffers.valid_len(0), 0);
+  assert_eq!(buffers.valid_len(2), 0);
+  // The data is where the OS put it; nothing was copied.
+  let valid = unsafe { buffers.valid(1) };
+  assert_eq!(valid, b"hello");
+  assert_eq!(valid.as_ptr(), buf);
+}
+
+#[test]
+#[should_panic(expected = "no registered buffer 3")]
+fn slot_index_must_be_in_range() {
+  RegisteredBufHandler::new(RegisteredBuffers::new(3, 16), 3, |_, _| {});
+}
+
+#[test]
+#[should_panic(expected = "registered buffer 1 is in use")]
+fn slot_takes_one_handler_at_a_time() {
+  let buffers = RegisteredBuffers::new(3, 16);
+  let _reading = RegisteredBufHandler::new(buffers.clone(), 1, |_, _| {});
+  RegisteredBufHandler::new(buffers, 1, |_, _| {});
+}
+
+#[test]
+fn slot_is_released_with_its_handler() {
+  let buffers = RegisteredBuffers::new(1, 16);
+  for _ in 0..2 {
+    let handler = RegisteredBufHandler::new(buffers.clone(), 0, |_, _| {});
+    drop(handler);
+  }
+}
@@ -0,0 +1,79 @@
+use std::ptr::NonNull;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+
+use miox::container_of::ContainerOf;
+use miox::result_cache::ResultCache;
+use miox::winapi::{OVERLAPPED, STATUS_SUCCESS};
+use miox::{Dispatchable, EventHandler, EventState};
+
+// A request that may be sent more than once, but must be processed once.
+struct Request {
+  state: EventState,
+  request_id: u64,
+  cache: Arc<ResultCache>,
+  processed: Sender<u64>,
+}
+
+impl EventHandler for Request {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {
+    if !self.cache.record(self.request_id) {
+      return;
+    }
+    self.processed.send(self.request_id).unwrap();
+  }
+}
+
+fn attempt(
+  request_id: u64,
+  cache: &Arc<ResultCache>,
+  processed: &Sender<u64>,
+) -> (NonNull<OVERLAPPED>, u64) {
+  let handler = Box::new(Request {
+    state: EventState::new(),
+    request_id,
+    cache: cache.clone(),
+    processed: processed.clone(),
+  });
+  let mut dispatch = handler.dispatch();
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  let sequence =
+    unsafe { EventState::container_of(&*overlapped.as_ptr()) }.sequence();
+  dispatch.pending();
+  (overlapped, sequence)
+}
+
+#[test]
+fn duplicate_completion_of_a_retried_operation_is_ignored() {
+  let (tx, rx) = channel();
+  let cache = Arc::new(ResultCache::with_capacity(16));
+  let (original, original_sequence) = attempt(7, &cache, &tx);
+  // The original seemed to fail, so the request is sent again. The retry is
+  // a dispatch of its own, with a sequence id of its own.
+  let (retry, retry_sequence) = attempt(7, &cache, &tx);
+  assert_ne!(original_sequence, retry_sequence);
+  let (other, _) = attempt(8, &cache, &tx);
+
+  // But both complete after all.
+  for overlapped in [original, retry, other] {
+    unsafe { EventState::complete_sync(overlapped, 0, STATUS_SUCCESS) };
+  }
+  assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![7, 8]);
+  assert!(cache.contains(7) && cache.contains(8));
+}
+
+#[test]
+fn only_the_most_recent_keys_are_kept() {
+  let cache = ResultCache::with_capacity(2);
+  assert!(cache.record(1));
+  assert!(cache.record(2));
+  assert!(cache.record(3));
+  assert_eq!(cache.len(), 2);
+  assert!(!cache.contains(1));
+  assert!(!cache.record(3));
+  assert!(cache.record(1));
+}
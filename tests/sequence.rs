@@ -0,0 +1,105 @@
+use std::ptr::NonNull;
+use std::sync::mpsc::{channel, Sender};
+
+use miox::completion_port::CompletionPort;
+use miox::container_of::ContainerOf;
+use miox::pool::{HandlerPool, PoolableEventHandler};
+use miox::winapi::STATUS_SUCCESS;
+use miox::{EventHandler, EventState, IoResult};
+
+const SUCCESS: IoResult = IoResult {
+  status: STATUS_SUCCESS,
+  bytes_transferred: 0,
+};
+
+struct Logged {
+  state: EventState,
+  done: Sender<u64>,
+}
+
+impl EventHandler for Logged {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {
+    self.done.send(self.state.sequence()).unwrap();
+  }
+}
+
+#[test]
+fn each_dispatch_gets_its_own_sequence_id() {
+  let (tx, rx) = channel();
+  let port = CompletionPort::new();
+  let mut ops = Vec::new();
+  for _ in 0..2 {
+    let mut logged = Box::new(Logged {
+      state: EventState::new(),
+      done: tx.clone(),
+    });
+    assert_eq!(logged.state().sequence(), 0);
+    let mut dispatch = port.dispatch(logged);
+    let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+    let sequence =
+      unsafe { EventState::container_of(&*overlapped.as_ptr()) }.sequence();
+    dispatch.pending();
+    ops.push((overlapped, sequence));
+  }
+  let (first, second) = (ops[0].1, ops[1].1);
+  assert!(first != 0 && first < second);
+
+  // Completed out of order, each reports its own.
+  for &(overlapped, _) in ops.iter().rev() {
+    unsafe { port.complete_io(0, overlapped, SUCCESS) };
+    port.run_one(None).unwrap();
+  }
+  assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![second, first]);
+}
+
+struct Datagram {
+  state: EventState,
+  done: Sender<u64>,
+}
+
+impl PoolableEventHandler for Datagram {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(&mut self) {
+    self.done.send(self.state.sequence()).unwrap();
+  }
+
+  fn reset(&mut self) {}
+}
+
+#[test]
+fn rearming_from_a_pool_takes_a_new_sequence_id() {
+  let (tx, rx) = channel();
+  let pool = HandlerPool::with_capacity(1);
+  let handler = pool.get_or_else(|| Datagram {
+    state: EventState::new(),
+    done: tx,
+  });
+  let mut dispatch = pool.dispatch(handler);
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+
+  let mut rearmed = None;
+  unsafe {
+    pool
+      .complete_and_rearm(overlapped, |handler, overlapped| {
+        rearmed = Some((handler.state.sequence(), overlapped));
+        Ok::<_, ()>(())
+      })
+      .unwrap()
+  };
+  let completed = rx.try_recv().unwrap();
+  let (sequence, overlapped) = rearmed.unwrap();
+  assert!(sequence > completed);
+  assert_eq!(pool.outstanding(), 1);
+
+  unsafe { EventState::complete(NonNull::new(overlapped).unwrap()) };
+  assert_eq!(rx.try_recv().unwrap(), sequence);
+  assert_eq!((pool.outstanding(), pool.len()), (0, 1));
+}
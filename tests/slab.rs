@@ -0,0 +1,46 @@
+use std::ptr::null_mut;
+
+use miox::slab::HandlerSlab;
+use miox::winapi::{OVERLAPPED, STATUS_SUCCESS};
+
+// Stands in for the kernel recording the outcome of an operation.
+unsafe fn finish(overlapped: *mut OVERLAPPED, bytes: usize) {
+  (*overlapped).Internal = STATUS_SUCCESS as usize;
+  (*overlapped).InternalHigh = bytes;
+}
+
+#[test]
+fn dispatch_complete_by_index_and_reclaim() {
+  let mut slab = HandlerSlab::with_capacity(2);
+  let (first, a) = slab.dispatch("first").unwrap();
+  let (second, b) = slab.dispatch("second").unwrap();
+  assert_ne!(first, second);
+  assert_eq!(slab.len(), 2);
+  // Full: the handler comes back.
+  assert_eq!(slab.dispatch("third").err(), Some("third"));
+
+  assert_eq!(slab.index_of(a), Some(first));
+  assert_eq!(slab.index_of(b), Some(second));
+  assert_eq!(slab.index_of(null_mut()), None);
+  let mut foreign = OVERLAPPED::default();
+  assert_eq!(slab.index_of(&mut foreign), None);
+
+  unsafe { finish(b, 5) };
+  let (handler, result) = slab.complete_by_index(second).unwrap();
+  assert_eq!(handler, "second");
+  assert_eq!(result.status, STATUS_SUCCESS);
+  assert_eq!(result.bytes_transferred, 5);
+  // The slot is free again, and can't be completed twice.
+  assert!(slab.complete_by_index(second).is_none());
+  assert_eq!(slab.len(), 1);
+
+  // The reclaimed slot is handed out again, with a fresh OVERLAPPED.
+  let (third, c) = slab.dispatch("third").unwrap();
+  assert_eq!((third, c), (second, b));
+  assert_eq!(unsafe { (*c).InternalHigh }, 0);
+
+  unsafe { finish(a, 1) };
+  assert_eq!(slab.complete(a).unwrap().0, "first");
+  assert_eq!(slab.complete_by_index(third).unwrap().0, "third");
+  assert!(slab.is_empty());
+}
@@ -0,0 +1,62 @@
+#![cfg(not(windows))]
+
+use std::io::ErrorKind;
+use std::ptr::NonNull;
+use std::sync::mpsc::channel;
+
+use miox::completion_port::CompletionPort;
+use miox::raw::RawSocket;
+use miox::socket_connect::ConnectState;
+use miox::winapi::{
+  ERROR_IO_PENDING, OVERLAPPED, STATUS_CONNECTION_REFUSED, STATUS_SUCCESS,
+};
+use miox::IoResult;
+
+// WSAEINVAL: the socket wasn't bound first.
+const WSAEINVAL: u32 = 10022;
+
+// Starts a connect that stays pending, and completes it with `status`.
+fn connect(status: i32) -> std::io::Result<RawSocket> {
+  let (tx, rx) = channel();
+  let socket = RawSocket::from_raw(0x5678);
+  let connect =
+    ConnectState::new(socket, move |connected| tx.send(connected).unwrap());
+
+  let mut issued: Option<NonNull<OVERLAPPED>> = None;
+  let started = connect.connect(|s, overlapped| {
+    assert_eq!(s, socket);
+    issued = NonNull::new(overlapped);
+    Err(ERROR_IO_PENDING)
+  });
+  assert!(started.is_ok());
+  assert!(rx.try_recv().is_err());
+
+  let port = CompletionPort::new();
+  let result = IoResult {
+    status,
+    bytes_transferred: 0,
+  };
+  unsafe { port.complete_io(0, issued.unwrap(), result) };
+  port.run_one(None).unwrap();
+  rx.try_recv().unwrap()
+}
+
+#[test]
+fn successful_connect_yields_the_socket() {
+  let socket = connect(STATUS_SUCCESS).unwrap();
+  assert_eq!(socket, RawSocket::from_raw(0x5678));
+}
+
+#[test]
+fn refused_connect_yields_connection_refused() {
+  let error = connect(STATUS_CONNECTION_REFUSED).unwrap_err();
+  assert_eq!(error.kind(), ErrorKind::ConnectionRefused);
+}
+
+#[test]
+fn failed_connect_hands_the_handler_back() {
+  let socket = RawSocket::from_raw(0x5678);
+  let connect = ConnectState::new(socket, |_| panic!("not completed"));
+  let handler = connect.connect(|_, _| Err(WSAEINVAL)).err().unwrap();
+  assert_eq!(handler.socket(), socket);
+}
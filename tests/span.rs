@@ -0,0 +1,100 @@
+#![cfg(feature = "trace-hooks")]
+
+mod common;
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr::NonNull;
+use std::sync::{Arc, Mutex};
+
+use miox::container_of::ContainerOf;
+use miox::trace_context::Span;
+use miox::EventState;
+
+use common::{Counters, Probe};
+
+// Logs what happens to it.
+struct Recorded(Arc<Mutex<Vec<&'static str>>>);
+
+impl Span for Recorded {
+  fn enter(&self) {
+    self.0.lock().unwrap().push("enter");
+  }
+
+  fn exit(&self) {
+    self.0.lock().unwrap().push("exit");
+  }
+
+  fn abandon(&self) {
+    self.0.lock().unwrap().push("abandon");
+  }
+}
+
+fn recorded_span() -> (Box<dyn Span>, Arc<Mutex<Vec<&'static str>>>) {
+  let log = Arc::default();
+  (Box::new(Recorded(Arc::clone(&log))), log)
+}
+
+fn log(log: &Mutex<Vec<&'static str>>) -> Vec<&'static str> {
+  log.lock().unwrap().clone()
+}
+
+#[test]
+fn completed_operation_runs_inside_its_span() {
+  let counters = Counters::new();
+  let (span, events) = recorded_span();
+  let dispatch = EventState::dispatch_with_span(Probe::new(&counters), span);
+  let overlapped = NonNull::new(dispatch.into_overlapped()).unwrap();
+  unsafe { EventState::complete(overlapped) };
+  assert_eq!(counters.completed(), 1);
+  assert_eq!(log(&events), ["enter", "exit"]);
+}
+
+#[test]
+fn force_dropped_operation_abandons_its_span() {
+  let counters = Counters::new();
+  let (span, events) = recorded_span();
+  let dispatch = EventState::dispatch_with_span(Probe::new(&counters), span);
+  let overlapped = dispatch.into_overlapped();
+
+  // E.g. at shutdown, with the handle closed: the handler is taken out and
+  // dropped without ever being completed.
+  let state = unsafe { EventState::container_of_mut(&mut *overlapped) };
+  let handler = state.detach_event_handler().unwrap();
+  assert!(log(&events).is_empty());
+  drop(handler);
+  assert_eq!(log(&events), ["abandon"]);
+  assert_eq!(counters.completed(), 0);
+}
+
+#[test]
+fn failed_dispatch_abandons_its_span() {
+  let counters = Counters::new();
+  let (span, events) = recorded_span();
+  let dispatch = EventState::dispatch_with_span(Probe::new(&counters), span);
+  drop(dispatch.failed());
+  assert_eq!(log(&events), ["abandon"]);
+
+  // Also when the dispatch is dropped by a panic before it's settled.
+  let (span, events) = recorded_span();
+  let panicked = catch_unwind(AssertUnwindSafe(|| {
+    let _dispatch = EventState::dispatch_with_span(Probe::new(&counters), span);
+    panic!("before the OS call");
+  }));
+  assert!(panicked.is_err());
+  assert_eq!(log(&events), ["abandon"]);
+}
+
+#[test]
+fn batch_runs_inside_the_spans_of_all_its_operations() {
+  let counters = Counters::new();
+  let (first, first_events) = recorded_span();
+  let (second, second_events) = recorded_span();
+  let overlappeds = [first, second].map(|span| {
+    let dispatch = EventState::dispatch_with_span(Probe::new(&counters), span);
+    NonNull::new(dispatch.into_overlapped()).unwrap()
+  });
+  unsafe { EventState::complete_batch::<Probe>(&overlappeds) };
+  assert_eq!(counters.completed(), 2);
+  assert_eq!(log(&first_events), ["enter", "exit"]);
+  assert_eq!(log(&second_events), ["enter", "exit"]);
+}
@@ -0,0 +1,40 @@
+mod common;
+
+use std::ptr::NonNull;
+use std::sync::{Arc, Mutex};
+
+use miox::submission_queue::SubmissionQueue;
+use miox::winapi::OVERLAPPED;
+use miox::EventState;
+
+use common::{Counters, Probe};
+
+#[test]
+fn flush_issues_in_order_and_hands_back_failures() {
+  let counters = Counters::new();
+  let issued: Arc<Mutex<Vec<usize>>> = Default::default();
+  let mut queue = SubmissionQueue::new();
+  for ok in [true, false, true].iter().copied() {
+    let issued = issued.clone();
+    queue.queue(Probe::new(&counters), move |overlapped: *mut OVERLAPPED| {
+      issued.lock().unwrap().push(overlapped as usize);
+      ok
+    });
+  }
+  assert_eq!(queue.len(), 3);
+  // Nothing is issued until the flush.
+  assert!(issued.lock().unwrap().is_empty());
+
+  let failed = queue.flush();
+  assert!(queue.is_empty());
+  assert_eq!(failed.len(), 1);
+  let issued = issued.lock().unwrap().clone();
+  assert_eq!(issued.len(), 3);
+
+  // The ones that started complete as usual.
+  for &overlapped in &[issued[0], issued[2]] {
+    let overlapped = NonNull::new(overlapped as *mut OVERLAPPED).unwrap();
+    unsafe { EventState::complete(overlapped) };
+  }
+  assert_eq!(counters.completed(), 2);
+}
@@ -0,0 +1,129 @@
+use std::ptr::NonNull;
+use std::sync::mpsc::{channel, Sender};
+use std::time::{Duration, Instant};
+
+use miox::completion_kind::{CancelReason, CompletionKind};
+use miox::completion_port::CompletionPort;
+use miox::timer_queue::TimerQueue;
+use miox::winapi::{OVERLAPPED, STATUS_CANCELLED, STATUS_SUCCESS};
+use miox::{EventHandler, EventState, IoResult};
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+  Completed,
+  TimedOut(bool),
+}
+
+struct Read {
+  state: EventState,
+  done: Sender<Outcome>,
+}
+
+impl Read {
+  fn new(done: &Sender<Outcome>) -> Box<Self> {
+    Box::new(Self {
+      state: EventState::new(),
+      done: done.clone(),
+    })
+  }
+}
+
+impl EventHandler for Read {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {
+    self.done.send(Outcome::Completed).unwrap();
+  }
+
+  fn timed_out(self: Box<Self>) {
+    let reported = matches!(
+      self.state.read_completion(),
+      CompletionKind::Cancelled(CancelReason::TimedOut)
+    );
+    self.done.send(Outcome::TimedOut(reported)).unwrap();
+  }
+}
+
+fn dispatch_with_timeout(
+  port: &CompletionPort,
+  handler: Box<Read>,
+  deadline: Instant,
+  timer: &TimerQueue,
+) -> NonNull<OVERLAPPED> {
+  let mut dispatch = port.dispatch(handler);
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  unsafe { EventState::schedule_timeout(overlapped, deadline, timer) };
+  dispatch.pending();
+  overlapped
+}
+
+#[test]
+fn timeout_cancels_and_the_completion_frees_the_handler() {
+  let (tx, rx) = channel();
+  let port = CompletionPort::new();
+  let timer = TimerQueue::new();
+  let now = Instant::now();
+  let overlapped = dispatch_with_timeout(&port, Read::new(&tx), now, &timer);
+
+  let mut cancelled = Vec::new();
+  let fired =
+    unsafe { timer.fire_expired(now, |overlapped| cancelled.push(overlapped)) };
+  assert_eq!(fired, 1);
+  assert_eq!(cancelled, vec![overlapped.as_ptr()]);
+  // The handler stays embedded until the cancellation comes back.
+  assert!(rx.try_recv().is_err());
+  assert_eq!(port.registry().len(), 1);
+  assert!(timer.is_empty());
+
+  let result = IoResult {
+    status: STATUS_CANCELLED,
+    bytes_transferred: 0,
+  };
+  unsafe { port.complete_io(0, overlapped, result) };
+  port.run_one(None).unwrap();
+  assert_eq!(rx.try_recv().unwrap(), Outcome::TimedOut(true));
+  port.assert_no_leaks();
+}
+
+#[test]
+fn completing_first_drops_the_timer() {
+  let (tx, rx) = channel();
+  let port = CompletionPort::new();
+  let timer = TimerQueue::new();
+  let deadline = Instant::now() + Duration::from_secs(60);
+  let overlapped =
+    dispatch_with_timeout(&port, Read::new(&tx), deadline, &timer);
+  assert_eq!(timer.len(), 1);
+
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 0,
+  };
+  unsafe { port.complete_io(0, overlapped, result) };
+  port.run_one(None).unwrap();
+  assert_eq!(rx.try_recv().unwrap(), Outcome::Completed);
+  assert!(timer.is_empty());
+  let fired = unsafe { timer.fire_expired(deadline, |_| unreachable!()) };
+  assert_eq!(fired, 0);
+}
+
+#[test]
+fn a_late_completion_is_not_a_timeout() {
+  let (tx, rx) = channel();
+  let port = CompletionPort::new();
+  let timer = TimerQueue::new();
+  let now = Instant::now();
+  let overlapped = dispatch_with_timeout(&port, Read::new(&tx), now, &timer);
+  assert_eq!(unsafe { timer.fire_expired(now, |_| {}) }, 1);
+
+  // The operation finished before the cancellation could take effect.
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 0,
+  };
+  unsafe { port.complete_io(0, overlapped, result) };
+  port.run_one(None).unwrap();
+  assert_eq!(rx.try_recv().unwrap(), Outcome::Completed);
+}
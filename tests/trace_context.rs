@@ -0,0 +1,56 @@
+#![cfg(feature = "trace-hooks")]
+
+use std::ptr::NonNull;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+use miox::trace_context::{self, TraceId};
+use miox::winapi::STATUS_SUCCESS;
+use miox::{Dispatchable, EventHandler, EventState};
+
+// Reports the trace that was current while it was completed.
+struct Traced {
+  state: EventState,
+  seen: Sender<Option<TraceId>>,
+}
+
+impl EventHandler for Traced {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {
+    self.seen.send(trace_context::current()).unwrap();
+  }
+}
+
+#[test]
+fn completion_runs_in_the_dispatching_trace() {
+  let (tx, rx) = channel();
+  let trace_id = TraceId([7; 16]);
+  let overlapped = {
+    let _entered = trace_context::enter(Some(trace_id));
+    let handler = Box::new(Traced {
+      state: EventState::new(),
+      seen: tx,
+    });
+    let mut dispatch = handler.dispatch();
+    let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+    dispatch.pending();
+    overlapped.as_ptr() as usize
+  };
+  assert_eq!(trace_context::current(), None);
+
+  // The completion may well be picked up by a thread that's working on
+  // something else entirely.
+  thread::spawn(move || {
+    let other = TraceId([9; 16]);
+    let _entered = trace_context::enter(Some(other));
+    let overlapped = NonNull::new(overlapped as *mut _).unwrap();
+    unsafe { EventState::complete_sync(overlapped, 0, STATUS_SUCCESS) };
+    assert_eq!(trace_context::current(), Some(other));
+  })
+  .join()
+  .unwrap();
+  assert_eq!(rx.try_recv().unwrap(), Some(trace_id));
+}
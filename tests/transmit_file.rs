@@ -0,0 +1,72 @@
+#![cfg(not(windows))]
+
+use std::ptr::NonNull;
+use std::sync::mpsc::channel;
+
+use miox::completion_port::CompletionPort;
+use miox::raw::{RawHandle, RawSocket};
+use miox::transmit_file::TransmitFileState;
+use miox::winapi::STATUS_SUCCESS;
+use miox::IoResult;
+
+#[test]
+fn transmit_file_hands_both_handles_to_the_completion() {
+  let (tx, rx) = channel();
+  let file = RawHandle::from_raw(0x1234);
+  let socket = RawSocket::from_raw(0x5678);
+  let mut transmit = TransmitFileState::new(
+    file,
+    socket,
+    b"head".to_vec(),
+    b"tail".to_vec(),
+    move |transmit| {
+      tx.send((
+        transmit.file(),
+        transmit.socket(),
+        transmit.head().to_vec(),
+        transmit.tail().to_vec(),
+        transmit.result(),
+      ))
+      .unwrap();
+    },
+  );
+  let buffers = transmit.transmit_buffers();
+  assert_eq!(buffers.HeadLength, 4);
+  assert_eq!(buffers.TailLength, 4);
+
+  let port = CompletionPort::new();
+  let mut dispatch = port.dispatch(transmit);
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  // The buffers handed to TransmitFile() don't move when the handler does.
+  let moved = dispatch.as_ref();
+  assert_eq!(moved.head().as_ptr(), buffers.Head as *const u8);
+  assert_eq!(moved.tail().as_ptr(), buffers.Tail as *const u8);
+  dispatch.pending();
+
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 4096 + 8,
+  };
+  unsafe { port.complete_io(0, overlapped, result) };
+  port.run_one(None).unwrap();
+  let (f, s, head, tail, r) = rx.try_recv().unwrap();
+  assert_eq!((f, s), (file, socket));
+  assert_eq!((&head[..], &tail[..]), (&b"head"[..], &b"tail"[..]));
+  assert_eq!(r, result);
+  port.assert_no_leaks();
+}
+
+#[test]
+fn transmit_file_passes_empty_buffers_as_null() {
+  let mut transmit = TransmitFileState::new(
+    RawHandle::from_raw(1),
+    RawSocket::from_raw(2),
+    Vec::new(),
+    b"tail".to_vec(),
+    |_| {},
+  );
+  let buffers = transmit.transmit_buffers();
+  assert!(buffers.Head.is_null());
+  assert_eq!(buffers.HeadLength, 0);
+  assert!(!buffers.Tail.is_null());
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use std::any::TypeId;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+use miox::{Dispatchable, EventHandler, EventState};
+
+use common::{Counters, Probe};
+
+// Re-arms with a fresh Probe, but forgets to settle its Dispatch.
+struct ForgetfulRearm {
+  state: EventState,
+  counters: Arc<Counters>,
+}
+
+impl EventHandler for ForgetfulRearm {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(self: Box<Self>) {
+    let _next = Probe::new(&self.counters).dispatch();
+  }
+}
+
+#[test]
+fn unsettled_rearm_names_the_completing_handler() {
+  let counters = Counters::new();
+  let mut dispatch = Box::new(ForgetfulRearm {
+    state: EventState::new(),
+    counters: counters.clone(),
+  })
+  .dispatch();
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+
+  let panicked = catch_unwind(AssertUnwindSafe(|| unsafe {
+    EventState::complete(overlapped)
+  }));
+  let payload = panicked.unwrap_err();
+  let message = payload.downcast_ref::<String>().unwrap();
+  assert!(message.contains("ForgetfulRearm"), "{}", message);
+  let type_id = format!("{:?}", TypeId::of::<ForgetfulRearm>());
+  assert!(message.contains(&type_id), "{}", message);
+}
@@ -0,0 +1,96 @@
+use std::any::TypeId;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use miox::vtable::{UnboxedEventHandler, Vtable};
+use miox::EventState;
+
+#[derive(Default)]
+struct Counts {
+  completed: AtomicUsize,
+  dropped: AtomicUsize,
+}
+
+// Lives in caller-provided storage rather than a Box.
+struct Unboxed {
+  state: EventState,
+  counts: Arc<Counts>,
+}
+
+impl UnboxedEventHandler for Unboxed {
+  fn state(&mut self) -> &mut EventState {
+    &mut self.state
+  }
+
+  fn complete(&mut self) {
+    // Still whole: it's dropped only after this returns.
+    assert_eq!(self.counts.dropped.load(Ordering::SeqCst), 0);
+    self.counts.completed.fetch_add(1, Ordering::SeqCst);
+  }
+}
+
+impl Drop for Unboxed {
+  fn drop(&mut self) {
+    self.counts.dropped.fetch_add(1, Ordering::SeqCst);
+  }
+}
+
+fn tally(counts: &Counts) -> (usize, usize) {
+  (
+    counts.completed.load(Ordering::SeqCst),
+    counts.dropped.load(Ordering::SeqCst),
+  )
+}
+
+#[test]
+fn embedded_handlers_complete_and_are_dropped_in_place() {
+  let counts = Arc::new(Counts::default());
+  let mut slot = MaybeUninit::<Unboxed>::uninit();
+  let handler = slot.as_mut_ptr();
+  unsafe {
+    ptr::write(
+      handler,
+      Unboxed {
+        state: EventState::new(),
+        counts: counts.clone(),
+      },
+    )
+  };
+
+  let vtable = unsafe { Vtable::new(handler) };
+  assert_eq!(vtable.handler(), handler);
+  assert_eq!(vtable.type_id(), TypeId::of::<Unboxed>());
+  let overlapped = unsafe { EventState::embed_vtable(vtable) };
+  assert_eq!(tally(&counts), (0, 0));
+  unsafe { EventState::complete(overlapped) };
+  assert_eq!(tally(&counts), (1, 1));
+  // The handler is gone, but not the storage, and nothing else holds on to
+  // the counts.
+  assert_eq!(Arc::strong_count(&counts), 1);
+}
+
+#[test]
+fn unembedded_handlers_are_dropped_without_completing() {
+  let counts = Arc::new(Counts::default());
+  let mut slot = MaybeUninit::<Unboxed>::uninit();
+  let handler = slot.as_mut_ptr();
+  for round in 1..=2 {
+    // The same storage is used again once the previous handler is dropped.
+    unsafe {
+      ptr::write(
+        handler,
+        Unboxed {
+          state: EventState::new(),
+          counts: counts.clone(),
+        },
+      )
+    };
+    let overlapped = unsafe { EventState::embed_vtable(Vtable::new(handler)) };
+    // The OS call failed.
+    unsafe { EventState::unembed_vtable(overlapped) };
+    assert_eq!(tally(&counts), (0, round));
+  }
+  assert_eq!(Arc::strong_count(&counts), 1);
+}
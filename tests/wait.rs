@@ -0,0 +1,64 @@
+// On Windows, waiting takes a real handle and a real operation.
+#![cfg(not(windows))]
+
+mod common;
+
+use std::ptr::NonNull;
+use std::thread;
+use std::time::Duration;
+
+use miox::iocp::{read_overlapped_result, write_overlapped_result};
+use miox::raw::RawHandle;
+use miox::winapi::{STATUS_PENDING, STATUS_SUCCESS};
+use miox::{Dispatchable, EventState, IoResult};
+
+use common::{Counters, Probe};
+
+#[test]
+fn wait_blocks_until_another_thread_records_the_result() {
+  let counters = Counters::new();
+  let mut dispatch = Probe::new(&counters).dispatch();
+  let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+  dispatch.pending();
+  // Dispatching marked the operation as in progress.
+  let recorded = unsafe { read_overlapped_result(overlapped.as_ptr()) };
+  assert_eq!(recorded.status, STATUS_PENDING);
+
+  let result = IoResult {
+    status: STATUS_SUCCESS,
+    bytes_transferred: 42,
+  };
+  // Stands in for the kernel finishing the operation a little later.
+  let address = overlapped.as_ptr() as usize;
+  let os = thread::spawn(move || {
+    thread::sleep(Duration::from_millis(20));
+    unsafe { write_overlapped_result(address as *mut _, result) };
+  });
+
+  let handle = RawHandle::from_raw(0x1234);
+  let waited = unsafe { EventState::wait(overlapped, handle) };
+  assert_eq!(waited.unwrap(), result);
+  assert_eq!(counters.results(), vec![result]);
+  os.join().unwrap();
+}
+
+#[test]
+fn event_handle_is_handed_to_the_os_with_the_overlapped() {
+  let counters = Counters::new();
+  let mut probe = Probe::new(&counters);
+  probe.state.set_event_handle(RawHandle::from_raw(0x1234));
+  let mut dispatch = probe.dispatch();
+  let overlapped = dispatch.overlapped();
+  assert_eq!(unsafe { (*overlapped).hEvent } as usize, 0x1234);
+  let mut probe = dispatch.failed();
+
+  probe.state.clear_event_handle();
+  assert!(probe.state.hEvent.is_null());
+}
+
+#[test]
+#[should_panic]
+fn event_handle_must_be_valid() {
+  let mut state = EventState::new();
+  state.set_event_handle(RawHandle::from_raw(0));
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Wake, Waker};
+
+use miox::container_of::ContainerOf;
+use miox::iocp::Dispatch;
+use miox::winapi::OVERLAPPED;
+use miox::{Dispatchable, EventHandler, EventState};
+
+use common::{Counters, Probe};
+
+#[derive(Default)]
+struct Task {
+  woken: AtomicUsize,
+}
+
+impl Wake for Task {
+  fn wake(self: Arc<Self>) {
+    self.woken.fetch_add(1, Ordering::SeqCst);
+  }
+}
+
+impl Task {
+  fn woken(&self) -> usize {
+    self.woken.load(Ordering::SeqCst)
+  }
+}
+
+// Dispatches a Probe with two tasks awaiting it, and hands back the
+// OVERLAPPED it was dispatched with.
+fn dispatch_awaited(
+  counters: &Arc<Counters>,
+  tasks: &[Arc<Task>; 2],
+) -> NonNull<OVERLAPPED> {
+  let mut dispatch: Dispatch<Probe> = Probe::new(counters).dispatch();
+  let state = unsafe { EventState::container_of(&*dispatch.overlapped()) };
+  for task in tasks {
+    assert!(state.wakers().register(&Waker::from(task.clone())));
+  }
+  NonNull::new(dispatch.into_overlapped()).unwrap()
+}
+
+fn tasks() -> [Arc<Task>; 2] {
+  [Arc::default(), Arc::default()]
+}
+
+fn assert_woken_once(tasks: &[Arc<Task>; 2]) {
+  assert!(tasks.iter().all(|task| task.woken() == 1));
+}
+
+#[test]
+fn complete_wakes_every_task() {
+  let counters = Counters::new();
+  let tasks = tasks();
+  let overlapped = dispatch_awaited(&counters, &tasks);
+  unsafe { EventState::complete(overlapped) };
+  assert_eq!(counters.completed(), 1);
+  assert_woken_once(&tasks);
+}
+
+#[test]
+fn complete_to_wakes_every_task() {
+  let counters = Counters::new();
+  let tasks = tasks();
+  let overlapped = dispatch_awaited(&counters, &tasks);
+  let sink = |handler: Box<dyn EventHandler>| handler.complete();
+  unsafe { EventState::complete_to(overlapped, &sink) };
+  assert_eq!(counters.completed(), 1);
+  assert_woken_once(&tasks);
+}
+
+#[test]
+fn complete_with_callback_wakes_every_task() {
+  let counters = Counters::new();
+  let tasks = tasks();
+  let overlapped = dispatch_awaited(&counters, &tasks);
+  unsafe {
+    EventState::complete_with_callback(overlapped, |handler, _| drop(handler))
+  };
+  assert_eq!(counters.completed(), 0);
+  assert_woken_once(&tasks);
+}
+
+#[test]
+fn owned_completion_wakes_every_task() {
+  let counters = Counters::new();
+  let finished = tasks();
+  let overlapped = dispatch_awaited(&counters, &finished);
+  let completion = unsafe { EventState::take_completion(overlapped) }.unwrap();
+  assert!(finished.iter().all(|task| task.woken() == 0));
+  completion.finish();
+  assert_woken_once(&finished);
+
+  // Also when the completion is dropped rather than finished.
+  let dropped = tasks();
+  let overlapped = dispatch_awaited(&counters, &dropped);
+  drop(unsafe { EventState::take_completion(overlapped) });
+  assert_woken_once(&dropped);
+  assert_eq!(counters.completed(), 1);
+}
+
+#[test]
+fn complete_panic_safe_wakes_every_task_when_the_handler_panics() {
+  struct Panicky {
+    state: EventState,
+  }
+
+  impl EventHandler for Panicky {
+    fn state(&mut self) -> &mut EventState {
+      &mut self.state
+    }
+
+    fn complete(self: Box<Self>) {
+      panic!("bug in complete()");
+    }
+  }
+
+  let tasks = tasks();
+  let handler = Box::new(Panicky {
+    state: EventState::new(),
+  });
+  let mut dispatch = handler.dispatch();
+  let state = unsafe { EventState::container_of(&*dispatch.overlapped()) };
+  for task in &tasks {
+    state.wakers().register(&Waker::from(task.clone()));
+  }
+  let overlapped = NonNull::new(dispatch.into_overlapped()).unwrap();
+
+  let panicked = unsafe { EventState::complete_panic_safe(overlapped) };
+  assert!(panicked.is_err());
+  // Otherwise the tasks would wait for an operation that's long gone.
+  assert_woken_once(&tasks);
+}
@@ -0,0 +1,98 @@
+#![cfg(not(windows))]
+
+mod common;
+
+use std::ptr::NonNull;
+use std::time::Duration;
+
+use miox::completion_port::{CompletionPort, DrainTimeout};
+use miox::raw::RawHandle;
+use miox::winapi::{STATUS_CANCELLED, STATUS_SUCCESS};
+use miox::IoResult;
+
+use common::{Counters, Probe};
+
+const WORKERS: usize = 4;
+
+#[test]
+fn worker_threads_run_completions_until_shut_down() {
+  let counters = Counters::new();
+  let (port, supervisor) = CompletionPort::with_thread_count(WORKERS).build();
+  for bytes in 0..100 {
+    let mut dispatch = port.dispatch(Probe::new(&counters));
+    let overlapped = NonNull::new(dispatch.overlapped()).unwrap();
+    dispatch.pending();
+    let result = IoResult {
+      status: STATUS_SUCCESS,
+      bytes_transferred: bytes,
+    };
+    unsafe { port.complete_io(0, overlapped, result) };
+  }
+  // Packets queued before the shutdown are still processed.
+  port.shutdown();
+  supervisor.join().unwrap();
+  assert_eq!(counters.completed(), 100);
+  let mut bytes: Vec<_> = counters
+    .results()
+    .iter()
+    .map(|result| result.bytes_transferred)
+    .collect();
+  bytes.sort_unstable();
+  assert_eq!(bytes, (0..100).collect::<Vec<_>>());
+  // Nothing is left for a worker to pick up.
+  assert!(port
+    .get_queued_completion_status(Some(Duration::from_secs(0)))
+    .is_none());
+  port.assert_no_leaks();
+}
+
+#[test]
+fn drain_shutdown_cancels_outstanding_operations_and_stops_the_workers() {
+  let counters = Counters::new();
+  let (port, supervisor) = CompletionPort::with_thread_count(WORKERS).build();
+  let handle = RawHandle::from_raw(0x1234);
+  let mut outstanding = Vec::new();
+  for _ in 0..10 {
+    let mut probe = Probe::new(&counters);
+    port.associate(handle, 1, &mut *probe);
+    let mut dispatch = port.dispatch_on(handle, probe);
+    outstanding.push(dispatch.overlapped());
+    dispatch.pending();
+  }
+
+  let drained = port.drain_shutdown(Duration::from_secs(5), |cancelled| {
+    assert_eq!(cancelled, handle);
+    // Stands in for CancelIoEx(): every operation on the handle comes back.
+    for overlapped in outstanding.drain(..) {
+      let result = IoResult {
+        status: STATUS_CANCELLED,
+        bytes_transferred: 0,
+      };
+      unsafe { port.complete_io(1, NonNull::new(overlapped).unwrap(), result) };
+    }
+  });
+  assert_eq!(drained, Ok(()));
+  supervisor.join().unwrap();
+  assert_eq!(counters.completed(), 10);
+  port.assert_no_leaks();
+}
+
+#[test]
+fn drain_shutdown_gives_up_on_operations_that_never_come_back() {
+  let counters = Counters::new();
+  let (port, supervisor) = CompletionPort::with_thread_count(WORKERS).build();
+  let mut dispatch = port.dispatch(Probe::new(&counters));
+  dispatch.overlapped();
+  dispatch.pending();
+
+  let drained = port.drain_shutdown(Duration::from_millis(30), |_| {});
+  assert_eq!(drained, Err(DrainTimeout { outstanding: 1 }));
+  // The workers are stopped all the same.
+  supervisor.join().unwrap();
+  assert_eq!(counters.completed(), 0);
+
+  // The OS is done with it now, so its handler can be reclaimed.
+  let handlers = unsafe { port.drain_handlers() };
+  assert_eq!(handlers.len(), 1);
+  port.assert_no_leaks();
+}